@@ -0,0 +1,349 @@
+use clap::{Parser, Subcommand};
+use daggyr::client::{Client, ACTIVE_STATES};
+use daggyr::prelude::*;
+use daggyr::specs;
+use daggyr::Result;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Every state, in the order they're listed in
+const STATES: [State; 7] = [
+    State::Queued,
+    State::Running,
+    State::Paused,
+    State::Completed,
+    State::Errored,
+    State::Killed,
+    State::Skipped,
+];
+
+/// Used when neither `--server` nor `DAGGYR_SERVER` is given
+const DEFAULT_SERVER: &str = "http://127.0.0.1:2503";
+
+/// The exit code for a run's state, so scripts can tell how it ended.
+/// Errors talking to the server exit with 1, and usage errors with 2.
+fn exit_code(state: State) -> i32 {
+    match state {
+        State::Completed | State::Skipped => 0,
+        State::Errored => 10,
+        State::Killed => 11,
+        State::Paused => 12,
+        State::Queued | State::Running => 13,
+    }
+}
+
+fn parse_state(state: &str) -> std::result::Result<State, String> {
+    STATES
+        .into_iter()
+        .find(|s| format!("{s:?}").eq_ignore_ascii_case(state))
+        .ok_or_else(|| format!("Unknown state {state}"))
+}
+
+fn parse_pair(pair: &str) -> std::result::Result<(String, String), String> {
+    pair.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("Expected KEY=VALUE, found {pair}"))
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Submit a run from a JSON, YAML or TOML spec, printing its ID
+    Submit {
+        spec: PathBuf,
+
+        /// Sets a parameter, replacing its values from the spec. Repeat a
+        /// key to give it several values.
+        #[clap(short, long = "param", value_name = "KEY=VALUE", value_parser = parse_pair)]
+        params: Vec<(String, String)>,
+
+        /// Adds a tag to the run
+        #[clap(short, long = "tag", value_name = "KEY=VALUE", value_parser = parse_pair)]
+        tags: Vec<(String, String)>,
+
+        /// The pool to run in
+        #[clap(long)]
+        pool: Option<String>,
+
+        /// Wait for the run to finish, exiting with its final state
+        #[clap(short, long)]
+        wait: bool,
+    },
+
+    /// List runs
+    List {
+        /// Only list runs with this tag
+        #[clap(short, long = "tag", value_name = "KEY=VALUE", value_parser = parse_pair)]
+        tags: Vec<(String, String)>,
+
+        /// Only list runs in this state
+        #[clap(short, long = "state", value_parser = parse_state)]
+        states: Vec<State>,
+    },
+
+    /// Show the state of a run, exiting with it
+    Status {
+        run_id: RunID,
+
+        /// Keep the view updated until the run finishes
+        #[clap(short, long)]
+        watch: bool,
+    },
+
+    /// List the tasks of a run
+    Tasks {
+        run_id: RunID,
+
+        /// Only list tasks in this state
+        #[clap(short, long = "state", value_parser = parse_state)]
+        states: Vec<State>,
+    },
+
+    /// Print the output of a task's attempt. Its stderr is printed to
+    /// stderr.
+    Logs {
+        run_id: RunID,
+        task_id: TaskID,
+
+        /// The attempt to show, starting from 1. Defaults to the latest.
+        #[clap(short, long)]
+        attempt: Option<usize>,
+    },
+
+    /// Stop a run, or kill a single task
+    Kill {
+        run_id: RunID,
+        task_id: Option<TaskID>,
+    },
+
+    /// Retry the tasks of a run that didn't complete
+    Retry {
+        run_id: RunID,
+
+        /// Only retry tasks matching this glob pattern
+        #[clap(short, long = "task")]
+        tasks: Vec<String>,
+
+        /// Also retry the upstream dependencies of matching tasks
+        #[clap(long)]
+        upstream: bool,
+
+        /// Also retry the downstream dependents of matching tasks
+        #[clap(long)]
+        downstream: bool,
+
+        /// Wait for the run to finish, exiting with its final state
+        #[clap(short, long)]
+        wait: bool,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about)]
+struct Args {
+    /// The base URL of the daggyr server. Defaults to `DAGGYR_SERVER`, or
+    /// http://127.0.0.1:2503.
+    #[clap(short, long)]
+    server: Option<String>,
+
+    /// How often to check on runs when waiting, in seconds
+    #[clap(short, long, default_value = "2")]
+    interval: u64,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// Counts the tasks in each state
+fn state_counts(tasks: &[TaskSummary]) -> HashMap<State, usize> {
+    let mut counts = HashMap::new();
+    for task in tasks {
+        *counts.entry(task.state).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Prints a run's state, with how many of its tasks are in each state
+async fn print_status(client: &Client, run_id: RunID) -> Result<State> {
+    let state = client.state(run_id).await?;
+    let tasks = client.tasks(run_id).await?;
+    let counts = state_counts(&tasks);
+
+    println!(
+        "Run {run_id}: {:?} since {}",
+        state.state,
+        state.datetime.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    for s in STATES {
+        if let Some(count) = counts.get(&s) {
+            println!("  {:<10} {count}", format!("{s:?}"));
+        }
+    }
+    for s in [State::Running, State::Errored] {
+        let mut ids: Vec<&str> = tasks
+            .iter()
+            .filter(|task| task.state == s)
+            .map(|task| task.task_id.as_str())
+            .collect();
+        if !ids.is_empty() {
+            ids.sort_unstable();
+            println!("{s:?}: {}", ids.join(", "));
+        }
+    }
+    Ok(state.state)
+}
+
+/// Waits for a run to finish, returning its final state
+async fn wait_for(client: &Client, run_id: RunID, interval: Duration) -> Result<State> {
+    loop {
+        let state = client.state(run_id).await?.state;
+        if !ACTIVE_STATES.contains(&state) {
+            eprintln!("Run {run_id} {state:?}");
+            return Ok(state);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Runs a command, returning the code to exit with
+async fn run_command(server: &str, command: Command, interval: Duration) -> Result<i32> {
+    let client = &Client::new(server)?;
+    match command {
+        Command::Submit {
+            spec,
+            params,
+            tags,
+            pool,
+            wait,
+        } => {
+            let mut spec: RunSpec = specs::read_run(&spec)?;
+            let mut overrides = Parameters::new();
+            for (key, value) in params {
+                overrides.entry(key).or_default().push(value);
+            }
+            spec.parameters.extend(overrides);
+            for (key, value) in tags {
+                spec.tags.insert(key, value);
+            }
+            if pool.is_some() {
+                spec.pool = pool;
+            }
+
+            let run_id = client.submit(&spec).await?;
+            println!("{run_id}");
+            if wait {
+                return Ok(exit_code(wait_for(client, run_id, interval).await?));
+            }
+        }
+        Command::List { tags, states } => {
+            let mut run_tags = RunTags::new();
+            for (key, value) in tags {
+                run_tags.insert(key, value);
+            }
+            let mut runs = client.runs(&run_tags, &states).await?;
+            runs.sort_by_key(|run| run.run_id);
+            println!("{:<8} {:<10} {:<20} TAGS", "RUN", "STATE", "STARTED");
+            for run in runs {
+                let mut tags: Vec<String> =
+                    run.tags.iter().map(|(k, v)| format!("{k}={v}")).collect();
+                tags.sort();
+                println!(
+                    "{:<8} {:<10} {:<20} {}",
+                    run.run_id,
+                    format!("{:?}", run.state),
+                    run.start_time.format("%Y-%m-%d %H:%M:%S"),
+                    tags.join(",")
+                );
+            }
+        }
+        Command::Status { run_id, watch } => {
+            // Only redraw in place when a person is watching
+            let redraw = watch && std::io::stdout().is_terminal();
+            loop {
+                if redraw {
+                    print!("\x1b[2J\x1b[H");
+                }
+                let state = print_status(client, run_id).await?;
+                if !watch || !ACTIVE_STATES.contains(&state) {
+                    return Ok(exit_code(state));
+                }
+                tokio::time::sleep(interval).await;
+                if !redraw {
+                    println!();
+                }
+            }
+        }
+        Command::Tasks { run_id, states } => {
+            let mut tasks = client.tasks(run_id).await?;
+            tasks.retain(|task| states.is_empty() || states.contains(&task.state));
+            tasks.sort_by(|a, b| a.task_id.cmp(&b.task_id));
+            for task in tasks {
+                println!("{:<10} {}", format!("{:?}", task.state), task.task_id);
+            }
+        }
+        Command::Logs {
+            run_id,
+            task_id,
+            attempt,
+        } => {
+            let record = client.task(run_id, &task_id).await?;
+            let count = record.attempts.len();
+            let number = attempt.unwrap_or(count);
+            let attempt = number
+                .checked_sub(1)
+                .and_then(|i| record.attempts.get(i))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Task {task_id} has {count} attempts, not attempt {number}")
+                })?;
+            eprintln!(
+                "Attempt {number} of {count}, started {}, exit code {}",
+                attempt.start_time.format("%Y-%m-%d %H:%M:%S UTC"),
+                attempt.exit_code
+            );
+            print!("{}", attempt.output);
+            eprint!("{}", attempt.error);
+        }
+        Command::Kill { run_id, task_id } => match task_id {
+            Some(task_id) => {
+                client.kill_task(run_id, &task_id).await?;
+                eprintln!("Killed {task_id} in run {run_id}");
+            }
+            None => {
+                client.stop_run(run_id).await?;
+                eprintln!("Stopped run {run_id}");
+            }
+        },
+        Command::Retry {
+            run_id,
+            tasks,
+            upstream,
+            downstream,
+            wait,
+        } => {
+            client.retry(run_id, &tasks, upstream, downstream).await?;
+            eprintln!("Retrying run {run_id}");
+            if wait {
+                return Ok(exit_code(wait_for(client, run_id, interval).await?));
+            }
+        }
+    }
+    Ok(0)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let server = args
+        .server
+        .or_else(|| std::env::var("DAGGYR_SERVER").ok())
+        .unwrap_or_else(|| DEFAULT_SERVER.to_owned());
+    let interval = Duration::from_secs(args.interval.max(1));
+
+    match run_command(&server, args.command, interval).await {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    }
+}
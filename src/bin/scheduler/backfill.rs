@@ -0,0 +1,161 @@
+use crate::source::{slot_tag, RunSource};
+use daggyr::client::{Client, ACTIVE_STATES};
+use daggyr::prelude::*;
+use daggyr::schedule::{format_time, intervals, parse_step, parse_time};
+use daggyr::Result;
+use log::info;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::Args, Debug)]
+pub struct BackfillArgs {
+    /// A spec file to submit
+    #[clap(
+        long,
+        required_unless_present = "template",
+        conflicts_with = "template"
+    )]
+    spec: Option<PathBuf>,
+
+    /// The name of a template stored on the server to submit
+    #[clap(long)]
+    template: Option<String>,
+
+    /// The start of the range, as YYYY-MM-DD or RFC 3339
+    #[clap(long)]
+    start: String,
+
+    /// The end of the range, which isn't included
+    #[clap(long)]
+    end: String,
+
+    /// The length of each interval, as Nh, Nd or Nw
+    #[clap(long, default_value = "1d")]
+    step: String,
+
+    /// The most backfill runs to have unfinished at once
+    #[clap(long, default_value = "4")]
+    concurrency: usize,
+
+    /// The parameter the start of each interval is passed in
+    #[clap(long, default_value = "{{DATE}}")]
+    parameter: String,
+
+    /// How the start of each interval is formatted
+    #[clap(long, default_value = "%Y-%m-%d")]
+    format: String,
+
+    /// Identifies the backfill so it can be resumed. Defaults to one made
+    /// from the source and range.
+    #[clap(long)]
+    name: Option<String>,
+
+    /// When resuming, resubmit intervals whose runs errored or were killed
+    #[clap(long)]
+    retry_failed: bool,
+
+    /// How often to check on the backfill's runs, in seconds
+    #[clap(long, default_value = "10")]
+    poll_seconds: u64,
+}
+
+fn failed(state: State) -> bool {
+    matches!(state, State::Errored | State::Killed)
+}
+
+/// Returns the state of the latest run for each interval of the backfill
+async fn interval_states(client: &Client, tags: &RunTags) -> Result<HashMap<String, State>> {
+    let mut latest: HashMap<String, (RunID, State)> = HashMap::new();
+    for run in client.runs(tags, &[]).await? {
+        let Some(slot) = run.tags.get(SLOT_TAG) else {
+            continue;
+        };
+        let entry = latest
+            .entry(slot.clone())
+            .or_insert((run.run_id, run.state));
+        if run.run_id >= entry.0 {
+            *entry = (run.run_id, run.state);
+        }
+    }
+    Ok(latest
+        .into_iter()
+        .map(|(slot, (_, state))| (slot, state))
+        .collect())
+}
+
+/// Submits a run for each interval of a range, keeping at most
+/// `concurrency` of them unfinished at a time. Intervals that already have
+/// a run from an earlier attempt at the same backfill are left alone.
+pub async fn backfill(args: BackfillArgs, client: Client) -> Result<()> {
+    let source = match (args.spec, args.template) {
+        (Some(path), _) => RunSource::Spec(path),
+        (None, Some(template)) => RunSource::Template(template),
+        (None, None) => return Err(anyhow::anyhow!("Either a spec or template is required")),
+    };
+    let start = parse_time(&args.start)?;
+    let end = parse_time(&args.end)?;
+    let step = parse_step(&args.step)?;
+    format_time(start, &args.format)?;
+    if args.concurrency == 0 {
+        return Err(anyhow::anyhow!("Concurrency must be at least 1"));
+    }
+
+    let name = args
+        .name
+        .unwrap_or_else(|| format!("{source}:{}..{}", args.start, args.end));
+    let mut tags = RunTags::new();
+    tags.insert("daggyr.backfill".to_owned(), name.clone());
+
+    let slots = intervals(start, end, step);
+    let previous = interval_states(&client, &tags).await?;
+    let mut pending = slots
+        .iter()
+        .filter(|slot| match previous.get(&slot_tag(**slot)) {
+            Some(state) => args.retry_failed && failed(*state),
+            None => true,
+        })
+        .peekable();
+    info!(
+        "Backfill {name}: {} intervals, {} to submit",
+        slots.len(),
+        pending.clone().count()
+    );
+
+    loop {
+        let active = client.runs(&tags, &ACTIVE_STATES).await?.len();
+        if active == 0 && pending.peek().is_none() {
+            break;
+        }
+        for slot in pending
+            .by_ref()
+            .take(args.concurrency.saturating_sub(active))
+        {
+            let mut run_tags = tags.clone();
+            run_tags.insert(SLOT_TAG.to_owned(), slot_tag(*slot));
+            let parameters = Parameters::from([(
+                args.parameter.clone(),
+                vec![format_time(*slot, &args.format)?],
+            )]);
+            let run_id = source.submit(&client, &run_tags, parameters).await?;
+            info!("Backfill {name}: submitted run {run_id} for {slot}");
+        }
+        tokio::time::sleep(Duration::from_secs(args.poll_seconds)).await;
+    }
+
+    let states = interval_states(&client, &tags).await?;
+    let failures = states.values().filter(|state| failed(**state)).count();
+    info!(
+        "Backfill {name}: {} of {} intervals completed",
+        states
+            .values()
+            .filter(|state| **state == State::Completed)
+            .count(),
+        slots.len()
+    );
+    if failures > 0 {
+        return Err(anyhow::anyhow!(
+            "{failures} runs of backfill {name} failed, rerun with --retry-failed to resubmit them"
+        ));
+    }
+    Ok(())
+}
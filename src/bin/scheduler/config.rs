@@ -0,0 +1,138 @@
+use crate::source::RunSource;
+use chrono::prelude::*;
+use daggyr::prelude::*;
+use daggyr::schedule::{self, Schedule};
+pub use serde::Deserialize;
+use std::path::PathBuf;
+
+fn default_server() -> String {
+    "http://127.0.0.1:2503".to_owned()
+}
+
+fn default_state_file() -> String {
+    "scheduler_state.json".to_owned()
+}
+
+fn default_poll_seconds() -> u64 {
+    30
+}
+
+fn default_parameter() -> String {
+    "{{DATE}}".to_owned()
+}
+
+fn default_format() -> String {
+    "%Y-%m-%d".to_owned()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScheduleSpec {
+    /// A cron expression, evaluated in UTC
+    pub cron: String,
+
+    /// A spec file to submit, in JSON, YAML or TOML
+    #[serde(default)]
+    pub spec: Option<PathBuf>,
+
+    /// The name of a template stored on the server to submit
+    #[serde(default)]
+    pub template: Option<String>,
+
+    /// The parameter the time of the slot is passed in. The slot is also
+    /// the base for the run's date macros, so the default `{{DATE}}` agrees
+    /// with `{{DATE-1d}}` and the like.
+    #[serde(default = "default_parameter")]
+    pub parameter: String,
+
+    /// How the time of the slot is formatted
+    #[serde(default = "default_format")]
+    pub format: String,
+
+    /// Submit every missed slot in order, instead of skipping them
+    #[serde(default)]
+    pub catch_up: bool,
+
+    /// Where the schedule starts the first time it's seen. Defaults to now.
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+
+    /// Extra tags to add to each run
+    #[serde(default)]
+    pub tags: RunTags,
+}
+
+impl ScheduleSpec {
+    pub fn build(&self, name: &str) -> daggyr::Result<ScheduledRun> {
+        let schedule = self
+            .cron
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Schedule {name}: {e}"))?;
+        let source = match (&self.spec, &self.template) {
+            (Some(path), None) => RunSource::Spec(path.clone()),
+            (None, Some(template)) => RunSource::Template(template.clone()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Schedule {name} needs either a spec or a template"
+                ))
+            }
+        };
+        schedule::format_time(Utc::now(), &self.format)?;
+
+        let mut tags = self.tags.clone();
+        tags.insert("daggyr.schedule".to_owned(), name.to_owned());
+
+        Ok(ScheduledRun {
+            name: name.to_owned(),
+            schedule,
+            source,
+            parameter: self.parameter.clone(),
+            format: self.format.clone(),
+            catch_up: self.catch_up,
+            start: self.start,
+            tags,
+        })
+    }
+}
+
+/// A schedule ready to run
+#[derive(Debug, Clone)]
+pub struct ScheduledRun {
+    pub name: String,
+    pub schedule: Schedule,
+    pub source: RunSource,
+    pub parameter: String,
+    pub format: String,
+    pub catch_up: bool,
+    pub start: Option<DateTime<Utc>>,
+
+    /// Tags shared by every run of the schedule
+    pub tags: RunTags,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SchedulerConfig {
+    /// The base URL of the daggyr server
+    #[serde(default = "default_server")]
+    pub server: String,
+
+    /// Where the last slot submitted for each schedule is kept
+    #[serde(default = "default_state_file")]
+    pub state_file: String,
+
+    #[serde(default = "default_poll_seconds")]
+    pub poll_seconds: u64,
+
+    #[serde(default)]
+    pub schedules: HashMap<String, ScheduleSpec>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            server: default_server(),
+            state_file: default_state_file(),
+            poll_seconds: default_poll_seconds(),
+            schedules: HashMap::new(),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+mod backfill;
+mod config;
+mod source;
+
+use backfill::{backfill, BackfillArgs};
+use chrono::prelude::*;
+use clap::{Parser, Subcommand};
+use config::*;
+use daggyr::client::{Client, ACTIVE_STATES};
+use daggyr::prelude::*;
+use daggyr::schedule::format_time;
+use daggyr::Result;
+use log::{error, info, warn};
+use source::slot_tag;
+use std::time::Duration;
+
+/// The last slot submitted for each schedule
+type SlotState = HashMap<String, DateTime<Utc>>;
+
+fn load_state(path: &str) -> Result<SlotState> {
+    match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json)
+            .map_err(|e| anyhow::anyhow!("Unable to parse state file {path}: {e}")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SlotState::new()),
+        Err(e) => Err(anyhow::anyhow!("Unable to read state file {path}: {e}")),
+    }
+}
+
+fn save_state(path: &str, state: &SlotState) -> Result<()> {
+    // Written alongside and renamed, so a crash can't leave it half written
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(state)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Submits the next due slot of a schedule, if there is one. If the
+/// previous run of the schedule is still going, the slot is skipped, or
+/// for schedules that catch up, held until the run finishes.
+async fn submit_due(
+    scheduled: &ScheduledRun,
+    client: &Client,
+    last_slots: &mut SlotState,
+) -> Result<()> {
+    let now = Utc::now();
+    let name = &scheduled.name;
+    let Some(first) = scheduled
+        .schedule
+        .next_after(last_slots[name])
+        .filter(|slot| *slot <= now)
+    else {
+        return Ok(());
+    };
+    let mut latest = first;
+    let mut missed = 0;
+    while let Some(next) = scheduled
+        .schedule
+        .next_after(latest)
+        .filter(|slot| *slot <= now)
+    {
+        latest = next;
+        missed += 1;
+    }
+
+    if let Some(run) = client.runs(&scheduled.tags, &ACTIVE_STATES).await?.first() {
+        if scheduled.catch_up {
+            info!(
+                "{name}: waiting on run {} before submitting {first}",
+                run.run_id
+            );
+        } else {
+            warn!(
+                "{name}: skipping {latest}, run {} is still going",
+                run.run_id
+            );
+            last_slots.insert(name.clone(), latest);
+        }
+        return Ok(());
+    }
+
+    let slot = if scheduled.catch_up {
+        first
+    } else {
+        if missed > 0 {
+            warn!("{name}: skipping {missed} missed slots before {latest}");
+        }
+        latest
+    };
+
+    let mut tags = scheduled.tags.clone();
+    tags.insert(SLOT_TAG.to_owned(), slot_tag(slot));
+    let parameters = Parameters::from([(
+        scheduled.parameter.clone(),
+        vec![format_time(slot, &scheduled.format)?],
+    )]);
+    let run_id = scheduled.source.submit(client, &tags, parameters).await?;
+    info!("{name}: submitted run {run_id} for {slot}");
+    last_slots.insert(name.clone(), slot);
+    Ok(())
+}
+
+async fn run_schedules(config: SchedulerConfig, client: Client) -> Result<()> {
+    let schedules = config
+        .schedules
+        .iter()
+        .map(|(name, spec)| spec.build(name))
+        .collect::<Result<Vec<ScheduledRun>>>()?;
+    if schedules.is_empty() {
+        return Err(anyhow::anyhow!("No schedules are configured"));
+    }
+
+    let mut last_slots = load_state(&config.state_file)?;
+    let now = Utc::now();
+    for scheduled in &schedules {
+        // Slots are taken strictly after the last one, so back up a second
+        // to include a start that falls on a slot
+        last_slots.entry(scheduled.name.clone()).or_insert_with(|| {
+            scheduled
+                .start
+                .map_or(now, |start| start - chrono::Duration::seconds(1))
+        });
+    }
+
+    loop {
+        for scheduled in &schedules {
+            if let Err(e) = submit_due(scheduled, &client, &mut last_slots).await {
+                error!("{}: {e}", scheduled.name);
+            }
+        }
+        save_state(&config.state_file, &last_slots)?;
+        tokio::time::sleep(Duration::from_secs(config.poll_seconds)).await;
+    }
+}
+
+fn init(config_file: &str) -> SchedulerConfig {
+    if config_file.is_empty() {
+        SchedulerConfig::default()
+    } else {
+        let json = std::fs::read_to_string(config_file)
+            .unwrap_or_else(|_| panic!("Unable to open {} for reading", config_file));
+        serde_json::from_str(&json).expect("Error parsing config json")
+    }
+}
+
+// Only one is ever made, so boxing the larger variant wouldn't save anything
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Submit runs as their schedules come due (the default)
+    Run,
+
+    /// Submit a run for each interval of a date range
+    Backfill(BackfillArgs),
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about)]
+struct Args {
+    /// Configuration File
+    #[clap(short, long, default_value = "")]
+    config: String,
+
+    /// The base URL of the daggyr server, overriding the configuration
+    #[clap(short, long)]
+    server: Option<String>,
+
+    /// Enable verbose logging
+    #[clap(short, long)]
+    verbose: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+async fn run_command(command: Command, config: SchedulerConfig) -> Result<()> {
+    let client = Client::new(&config.server)?;
+    match command {
+        Command::Run => run_schedules(config, client).await,
+        Command::Backfill(backfill_args) => backfill(backfill_args, client).await,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    let mut config = init(args.config.as_ref());
+    if let Some(server) = args.server {
+        config.server = server;
+    }
+
+    if args.verbose {
+        println!("{:?}", config);
+    }
+
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    if let Err(e) = run_command(args.command.unwrap_or(Command::Run), config).await {
+        error!("{e}");
+        std::process::exit(1);
+    }
+}
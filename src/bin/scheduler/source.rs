@@ -0,0 +1,53 @@
+use chrono::prelude::*;
+use daggyr::client::Client;
+use daggyr::prelude::*;
+use daggyr::specs;
+use daggyr::Result;
+use std::fmt;
+use std::path::PathBuf;
+
+pub fn slot_tag(slot: DateTime<Utc>) -> String {
+    slot.to_rfc3339()
+}
+
+/// Where scheduled runs come from
+#[derive(Clone, Debug)]
+pub enum RunSource {
+    /// A spec file, read each time a run is submitted
+    Spec(PathBuf),
+
+    /// A template stored on the server
+    Template(String),
+}
+
+impl fmt::Display for RunSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunSource::Spec(path) => write!(f, "{}", path.display()),
+            RunSource::Template(name) => write!(f, "template {name}"),
+        }
+    }
+}
+
+impl RunSource {
+    /// Submits a run, adding `tags` and `parameters` to those of its spec
+    pub async fn submit(
+        &self,
+        client: &Client,
+        tags: &RunTags,
+        parameters: Parameters,
+    ) -> Result<RunID> {
+        let result = match self {
+            RunSource::Spec(path) => {
+                let mut spec: RunSpec = specs::read_run(path)?;
+                for (key, value) in tags.iter() {
+                    spec.tags.insert(key.clone(), value.clone());
+                }
+                spec.parameters.extend(parameters);
+                client.submit(&spec).await
+            }
+            RunSource::Template(name) => client.submit_template(name, tags, &parameters).await,
+        };
+        result.map_err(|e| anyhow::anyhow!("Unable to submit {self}: {e}"))
+    }
+}
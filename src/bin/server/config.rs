@@ -50,6 +50,14 @@ pub enum PoolConfig {
     Slurm { base_url: String },
 }
 
+impl PoolConfig {
+    /// Whether the pool runs tasks on the server itself, where the runner
+    /// checks their input and output files
+    pub fn is_local(&self) -> bool {
+        matches!(self, PoolConfig::Local { .. })
+    }
+}
+
 fn default_pools() -> HashMap<String, PoolConfig> {
     HashMap::from([(
         "default".to_owned(),
@@ -84,6 +92,10 @@ pub struct GlobalConfigSpec {
 
     #[serde(default)]
     pub default_pool: String,
+
+    /// Named semaphores shared by all runs, and how many tasks can hold each
+    #[serde(default)]
+    pub semaphores: HashMap<String, usize>,
 }
 
 #[derive(Clone)]
@@ -134,7 +146,7 @@ impl GlobalConfig {
         // Runner
         let (runner, rrx) = mpsc::unbounded_channel();
         let rtx = runner.clone();
-        runner::start(rtx, rrx);
+        runner::start_with_semaphores(&spec.semaphores, rtx, rrx);
 
         let default_pool = if spec.default_pool.is_empty() {
             pools.keys().next().unwrap().clone()
@@ -1,16 +1,20 @@
 mod config;
 
 use actix_cors::Cors;
-use actix_web::{error, middleware::Logger, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{
+    error, middleware::Logger, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
 use chrono::prelude::*;
 use clap::Parser;
 use config::*;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 
+use daggyr::graph::{GraphFormat, RunGraph};
 use daggyr::prelude::*;
-use tokio::sync::oneshot;
+use daggyr::specs::{self, SpecFormat};
+use tokio::sync::{mpsc, oneshot};
 
 #[derive(Serialize)]
 struct SimpleError {
@@ -22,20 +26,6 @@ struct RunIDResponse {
     run_id: RunID,
 }
 
-#[derive(Clone, Deserialize)]
-struct RunSpec {
-    #[serde(default)]
-    tags: RunTags,
-
-    tasks: TaskSet,
-
-    #[serde(default)]
-    parameters: Parameters,
-
-    #[serde(default)]
-    pool: Option<String>,
-}
-
 fn min_datetime() -> DateTime<Utc> {
     chrono::MIN_DATETIME
 }
@@ -44,12 +34,39 @@ fn max_datetime() -> DateTime<Utc> {
     chrono::MAX_DATETIME
 }
 
+/// Reads tags given as comma separated `key=value` pairs
+fn tag_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RunTags, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let mut tags = RunTags::new();
+    for pair in text.split(',').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| D::Error::custom(format!("Expected key=value, found {pair:?}")))?;
+        tags.insert(key.to_owned(), value.to_owned());
+    }
+    Ok(tags)
+}
+
+/// Reads comma separated states
+fn state_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashSet<State>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    text.split(',')
+        .filter(|state| !state.is_empty())
+        .map(|state| {
+            serde_json::from_value(serde_json::Value::String(state.trim().to_owned()))
+                .map_err(D::Error::custom)
+        })
+        .collect()
+}
+
 #[derive(Clone, Deserialize, Debug)]
 struct RunsSelection {
-    #[serde(default)]
+    /// e.g. `?tags=env=prod,team=data`
+    #[serde(default, deserialize_with = "tag_list")]
     tags: RunTags,
 
-    #[serde(default)]
+    /// e.g. `?states=Queued,Running`. If empty, runs in any state match.
+    #[serde(default, deserialize_with = "state_list")]
     states: HashSet<State>,
 
     #[serde(default = "min_datetime")]
@@ -72,7 +89,11 @@ async fn get_runs(
         .tracker
         .send(TrackerMessage::GetRuns {
             tags: Some(criteria.tags.clone()),
-            states: Some(criteria.states.clone()),
+            states: if criteria.states.is_empty() {
+                None
+            } else {
+                Some(criteria.states.clone())
+            },
             start_time: Some(criteria.start_time),
             end_time: Some(criteria.end_time),
             response,
@@ -82,55 +103,349 @@ async fn get_runs(
     HttpResponse::Ok().json(rx.await.unwrap().unwrap_or_default())
 }
 
-async fn submit_run(spec: web::Json<RunSpec>, state: web::Data<AppState>) -> impl Responder {
+/// Parses a run spec from a request body as JSON, YAML or TOML, depending
+/// on its `Content-Type`
+fn parse_run_spec(req: &HttpRequest, body: &[u8]) -> daggyr::Result<RunSpec> {
+    let content_type = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .map(|value| value.to_str())
+        .transpose()?;
+    let format = SpecFormat::from_content_type(content_type)?;
+    specs::parse_run(std::str::from_utf8(body)?, format)
+}
+
+/// Reads a submitted run, returning its spec, the pool it should run in, and
+/// its tasks along with its hooks
+fn read_run(
+    req: &HttpRequest,
+    body: &[u8],
+    state: &AppState,
+) -> std::result::Result<(RunSpec, String, TaskSet), SimpleError> {
+    let spec = parse_run_spec(req, body).map_err(|e| SimpleError {
+        error: format!("Parsing error: {e}"),
+    })?;
+    let (pool, tasks) = check_run(&spec, state)?;
+    Ok((spec, pool, tasks))
+}
+
+/// Checks the pool and hooks of a run spec, returning the pool it should run
+/// in and its tasks along with its hooks
+fn check_run(
+    spec: &RunSpec,
+    state: &AppState,
+) -> std::result::Result<(String, TaskSet), SimpleError> {
     let pool = match &spec.pool {
         Some(name) => name.clone(),
         None => state.config.default_pool.clone(),
     };
 
     if !state.config.pools.contains_key(&pool) {
-        return HttpResponse::BadRequest().json(SimpleError {
+        return Err(SimpleError {
             error: format!("Pool {} is not defined", pool),
         });
     }
 
-    let tasks: Vec<Task> = spec.tasks.iter().map(|(_, v)| v).cloned().collect();
+    let mut tasks = spec.tasks.clone();
+    if let Err(e) = spec.hooks.clone().add_to(&mut tasks) {
+        return Err(SimpleError {
+            error: e.to_string(),
+        });
+    }
 
-    // Validate the tasks
-    for task in &tasks {
-        let (response, rx) = oneshot::channel();
-        state.config.pools[&pool]
-            .send(ExecutorMessage::ValidateTask { details: task.details.clone(), response })
-            .expect("Unable to contact executor");
-        if let Err(e) = rx.await.unwrap() {
-            return HttpResponse::BadRequest().json(SimpleError {
-                error: format!("Invalid tasks for pool {}: {:?}", pool, e),
+    if !state.config.spec.pools[&pool].is_local() {
+        if let Some(task_id) = task_with_files(&tasks) {
+            return Err(SimpleError {
+                error: format!(
+                    "Task {task_id} has inputs or outputs, which pool {pool} doesn't support"
+                ),
             });
         }
     }
+    Ok((pool, tasks))
+}
+
+/// Finds a task that declares input or output files, including the inline
+/// tasks of sub-runs. The server checks these files itself, so they can't be
+/// used with tasks that run elsewhere.
+fn task_with_files(tasks: &TaskSet) -> Option<TaskID> {
+    for (task_id, task) in tasks {
+        if !task.inputs.is_empty() || !task.outputs.is_empty() {
+            return Some(task_id.clone());
+        }
+        if task.task_type != TaskType::Subrun {
+            continue;
+        }
+        let Ok(subrun) = serde_json::from_value::<SubrunSpec>(task.details.clone()) else {
+            continue;
+        };
+        let mut nested = subrun.tasks;
+        nested.extend(subrun.hooks.finally);
+        nested.extend(subrun.hooks.on_success);
+        nested.extend(subrun.hooks.on_failure);
+        if let Some(nested_id) = task_with_files(&nested) {
+            return Some(format!("{task_id}/{nested_id}"));
+        }
+    }
+    None
+}
+
+async fn submit_run(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    match read_run(&req, &body, &state) {
+        Ok((spec, pool, tasks)) => start_run(spec, pool, tasks, &state).await,
+        Err(error) => HttpResponse::BadRequest().json(error),
+    }
+}
 
+async fn start_run(spec: RunSpec, pool: String, tasks: TaskSet, state: &AppState) -> HttpResponse {
+    // Tasks are validated by the pool's executor once they've been expanded
     let (tx, rx) = oneshot::channel();
     state
         .config
         .runner
         .send(RunnerMessage::Start {
             tags: spec.tags.clone(),
-            tasks: spec.tasks.clone(),
+            tasks,
             response: tx,
             parameters: spec.parameters.clone(),
+            filter: TaskFilter {
+                include: spec.include.clone(),
+                exclude: spec.exclude.clone(),
+            },
             tracker: state.config.tracker.clone(),
             executor: state.config.pools.get(&pool).unwrap().clone(),
         })
         .unwrap();
 
     match rx.await.unwrap() {
-        Ok(run_id) => HttpResponse::Ok().json(RunIDResponse { run_id }),
+        Ok(run_id) => {
+            state.run_pools.lock().unwrap().insert(run_id, pool);
+            HttpResponse::Ok().json(RunIDResponse { run_id })
+        }
         Err(error) => HttpResponse::BadRequest().json(SimpleError {
             error: format!("{:?}", error),
         }),
     }
 }
 
+/// Expands a run spec without starting it, reporting what it would run
+async fn plan_run(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let (spec, pool, tasks) = match read_run(&req, &body, &state) {
+        Ok(run) => run,
+        Err(error) => return HttpResponse::BadRequest().json(error),
+    };
+
+    let (response, rx) = oneshot::channel();
+    state
+        .config
+        .runner
+        .send(RunnerMessage::Plan {
+            tasks,
+            parameters: spec.parameters,
+            filter: TaskFilter {
+                include: spec.include,
+                exclude: spec.exclude,
+            },
+            tracker: state.config.tracker.clone(),
+            executor: state.config.pools[&pool].clone(),
+            pools: state.config.pools.clone(),
+            response,
+        })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(plan) => HttpResponse::Ok().json(plan),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{:?}", error),
+        }),
+    }
+}
+
+#[derive(Serialize)]
+struct TemplateVersion {
+    name: String,
+    version: usize,
+}
+
+/// Stores a run spec as a new version of a template
+async fn store_template(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let spec = match read_run(&req, &body, &state) {
+        Ok((spec, _, _)) => spec,
+        Err(error) => return HttpResponse::BadRequest().json(error),
+    };
+
+    let (response, rx) = oneshot::channel();
+    state
+        .config
+        .tracker
+        .send(TrackerMessage::StoreTemplate {
+            name: name.clone(),
+            spec,
+            response,
+        })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(version) => HttpResponse::Ok().json(TemplateVersion { name, version }),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{:?}", error),
+        }),
+    }
+}
+
+async fn get_templates(state: web::Data<AppState>) -> impl Responder {
+    let (response, rx) = oneshot::channel();
+    state
+        .config
+        .tracker
+        .send(TrackerMessage::GetTemplates { response })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(templates) => HttpResponse::Ok().json(templates),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{:?}", error),
+        }),
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct TemplateSelection {
+    /// Defaults to the latest version
+    #[serde(default)]
+    version: Option<usize>,
+}
+
+async fn fetch_template(
+    name: String,
+    version: Option<usize>,
+    state: &AppState,
+) -> daggyr::Result<RunTemplate> {
+    let (response, rx) = oneshot::channel();
+    state
+        .config
+        .tracker
+        .send(TrackerMessage::GetTemplate {
+            name,
+            version,
+            response,
+        })
+        .unwrap();
+    rx.await?
+}
+
+async fn get_template(
+    path: web::Path<String>,
+    selection: web::Query<TemplateSelection>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    match fetch_template(path.into_inner(), selection.version, &state).await {
+        Ok(template) => HttpResponse::Ok().json(template),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{:?}", error),
+        }),
+    }
+}
+
+async fn delete_template(path: web::Path<String>, state: web::Data<AppState>) -> impl Responder {
+    let name = path.into_inner();
+    let (response, rx) = oneshot::channel();
+    state
+        .config
+        .tracker
+        .send(TrackerMessage::DeleteTemplate { name, response })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{:?}", error),
+        }),
+    }
+}
+
+/// Overrides applied to a template when starting a run from it
+#[derive(Clone, Deserialize, Debug, Default)]
+struct TemplateRun {
+    /// Defaults to the latest version
+    #[serde(default)]
+    version: Option<usize>,
+
+    #[serde(default)]
+    tags: RunTags,
+
+    #[serde(default)]
+    parameters: Parameters,
+
+    #[serde(default)]
+    pool: Option<String>,
+}
+
+/// Starts a run from a stored template. The run is tagged with the name and
+/// version of the template it came from.
+async fn submit_template_run(
+    path: web::Path<String>,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let overrides: TemplateRun = if body.is_empty() {
+        TemplateRun::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                return HttpResponse::BadRequest().json(SimpleError {
+                    error: format!("Parsing error: {e}"),
+                })
+            }
+        }
+    };
+
+    let template = match fetch_template(name, overrides.version, &state).await {
+        Ok(template) => template,
+        Err(error) => {
+            return HttpResponse::BadRequest().json(SimpleError {
+                error: format!("{:?}", error),
+            })
+        }
+    };
+
+    let mut spec = template.spec;
+    let mut tags = overrides.tags;
+    tags.insert("daggyr.template".to_owned(), template.name);
+    tags.insert(
+        "daggyr.template_version".to_owned(),
+        template.version.to_string(),
+    );
+    for (key, value) in tags.iter() {
+        spec.tags.insert(key.clone(), value.clone());
+    }
+    spec.parameters.extend(overrides.parameters);
+    if overrides.pool.is_some() {
+        spec.pool = overrides.pool;
+    }
+
+    match check_run(&spec, &state) {
+        Ok((pool, tasks)) => start_run(spec, pool, tasks, &state).await,
+        Err(error) => HttpResponse::BadRequest().json(error),
+    }
+}
+
 async fn get_run(path: web::Path<RunID>, state: web::Data<AppState>) -> impl Responder {
     let run_id = path.into_inner();
     let (response, rx) = oneshot::channel();
@@ -167,6 +482,49 @@ async fn get_task_summary(path: web::Path<RunID>, state: web::Data<AppState>) ->
     }
 }
 
+#[derive(Clone, Deserialize, Debug)]
+struct GraphOptions {
+    #[serde(default)]
+    format: GraphFormat,
+
+    /// Fill tasks with the color of their state
+    #[serde(default)]
+    color: bool,
+}
+
+async fn get_run_graph(
+    path: web::Path<RunID>,
+    options: web::Query<GraphOptions>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let run_id = path.into_inner();
+    let (response, rx) = oneshot::channel();
+
+    state
+        .config
+        .tracker
+        .send(TrackerMessage::GetTasks { run_id, response })
+        .unwrap();
+
+    let graph = match rx.await.unwrap() {
+        Ok(records) => RunGraph::from_records(&records),
+        Err(error) => {
+            return HttpResponse::BadRequest().json(SimpleError {
+                error: format!("{:?}", error),
+            })
+        }
+    };
+    match options.format {
+        GraphFormat::Json => HttpResponse::Ok().json(graph),
+        GraphFormat::Dot => HttpResponse::Ok()
+            .content_type("text/vnd.graphviz; charset=utf-8")
+            .body(graph.to_dot(&format!("run_{run_id}"), options.color)),
+        GraphFormat::Mermaid => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(graph.to_mermaid(options.color)),
+    }
+}
+
 async fn get_run_state(path: web::Path<RunID>, state: web::Data<AppState>) -> impl Responder {
     let run_id = path.into_inner();
     let (response, rx) = oneshot::channel();
@@ -228,6 +586,165 @@ async fn get_run_task(
     }
 }
 
+#[derive(Clone, Deserialize, Debug)]
+struct TaskAction {
+    #[serde(default)]
+    actor: Option<String>,
+
+    #[serde(default)]
+    reason: Option<String>,
+
+    /// Only used when rerunning tasks
+    #[serde(default)]
+    descendants: bool,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct TaskMark {
+    state: State,
+
+    #[serde(default)]
+    actor: Option<String>,
+
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+fn run_executor(
+    state: &web::Data<AppState>,
+    run_id: RunID,
+) -> mpsc::UnboundedSender<ExecutorMessage> {
+    let pool = {
+        let pools = state.run_pools.lock().unwrap();
+        pools
+            .get(&run_id)
+            .unwrap_or(&state.config.default_pool)
+            .clone()
+    };
+    state.config.pools.get(&pool).unwrap().clone()
+}
+
+async fn kill_task(
+    path: web::Path<(RunID, TaskID)>,
+    action: web::Query<TaskAction>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let (run_id, task_id) = path.into_inner();
+    let action = action.into_inner();
+    let (response, rx) = oneshot::channel();
+
+    state
+        .config
+        .runner
+        .send(RunnerMessage::KillTask {
+            run_id,
+            task_id,
+            actor: action.actor,
+            reason: action.reason,
+            response,
+        })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(()) => HttpResponse::Ok().json(RunIDResponse { run_id }),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{error:?}"),
+        }),
+    }
+}
+
+async fn rerun_task(
+    path: web::Path<(RunID, TaskID)>,
+    action: web::Query<TaskAction>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let (run_id, task_id) = path.into_inner();
+    let action = action.into_inner();
+    let (response, rx) = oneshot::channel();
+
+    state
+        .config
+        .runner
+        .send(RunnerMessage::RerunTask {
+            run_id,
+            task_id,
+            descendants: action.descendants,
+            actor: action.actor,
+            reason: action.reason,
+            tracker: state.config.tracker.clone(),
+            executor: run_executor(&state, run_id),
+            response,
+        })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(()) => HttpResponse::Ok().json(RunIDResponse { run_id }),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{error:?}"),
+        }),
+    }
+}
+
+async fn mark_task(
+    path: web::Path<(RunID, TaskID)>,
+    mark: web::Json<TaskMark>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let (run_id, task_id) = path.into_inner();
+    let mark = mark.into_inner();
+    let (response, rx) = oneshot::channel();
+
+    state
+        .config
+        .runner
+        .send(RunnerMessage::MarkTask {
+            run_id,
+            task_id,
+            state: mark.state,
+            actor: mark.actor,
+            reason: mark.reason,
+            tracker: state.config.tracker.clone(),
+            response,
+        })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(()) => HttpResponse::Ok().json(RunIDResponse { run_id }),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{error:?}"),
+        }),
+    }
+}
+
+async fn update_task(
+    path: web::Path<(RunID, TaskID)>,
+    update: web::Json<TaskUpdate>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let (run_id, task_id) = path.into_inner();
+    let (response, rx) = oneshot::channel();
+
+    state
+        .config
+        .runner
+        .send(RunnerMessage::UpdateTask {
+            run_id,
+            task_id,
+            update: update.into_inner(),
+            tracker: state.config.tracker.clone(),
+            executor: run_executor(&state, run_id),
+            response,
+        })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(()) => HttpResponse::Ok().json(RunIDResponse { run_id }),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{error:?}"),
+        }),
+    }
+}
+
 async fn submit_task_attempt(
     payload: web::Json<AttemptReport>,
     state: web::Data<AppState>,
@@ -259,25 +776,53 @@ async fn stop_run(path: web::Path<RunID>, state: web::Data<AppState>) -> impl Re
     HttpResponse::Ok()
 }
 
-async fn retry_run(path: web::Path<RunID>, state: web::Data<AppState>) -> impl Responder {
-    let run_id = path.into_inner();
-    let (response, rx) = oneshot::channel();
+#[derive(Clone, Deserialize, Debug)]
+struct RetrySelection {
+    /// Comma-separated glob patterns of tasks to retry. If empty, all
+    /// incomplete tasks are retried.
+    #[serde(default)]
+    tasks: String,
 
-    let pool = {
-        let pools = state.run_pools.lock().unwrap();
-        pools
-            .get(&run_id)
-            .unwrap_or(&state.config.default_pool)
-            .clone()
+    #[serde(default)]
+    upstream: bool,
+
+    #[serde(default)]
+    downstream: bool,
+}
+
+async fn retry_run(
+    path: web::Path<RunID>,
+    selection: web::Query<RetrySelection>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let run_id = path.into_inner();
+    let selection = selection.into_inner();
+    let patterns: Vec<String> = selection
+        .tasks
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+    let selection = if patterns.is_empty() {
+        None
+    } else {
+        Some(TaskSelection {
+            patterns,
+            upstream: selection.upstream,
+            downstream: selection.downstream,
+        })
     };
+    let (response, rx) = oneshot::channel();
 
     state
         .config
         .runner
         .send(RunnerMessage::Retry {
             run_id,
+            selection,
             tracker: state.config.tracker.clone(),
-            executor: state.config.pools.get(&pool).unwrap().clone(),
+            executor: run_executor(&state, run_id),
             response,
         })
         .unwrap();
@@ -290,10 +835,55 @@ async fn retry_run(path: web::Path<RunID>, state: web::Data<AppState>) -> impl R
     }
 }
 
+async fn pause_run(path: web::Path<RunID>, state: web::Data<AppState>) -> impl Responder {
+    let run_id = path.into_inner();
+    let (response, rx) = oneshot::channel();
+
+    state
+        .config
+        .runner
+        .send(RunnerMessage::Pause { run_id, response })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(()) => HttpResponse::Ok().json(RunIDResponse { run_id }),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{:?}", error),
+        }),
+    }
+}
+
+async fn resume_run(path: web::Path<RunID>, state: web::Data<AppState>) -> impl Responder {
+    let run_id = path.into_inner();
+    let (response, rx) = oneshot::channel();
+
+    state
+        .config
+        .runner
+        .send(RunnerMessage::Resume { run_id, response })
+        .unwrap();
+
+    match rx.await.unwrap() {
+        Ok(()) => HttpResponse::Ok().json(RunIDResponse { run_id }),
+        Err(error) => HttpResponse::BadRequest().json(SimpleError {
+            error: format!("{:?}", error),
+        }),
+    }
+}
+
 async fn ready() -> impl Responder {
     HttpResponse::Ok()
 }
 
+/// The web UI, a single page that only talks to the API
+const UI_PAGE: &str = include_str!("ui/index.html");
+
+async fn ui() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(UI_PAGE)
+}
+
 fn init(config_file: &str) -> GlobalConfig {
     let spec: GlobalConfigSpec = if config_file.is_empty() {
         serde_json::from_str("{}").unwrap()
@@ -386,23 +976,42 @@ async fn main() -> std::io::Result<()> {
                 r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#,
             ))
             .app_data(json_config)
+            .app_data(web::PayloadConfig::new(1048576))
+            .route("/", web::get().to(ui))
+            .route("/ui", web::get().to(ui))
             .route("/ready", web::get().to(ready))
             .route("/task/attempt", web::post().to(submit_task_attempt))
             .service(
                 web::scope("/api/v1/runs")
                     .route("", web::get().to(get_runs))
                     .route("", web::post().to(submit_run))
+                    .route("/plan", web::post().to(plan_run))
                     .service(
                         web::scope("/{run_id}")
                             .route("", web::get().to(get_task_summary))
                             .route("", web::delete().to(stop_run))
                             .route("", web::patch().to(retry_run))
+                            .route("/pause", web::post().to(pause_run))
+                            .route("/resume", web::post().to(resume_run))
                             .route("/state", web::get().to(get_run_state))
                             .route("/full", web::get().to(get_run))
+                            .route("/graph", web::get().to(get_run_graph))
                             .route("/tasks", web::get().to(get_run_tasks))
-                            .route("/tasks/{task_id}", web::get().to(get_run_task)),
+                            .route("/tasks/{task_id}", web::get().to(get_run_task))
+                            .route("/tasks/{task_id}", web::delete().to(kill_task))
+                            .route("/tasks/{task_id}", web::patch().to(rerun_task))
+                            .route("/tasks/{task_id}/state", web::put().to(mark_task))
+                            .route("/tasks/{task_id}/definition", web::patch().to(update_task)),
                     ),
             )
+            .service(
+                web::scope("/api/v1/templates")
+                    .route("", web::get().to(get_templates))
+                    .route("/{name}", web::get().to(get_template))
+                    .route("/{name}", web::put().to(store_template))
+                    .route("/{name}", web::delete().to(delete_template))
+                    .route("/{name}/runs", web::post().to(submit_template_run)),
+            )
     })
     .bind(config.listen_spec())?
     .run()
@@ -0,0 +1,215 @@
+use super::Result;
+use crate::structs::{
+    Deserialize, Parameters, RunID, RunSpec, RunSummary, RunTags, State, StateChange, TaskID,
+    TaskRecord, TaskSummary,
+};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+
+/// Run states that haven't finished yet
+pub const ACTIVE_STATES: [State; 3] = [State::Queued, State::Running, State::Paused];
+
+#[derive(Deserialize)]
+struct RunIDResponse {
+    run_id: RunID,
+}
+
+#[derive(Deserialize)]
+struct SimpleError {
+    error: String,
+}
+
+/// A client for the server's `/api/v1` endpoints
+#[derive(Clone, Debug)]
+pub struct Client {
+    base_url: Url,
+    client: reqwest::Client,
+}
+
+impl Client {
+    /// # Errors
+    /// Will return `Err` if `base_url` isn't a valid URL
+    pub fn new(base_url: &str) -> Result<Self> {
+        let base_url =
+            Url::parse(base_url).map_err(|e| anyhow!("Invalid server URL {base_url}: {e}"))?;
+        if base_url.cannot_be_a_base() {
+            return Err(anyhow!("Invalid server URL {base_url}"));
+        }
+        Ok(Client {
+            base_url,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Builds the URL of an endpoint, escaping each part of its path
+    fn url(&self, path: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("Checked when the client was made")
+            .pop_if_empty()
+            .extend(["api", "v1"])
+            .extend(path);
+        url
+    }
+
+    /// Sends a request, returning the body of the response
+    async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+        let response = request.send().await?;
+        if response.status() == StatusCode::OK {
+            return Ok(response.json().await?);
+        }
+        let status = response.status();
+        let text = response.text().await?;
+        match serde_json::from_str::<SimpleError>(&text) {
+            Ok(error) => Err(anyhow!("{}", error.error)),
+            Err(_) if text.is_empty() => Err(anyhow!("Server responded with {status}")),
+            Err(_) => Err(anyhow!("Server responded with {status}: {text}")),
+        }
+    }
+
+    /// Sends a request, ignoring the body of the response
+    async fn send_empty(request: RequestBuilder) -> Result<()> {
+        Self::send::<serde_json::Value>(request)
+            .await
+            .or_else(|e| match e.downcast_ref::<reqwest::Error>() {
+                // Some endpoints don't return a body
+                Some(e) if e.is_decode() => Ok(serde_json::Value::Null),
+                _ => Err(e),
+            })
+            .map(|_| ())
+    }
+
+    /// Starts a run
+    /// # Errors
+    /// Will return `Err` if the server can't be reached, or rejects the run
+    pub async fn submit(&self, spec: &RunSpec) -> Result<RunID> {
+        let request = self.client.post(self.url(&["runs"])).json(spec);
+        Ok(Self::send::<RunIDResponse>(request).await?.run_id)
+    }
+
+    /// Starts a run from a stored template, adding `tags` and `parameters`
+    /// to its own
+    /// # Errors
+    /// Will return `Err` if the server can't be reached, or rejects the run
+    pub async fn submit_template(
+        &self,
+        name: &str,
+        tags: &RunTags,
+        parameters: &Parameters,
+    ) -> Result<RunID> {
+        let request = self
+            .client
+            .post(self.url(&["templates", name, "runs"]))
+            .json(&serde_json::json!({ "tags": tags, "parameters": parameters }));
+        Ok(Self::send::<RunIDResponse>(request).await?.run_id)
+    }
+
+    /// Returns the runs with all of `tags`, in any of `states`. If `states`
+    /// is empty, runs in any state are returned.
+    /// # Errors
+    /// Will return `Err` if the server can't be reached
+    pub async fn runs(&self, tags: &RunTags, states: &[State]) -> Result<Vec<RunSummary>> {
+        let tags: Vec<String> = tags.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        let states: Vec<String> = states.iter().map(|state| format!("{state:?}")).collect();
+        let request = self
+            .client
+            .get(self.url(&["runs"]))
+            .query(&[("tags", tags.join(",")), ("states", states.join(","))]);
+        Self::send(request).await
+    }
+
+    /// # Errors
+    /// Will return `Err` if the server can't be reached, or the run doesn't
+    /// exist
+    pub async fn state(&self, run_id: RunID) -> Result<StateChange> {
+        let request = self
+            .client
+            .get(self.url(&["runs", &run_id.to_string(), "state"]));
+        Self::send(request).await
+    }
+
+    /// # Errors
+    /// Will return `Err` if the server can't be reached, or the run doesn't
+    /// exist
+    pub async fn tasks(&self, run_id: RunID) -> Result<Vec<TaskSummary>> {
+        let request = self
+            .client
+            .get(self.url(&["runs", &run_id.to_string(), "tasks"]));
+        Self::send(request).await
+    }
+
+    /// # Errors
+    /// Will return `Err` if the server can't be reached, or the task doesn't
+    /// exist
+    pub async fn task(&self, run_id: RunID, task_id: &TaskID) -> Result<TaskRecord> {
+        let request = self
+            .client
+            .get(self.url(&["runs", &run_id.to_string(), "tasks", task_id]));
+        Self::send(request).await
+    }
+
+    /// Stops a run, killing its running tasks
+    /// # Errors
+    /// Will return `Err` if the server can't be reached
+    pub async fn stop_run(&self, run_id: RunID) -> Result<()> {
+        let request = self.client.delete(self.url(&["runs", &run_id.to_string()]));
+        Self::send_empty(request).await
+    }
+
+    /// # Errors
+    /// Will return `Err` if the server can't be reached, or the task can't
+    /// be killed
+    pub async fn kill_task(&self, run_id: RunID, task_id: &TaskID) -> Result<()> {
+        let request =
+            self.client
+                .delete(self.url(&["runs", &run_id.to_string(), "tasks", task_id]));
+        Self::send_empty(request).await
+    }
+
+    /// Retries the tasks of a run matching `patterns`, or every incomplete
+    /// task if there are none
+    /// # Errors
+    /// Will return `Err` if the server can't be reached, or the run can't be
+    /// retried
+    pub async fn retry(
+        &self,
+        run_id: RunID,
+        patterns: &[String],
+        upstream: bool,
+        downstream: bool,
+    ) -> Result<()> {
+        let request = self
+            .client
+            .patch(self.url(&["runs", &run_id.to_string()]))
+            .query(&[
+                ("tasks", patterns.join(",")),
+                ("upstream", upstream.to_string()),
+                ("downstream", downstream.to_string()),
+            ]);
+        Self::send_empty(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_urls() {
+        let client = Client::new("http://localhost:2503/").unwrap();
+        assert_eq!(
+            client
+                .url(&["runs", "3", "tasks", "load.DATE:2022/01/01"])
+                .as_str(),
+            "http://localhost:2503/api/v1/runs/3/tasks/load.DATE:2022%2F01%2F01"
+        );
+
+        let client = Client::new("https://example.com/daggyr").unwrap();
+        assert_eq!(
+            client.url(&["runs"]).as_str(),
+            "https://example.com/daggyr/api/v1/runs"
+        );
+
+        assert!(Client::new("localhost").is_err());
+    }
+}
@@ -125,15 +125,15 @@ where
         match (cur_state, state) {
             (_, State::Completed) => {
                 self.ready.remove(&idx);
-                self.visiting.remove(&idx);
+                self.visiting.insert(idx);
                 self.complete_visit(key, false)?;
             }
             (State::Errored | State::Killed, State::Queued) => {
-                self.ready.insert(idx);
+                self.update_ready(idx);
             }
             (_, State::Errored | State::Killed) => {
                 self.ready.remove(&idx);
-                self.visiting.remove(&idx);
+                self.visiting.insert(idx);
                 self.complete_visit(key, true)?;
             }
             (_, _) => {
@@ -148,6 +148,95 @@ where
         Ok(())
     }
 
+    /// Resets the vertices identified by `keys` to `Queued`, including ones
+    /// that have already completed. Any queued vertices downstream of a reset
+    /// vertex will wait for it to complete again.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a key doesn't identify a vertex in the DAG, or
+    /// if the vertex is currently being visited.
+    pub fn requeue_vertices(&mut self, keys: &[T]) -> Result<()> {
+        let mut indices = Vec::new();
+        for key in keys {
+            let idx = *self.keymap.get(key).ok_or_else(|| anyhow!("No such key"))?;
+            if self.visiting.contains(&idx) {
+                return Err(anyhow!("Currently visiting {key:?}"));
+            }
+            indices.push(idx);
+        }
+
+        for idx in indices {
+            self.vertices[idx].state = State::Queued;
+        }
+        self.refresh_ready();
+        Ok(())
+    }
+
+    /// Recomputes the outstanding dependencies of every queued vertex from
+    /// the states of its parents, e.g. after states were set out of order
+    pub fn refresh_ready(&mut self) {
+        for idx in 0..self.vertices.len() {
+            if self.vertices[idx].state == State::Queued && !self.visiting.contains(&idx) {
+                self.update_ready(idx);
+            }
+        }
+    }
+
+    /// Recomputes the outstanding dependencies of a single vertex
+    fn update_ready(&mut self, idx: usize) {
+        let outstanding = self.vertices[idx]
+            .parents
+            .iter()
+            .filter(|p| self.vertices[**p].state != State::Completed)
+            .count();
+        self.vertices[idx].parents_outstanding = outstanding;
+        if outstanding == 0 {
+            self.ready.insert(idx);
+        } else {
+            self.ready.remove(&idx);
+        }
+    }
+
+    /// Returns the keys of all vertices reachable from `key`, not
+    /// including `key` itself.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `key` doesn't identify a vertex in the DAG.
+    pub fn descendants(&self, key: &T) -> Result<Vec<T>> {
+        self.reachable(key, |v| &v.children)
+    }
+
+    /// Returns the keys of all vertices that `key` is reachable from,
+    /// not including `key` itself.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `key` doesn't identify a vertex in the DAG.
+    pub fn ancestors(&self, key: &T) -> Result<Vec<T>> {
+        self.reachable(key, |v| &v.parents)
+    }
+
+    /// Walks the DAG from `key`, following the edges returned by `edges`
+    fn reachable<F>(&self, key: &T, edges: F) -> Result<Vec<T>>
+    where
+        F: Fn(&Vertex<T>) -> &HashSet<usize>,
+    {
+        let src = *self.keymap.get(key).ok_or_else(|| anyhow!("No such key"))?;
+        let mut seen = HashSet::new();
+        let mut stack: Vec<usize> = edges(&self.vertices[src]).iter().copied().collect();
+        while let Some(idx) = stack.pop() {
+            if seen.insert(idx) {
+                stack.extend(edges(&self.vertices[idx]).iter().copied());
+            }
+        }
+        Ok(seen
+            .into_iter()
+            .map(|idx| self.vertices[idx].id.clone())
+            .collect())
+    }
+
     /// Add an edge from the vertex identified by `src` to the one
     /// identified by `dst`.
     ///
@@ -262,6 +351,11 @@ where
             self.vertices[idx].state = State::Completed;
             let children = self.vertices[idx].children.clone();
             for child in &children {
+                // Children that were already marked done aren't waiting on
+                // this vertex any more
+                if self.vertices[*child].state != State::Queued {
+                    continue;
+                }
                 self.vertices[*child].parents_outstanding -= 1;
                 if self.vertices[*child].parents_outstanding == 0 {
                     self.ready.insert(*child);
@@ -280,7 +374,59 @@ where
     /// Has everything been successfully visited
     #[must_use]
     pub fn is_complete(&self) -> bool {
-        self.visiting.is_empty() && self.ready.is_empty()
+        self.visiting.is_empty()
+            && self.ready.is_empty()
+            && self.vertices.iter().all(|v| v.state == State::Completed)
+    }
+
+    /// The number of edges in the DAG
+    #[must_use]
+    pub fn edge_count(&self) -> usize {
+        self.vertices.iter().map(|v| v.children.len()).sum()
+    }
+
+    /// The keys of vertices without any parents
+    #[must_use]
+    pub fn roots(&self) -> Vec<T> {
+        self.vertices
+            .iter()
+            .filter(|v| v.parents.is_empty())
+            .map(|v| v.id.clone())
+            .collect()
+    }
+
+    /// The keys of vertices without any children
+    #[must_use]
+    pub fn leaves(&self) -> Vec<T> {
+        self.vertices
+            .iter()
+            .filter(|v| v.children.is_empty())
+            .map(|v| v.id.clone())
+            .collect()
+    }
+
+    /// The total weight of the heaviest path through the DAG, where each
+    /// vertex weighs `weight(key)`
+    pub fn longest_path<F: Fn(&T) -> usize>(&self, weight: F) -> usize {
+        let mut outstanding: Vec<usize> = self.vertices.iter().map(|v| v.parents.len()).collect();
+        let mut lengths = vec![0; self.vertices.len()];
+        let mut ready: Vec<usize> = (0..self.vertices.len())
+            .filter(|idx| outstanding[*idx] == 0)
+            .collect();
+        let mut longest = 0;
+        while let Some(idx) = ready.pop() {
+            let vertex = &self.vertices[idx];
+            let length = lengths[idx] + weight(&vertex.id);
+            longest = longest.max(length);
+            for child in &vertex.children {
+                lengths[*child] = lengths[*child].max(length);
+                outstanding[*child] -= 1;
+                if outstanding[*child] == 0 {
+                    ready.push(*child);
+                }
+            }
+        }
+        longest
     }
 }
 
@@ -362,6 +508,101 @@ mod test {
         }
     }
 
+    #[test]
+    fn dag_requeue_vertices() {
+        let mut dag = DAG::new();
+        dag.add_vertices(&[0, 1, 2, 3]).unwrap();
+
+        // 0 -> 1 -> 2, 0 -> 3
+        for (src, dst) in &[(0usize, 1usize), (1, 2), (0, 3)] {
+            dag.add_edge(src, dst).unwrap();
+        }
+        dag.reset();
+
+        while let Some(id) = dag.visit_next() {
+            dag.complete_visit(&id, false).unwrap();
+        }
+        assert!(dag.is_complete());
+
+        let mut descendants = dag.descendants(&1).unwrap();
+        descendants.sort_unstable();
+        assert_eq!(descendants, vec![2]);
+        assert_eq!(dag.ancestors(&2).unwrap().len(), 2);
+
+        let mut targets = vec![1];
+        targets.extend(descendants);
+        dag.requeue_vertices(&targets).unwrap();
+        assert!(dag.can_progress());
+
+        let mut visited = Vec::new();
+        while let Some(id) = dag.visit_next() {
+            dag.complete_visit(&id, false).unwrap();
+            visited.push(id);
+        }
+        assert_eq!(visited, vec![1, 2]);
+        assert_eq!(dag.get_vertex(&0).unwrap().state, State::Completed);
+        assert_eq!(dag.get_vertex(&3).unwrap().state, State::Completed);
+    }
+
+    #[test]
+    fn dag_shape() {
+        let mut dag = DAG::new();
+        dag.add_vertices(&[0, 1, 2, 3, 4]).unwrap();
+
+        // 0 -> 1 -> 2 -> 3, 0 -> 3, 4 on its own
+        for (src, dst) in &[(0usize, 1usize), (1, 2), (2, 3), (0, 3)] {
+            dag.add_edge(src, dst).unwrap();
+        }
+
+        assert_eq!(dag.edge_count(), 4);
+        let mut roots = dag.roots();
+        roots.sort_unstable();
+        assert_eq!(roots, vec![0, 4]);
+        let mut leaves = dag.leaves();
+        leaves.sort_unstable();
+        assert_eq!(leaves, vec![3, 4]);
+        assert_eq!(dag.longest_path(|_| 1), 4);
+        assert_eq!(dag.longest_path(|id| usize::from(*id != 1)), 3);
+    }
+
+    #[test]
+    fn dag_set_vertex_states() {
+        let mut dag = DAG::new();
+        dag.add_vertices(&[0, 1, 2]).unwrap();
+        dag.add_edge(&0, &1).unwrap();
+        dag.add_edge(&1, &2).unwrap();
+        dag.reset();
+
+        // Marking a vertex done before it's ready lets its children go,
+        // and it isn't visited once its own parents finish
+        dag.set_vertex_state(&1, State::Completed).unwrap();
+        let mut visited = Vec::new();
+        while let Some(id) = dag.visit_next() {
+            dag.complete_visit(&id, false).unwrap();
+            visited.push(id);
+        }
+        visited.sort_unstable();
+        assert_eq!(visited, vec![0, 2]);
+        assert!(dag.is_complete());
+
+        // States restored children first don't visit anything twice
+        let mut dag = DAG::new();
+        dag.add_vertices(&[0, 1, 2]).unwrap();
+        dag.add_edge(&0, &1).unwrap();
+        dag.add_edge(&1, &2).unwrap();
+        dag.reset();
+        dag.set_vertex_state(&1, State::Completed).unwrap();
+        dag.set_vertex_state(&0, State::Completed).unwrap();
+        dag.refresh_ready();
+        assert_eq!(dag.visit_next(), Some(2));
+        assert_eq!(dag.visit_next(), None);
+        dag.complete_visit(&2, true).unwrap();
+
+        // A run with an errored vertex isn't complete
+        assert!(!dag.can_progress());
+        assert!(!dag.is_complete());
+    }
+
     #[test]
     fn dag_additions_during_traversal() {
         let mut dag = DAG::new();
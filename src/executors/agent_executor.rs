@@ -5,6 +5,7 @@ extern crate serde_json;
 
 use super::{local_executor, ExecutorMessage, Result, RunnerMessage, TrackerMessage};
 use crate::structs::{HashMap, RunID, State, TaskAttempt, TaskID, TaskResources, TaskDetails};
+use crate::utilities::number_or_string;
 use futures::stream::futures_unordered::FuturesUnordered;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, oneshot};
@@ -63,7 +64,7 @@ struct AgentTaskDetail {
     environment: HashMap<String, String>,
 
     /// Timeout in seconds
-    #[serde(default)]
+    #[serde(default, deserialize_with = "number_or_string")]
     timeout: i64,
 
     /// resources required by the task
@@ -1,11 +1,15 @@
 use super::{ExecutorMessage, Result, RunnerMessage, TrackerMessage};
-use crate::structs::{ExpansionValues, Parameters, RunID, State, TaskAttempt, TaskID, TaskDetails};
-use crate::utilities::{apply_vars, find_applicable_vars, generate_interpolation_sets};
+use crate::structs::{ExpansionValues, Parameters, RunID, State, TaskAttempt, TaskDetails, TaskID};
+use crate::utilities::{
+    apply_vars_to_details, detail_strings, find_applicable_vars, generate_interpolation_sets,
+    number_or_string, parse_outputs, resolve_parameters, ZIP_PARAMETER,
+};
 use chrono::prelude::*;
 use futures::stream::futures_unordered::FuturesUnordered;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::process::Command;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{sleep, Duration};
@@ -25,7 +29,7 @@ struct LocalTaskDetail {
     environment: HashMap<String, String>,
 
     /// Timeout in seconds
-    #[serde(default)]
+    #[serde(default, deserialize_with = "number_or_string")]
     timeout: u64,
 }
 
@@ -41,58 +45,47 @@ fn validate_task(details: &TaskDetails) -> Result<()> {
     }
 }
 
+/// Expands the parameters referenced anywhere in the task details
 /// # Errors
-/// Will return `Err` if the task details are missing required members
+/// Will return `Err` if the parameters are invalid
 pub fn expand_task_details(
     details: serde_json::Value,
     parameters: &Parameters,
 ) -> Result<Vec<(serde_json::Value, ExpansionValues)>> {
-    let mut expanded_tasks = Vec::new();
-
-    let template = extract_details(&details)?;
-
-    let all_vars: Vec<String> = parameters.keys().into_iter().cloned().collect();
-
-    // Need to decompose the environment to apply the expansion
-    let env_keys: Vec<String> = template.environment.keys().into_iter().cloned().collect();
-    let env_values: Vec<String> = env_keys
-        .iter()
-        .map(|x| template.environment[x].clone())
-        .collect();
+    expand_task_details_except(details, parameters, &[])
+}
 
-    // The expansion set will include both environment
-    let vars: HashSet<_> = find_applicable_vars(&template.command, &all_vars)
-        .union(&find_applicable_vars(&env_values, &all_vars))
+/// Expands the parameters referenced anywhere in the task details, except
+/// under the top-level fields named in `skip_fields`
+/// # Errors
+/// Will return `Err` if the parameters are invalid
+pub fn expand_task_details_except(
+    details: serde_json::Value,
+    parameters: &Parameters,
+    skip_fields: &[&str],
+) -> Result<Vec<(serde_json::Value, ExpansionValues)>> {
+    let parameters = &resolve_parameters(parameters)?;
+    let all_vars: Vec<String> = parameters
+        .keys()
+        .filter(|k| *k != ZIP_PARAMETER)
         .cloned()
         .collect();
 
+    let vars = find_applicable_vars(&detail_strings(&details, skip_fields), &all_vars);
     if vars.is_empty() {
-        expanded_tasks.push((details, Vec::new()));
-    } else {
-        // Build out the interpolation sets
-        let interpolation_sets = generate_interpolation_sets(parameters, &vars);
-
-        let new_cmds = apply_vars(&template.command, &interpolation_sets);
-        let new_envs = apply_vars(&env_values, &interpolation_sets);
-        for ((new_cmd, new_env_vals), int_set) in new_cmds
-            .iter()
-            .zip(new_envs.iter())
-            .zip(interpolation_sets.into_iter())
-        {
-            let mut new_details = details.clone();
-            new_details["command"] = serde_json::json!(new_cmd);
-            new_details["environment"] = env_keys
-                .iter()
-                .cloned()
-                .zip(new_env_vals.iter().cloned())
-                .collect();
-            expanded_tasks.push((new_details, int_set));
-        }
+        return Ok(vec![(details, Vec::new())]);
     }
 
-    Ok(expanded_tasks)
+    // Build out the interpolation sets
+    Ok(generate_interpolation_sets(parameters, &vars)
+        .into_iter()
+        .map(|set| (apply_vars_to_details(&details, &set, skip_fields), set))
+        .collect())
 }
 
+/// Used to give each task its own output files
+static OUTPUT_FILE_ID: AtomicUsize = AtomicUsize::new(0);
+
 async fn run_task(task: TaskDetails, mut stop_rx: oneshot::Receiver<()>) -> TaskAttempt {
     let details = extract_details(&task).unwrap();
     let mut attempt = TaskAttempt::new();
@@ -102,6 +95,18 @@ async fn run_task(task: TaskDetails, mut stop_rx: oneshot::Receiver<()>) -> Task
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
     command.args(args);
+
+    // Tasks can publish outputs by writing KEY=VALUE lines to the output
+    // file, and generators can write their tasks to the generator file
+    let file_id = format!(
+        "{}-{}",
+        std::process::id(),
+        OUTPUT_FILE_ID.fetch_add(1, Ordering::Relaxed)
+    );
+    let output_file = std::env::temp_dir().join(format!("daggyr-output-{file_id}"));
+    let generator_file = std::env::temp_dir().join(format!("daggyr-generator-{file_id}"));
+    command.env("DAGGY_OUTPUT_FILE", &output_file);
+    command.env("DAGGY_GENERATOR_FILE", &generator_file);
     command.envs(details.environment);
 
     attempt.start_time = Utc::now();
@@ -151,6 +156,14 @@ async fn run_task(task: TaskDetails, mut stop_rx: oneshot::Receiver<()>) -> Task
     attempt.output = String::from_utf8_lossy(&stdout_reader.await.unwrap()).to_string();
     attempt.error = String::from_utf8_lossy(&stderr_reader.await.unwrap()).to_string();
     attempt.exit_code = output.status.code().unwrap_or(-1i32);
+    if let Ok(contents) = tokio::fs::read_to_string(&output_file).await {
+        attempt.outputs = parse_outputs(&contents);
+        tokio::fs::remove_file(&output_file).await.unwrap_or(());
+    }
+    if let Ok(contents) = tokio::fs::read_to_string(&generator_file).await {
+        attempt.generated_tasks = contents;
+        tokio::fs::remove_file(&generator_file).await.unwrap_or(());
+    }
 
     attempt.stop_time = Utc::now();
     attempt
@@ -1,8 +1,9 @@
 use super::{local_executor, Result};
 use crate::prelude::*;
+use crate::utilities::number_or_string;
 use chrono::{DateTime, Utc};
 use futures::stream::futures_unordered::FuturesUnordered;
-use local_executor::expand_task_details;
+use local_executor::expand_task_details_except;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use tokio::sync::{mpsc, oneshot};
@@ -35,19 +36,25 @@ pub struct SlurmTaskDetail {
 
     pub jwt_token: String,
 
-    #[serde(default = "default_cpus")]
+    #[serde(default = "default_cpus", deserialize_with = "number_or_string")]
     pub min_cpus: usize,
 
-    #[serde(default = "default_min_memory_mb")]
+    #[serde(
+        default = "default_min_memory_mb",
+        deserialize_with = "number_or_string"
+    )]
     pub min_memory_mb: usize,
 
-    #[serde(default = "default_min_tmp_disk_mb")]
+    #[serde(
+        default = "default_min_tmp_disk_mb",
+        deserialize_with = "number_or_string"
+    )]
     pub min_tmp_disk_mb: usize,
 
-    #[serde(default = "default_priority")]
+    #[serde(default = "default_priority", deserialize_with = "number_or_string")]
     pub priority: usize,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "number_or_string")]
     pub time_limit_seconds: usize,
 
     /// The command and all arguments to run
@@ -336,8 +343,13 @@ pub async fn start_executor(base_url: String, mut msgs: mpsc::UnboundedReceiver<
                 parameters,
                 response,
             } => {
+                // Credentials are passed through untouched
                 response
-                    .send(expand_task_details(details, &parameters))
+                    .send(expand_task_details_except(
+                        details,
+                        &parameters,
+                        &["user", "jwt_token"],
+                    ))
                     .unwrap_or(());
             }
             ExecuteTask {
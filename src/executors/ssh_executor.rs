@@ -5,6 +5,7 @@ extern crate serde_json;
 
 use super::{local_executor, ExecutorMessage, Result, RunnerMessage};
 use crate::structs::{HashMap, TaskResources, TaskDetails};
+use crate::utilities::number_or_string;
 use futures::stream::futures_unordered::FuturesUnordered;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -52,7 +53,7 @@ struct SSHTaskDetail {
     environment: HashMap<String, String>,
 
     /// Timeout in seconds
-    #[serde(default)]
+    #[serde(default, deserialize_with = "number_or_string")]
     timeout: i64,
 
     /// Cores required by the task
@@ -63,11 +64,13 @@ fn extract_details(details: &TaskDetails) -> Result<SSHTaskDetail, serde_json::E
     serde_json::from_value::<SSHTaskDetail>(details.clone())
 }
 
+/// Characters that are still special inside double quotes
 fn shell_escape_char(ch: char) -> Option<&'static str> {
     match ch {
         '"' => Some("\\\""),
-        '\'' => Some("\\'"),
-        ';' => Some("\\;"),
+        '\\' => Some("\\\\"),
+        '$' => Some("\\$"),
+        '`' => Some("\\`"),
         _ => None,
     }
 }
@@ -108,12 +111,14 @@ fn sshify_task(mut details: TaskDetails, target: &SSHTarget) -> Result<TaskDetai
         new_command.push(key.clone());
     }
 
-    // Add the environment
-    for (k, v) in &parsed.environment {
-        new_command.push("export".to_owned());
-
-        // TODO -- this needs to be quoted properly
-        new_command.push(format!("{}={}", shell_escape(k), shell_escape(v)));
+    // The remote shell runs the command under `env` with the environment
+    if !parsed.environment.is_empty() {
+        new_command.push("env".to_owned());
+        let mut environment: Vec<_> = parsed.environment.iter().collect();
+        environment.sort();
+        for (k, v) in environment {
+            new_command.push(format!("{}={}", shell_escape(k), shell_escape(v)));
+        }
     }
 
     // Copy in the remaining
@@ -264,3 +269,54 @@ pub fn start(targets: Vec<SSHTarget>, msgs: mpsc::UnboundedReceiver<ExecutorMess
         start_ssh_executor(targets, msgs).await;
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_sshify_task() {
+        let target: SSHTarget = serde_json::from_value(json!({
+            "host": "worker",
+            "user": "daggy",
+            "port": 2222,
+            "resources": {}
+        }))
+        .unwrap();
+        let details = json!({
+            "command": [ "printenv", "DAGGY_TASK_ID", "DAGGY_RUN_TAGS" ],
+            "environment": {
+                "DAGGY_TASK_ID": "load;date",
+                "DAGGY_RUN_TAGS": r#"{"owner":"it's $USER\`x`"}"#
+            },
+            "resources": {}
+        });
+
+        let details = sshify_task(details, &target).unwrap();
+        let command: Vec<String> = serde_json::from_value(details["command"].clone()).unwrap();
+        assert_eq!(
+            command[..6],
+            [
+                "ssh",
+                "daggy@worker",
+                "-p",
+                "2222",
+                "env",
+                r#""DAGGY_RUN_TAGS"="{\"owner\":\"it's \$USER\\\`x\`\"}""#
+            ]
+        );
+
+        // ssh joins the remote command with spaces for the remote shell
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command[4..].join(" "))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "load;date\n{\"owner\":\"it's $USER\\`x`\"}\n"
+        );
+    }
+}
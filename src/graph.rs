@@ -0,0 +1,273 @@
+use crate::structs::{Deserialize, HookType, Serialize, State, TaskID, TaskRecord, TaskType};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// The formats a run's graph can be exported in
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    #[default]
+    Json,
+    Dot,
+    Mermaid,
+}
+
+/// A task in a run's graph
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct GraphVertex {
+    pub task_id: TaskID,
+    pub state: State,
+    pub task_type: TaskType,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hook: Option<HookType>,
+}
+
+/// A dependency between two tasks, pointing from parent to child
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GraphEdge {
+    pub parent: TaskID,
+    pub child: TaskID,
+}
+
+/// The tasks of a run and the dependencies between them, as currently
+/// recorded by the tracker, so expanded and generated tasks are included
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct RunGraph {
+    /// Sorted by task ID
+    pub vertices: Vec<GraphVertex>,
+
+    /// Sorted by parent, then child
+    pub edges: Vec<GraphEdge>,
+}
+
+/// The fill color used for tasks in each state
+#[must_use]
+pub fn state_color(state: State) -> &'static str {
+    match state {
+        State::Queued => "#9e9e9e",
+        State::Running => "#2196f3",
+        State::Paused => "#ff9800",
+        State::Completed => "#43a047",
+        State::Errored => "#e53935",
+        State::Killed => "#6d4c41",
+        State::Skipped => "#cfd8dc",
+    }
+}
+
+/// The text color that stays readable over `state_color`
+fn text_color(state: State) -> &'static str {
+    if state == State::Skipped {
+        "#212121"
+    } else {
+        "#ffffff"
+    }
+}
+
+impl RunGraph {
+    /// Builds the graph from the records of a run's tasks. Edges are taken
+    /// from both the parents and children of each task, and edges to tasks
+    /// that aren't in the run are dropped.
+    #[must_use]
+    pub fn from_records(records: &HashMap<TaskID, TaskRecord>) -> Self {
+        let mut vertices: Vec<GraphVertex> = records
+            .iter()
+            .map(|(task_id, record)| GraphVertex {
+                task_id: task_id.clone(),
+                state: record
+                    .state_changes
+                    .last()
+                    .map_or(State::Queued, |change| change.state),
+                task_type: record.task.task_type,
+                hook: record.task.hook,
+            })
+            .collect();
+        vertices.sort_by(|a, b| a.task_id.cmp(&b.task_id));
+
+        let mut edges = BTreeSet::new();
+        for (task_id, record) in records {
+            for child in &record.task.children {
+                edges.insert((task_id.clone(), child.clone()));
+            }
+            for parent in &record.task.parents {
+                edges.insert((parent.clone(), task_id.clone()));
+            }
+        }
+        let edges = edges
+            .into_iter()
+            .filter(|(parent, child)| records.contains_key(parent) && records.contains_key(child))
+            .map(|(parent, child)| GraphEdge { parent, child })
+            .collect();
+
+        RunGraph { vertices, edges }
+    }
+
+    /// Renders the graph in Graphviz's DOT language. Each task is labeled
+    /// with its state, and filled with the state's color if `color` is set.
+    #[must_use]
+    pub fn to_dot(&self, name: &str, color: bool) -> String {
+        let quote = |text: &str| {
+            let text = text
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("\"{text}\"")
+        };
+
+        let mut dot = format!("digraph {} {{\n", quote(name));
+        dot.push_str("  rankdir=LR;\n  node [shape=box, style=rounded];\n");
+        for vertex in &self.vertices {
+            let mut styles = vec!["rounded"];
+            if vertex.task_type == TaskType::Structural {
+                styles.push("dashed");
+            }
+            let mut attrs = format!(
+                "label={}",
+                quote(&format!("{}\n{:?}", vertex.task_id, vertex.state))
+            );
+            if color {
+                styles.push("filled");
+                let _ = write!(
+                    attrs,
+                    ", fillcolor=\"{}\", fontcolor=\"{}\"",
+                    state_color(vertex.state),
+                    text_color(vertex.state)
+                );
+            }
+            let _ = writeln!(
+                dot,
+                "  {} [{attrs}, style=\"{}\"];",
+                quote(&vertex.task_id),
+                styles.join(",")
+            );
+        }
+        for edge in &self.edges {
+            let _ = writeln!(dot, "  {} -> {};", quote(&edge.parent), quote(&edge.child));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the graph as a Mermaid flowchart. Task IDs can contain
+    /// characters Mermaid doesn't allow in node IDs, so nodes are numbered
+    /// and labeled with the task ID and state. If `color` is set, nodes are
+    /// given a class for their state, filled with the state's color.
+    #[must_use]
+    pub fn to_mermaid(&self, color: bool) -> String {
+        let node_ids: HashMap<&str, String> = self
+            .vertices
+            .iter()
+            .enumerate()
+            .map(|(i, vertex)| (vertex.task_id.as_str(), format!("t{i}")))
+            .collect();
+        // Quotes end a label, and `#` starts an entity code
+        let escape = |text: &str| text.replace('#', "#35;").replace('"', "#quot;");
+
+        let mut mermaid = "graph LR\n".to_owned();
+        for vertex in &self.vertices {
+            let _ = writeln!(
+                mermaid,
+                "  {}[\"{} ({:?})\"]",
+                node_ids[vertex.task_id.as_str()],
+                escape(&vertex.task_id),
+                vertex.state
+            );
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                mermaid,
+                "  {} --> {}",
+                node_ids[edge.parent.as_str()],
+                node_ids[edge.child.as_str()]
+            );
+        }
+        if color {
+            let mut by_state: Vec<(State, Vec<&str>)> = Vec::new();
+            for vertex in &self.vertices {
+                let node_id = node_ids[vertex.task_id.as_str()].as_str();
+                match by_state
+                    .iter_mut()
+                    .find(|(state, _)| *state == vertex.state)
+                {
+                    Some((_, nodes)) => nodes.push(node_id),
+                    None => by_state.push((vertex.state, vec![node_id])),
+                }
+            }
+            for (state, nodes) in by_state {
+                let _ = writeln!(
+                    mermaid,
+                    "  classDef {state:?} fill:{},color:{}",
+                    state_color(state),
+                    text_color(state)
+                );
+                let _ = writeln!(mermaid, "  class {} {state:?}", nodes.join(","));
+            }
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{StateChange, Task};
+
+    fn record(children: &[&str], parents: &[&str], state: State) -> TaskRecord {
+        let mut record = TaskRecord::new(Task {
+            children: children.iter().map(|c| (*c).to_owned()).collect(),
+            parents: parents.iter().map(|p| (*p).to_owned()).collect(),
+            ..Task::new()
+        });
+        record.state_changes.push(StateChange::new(state));
+        record
+    }
+
+    #[test]
+    fn test_run_graph() {
+        let records = HashMap::from([
+            (
+                "load.DATE:2022-01-01".to_owned(),
+                record(&["report"], &[], State::Completed),
+            ),
+            ("report".to_owned(), record(&[], &[], State::Running)),
+            // Generated tasks may only record their parent
+            (
+                "say \"hi\"".to_owned(),
+                record(&["missing"], &["report"], State::Queued),
+            ),
+        ]);
+        let graph = RunGraph::from_records(&records);
+
+        let ids: Vec<&str> = graph.vertices.iter().map(|v| v.task_id.as_str()).collect();
+        assert_eq!(ids, vec!["load.DATE:2022-01-01", "report", "say \"hi\""]);
+        assert_eq!(
+            graph.edges,
+            vec![
+                GraphEdge {
+                    parent: "load.DATE:2022-01-01".to_owned(),
+                    child: "report".to_owned()
+                },
+                GraphEdge {
+                    parent: "report".to_owned(),
+                    child: "say \"hi\"".to_owned()
+                },
+            ]
+        );
+
+        let dot = graph.to_dot("run 3", true);
+        assert!(dot.starts_with("digraph \"run 3\" {\n"));
+        assert!(dot.contains(
+            "  \"say \\\"hi\\\"\" [label=\"say \\\"hi\\\"\\nQueued\", fillcolor=\"#9e9e9e\""
+        ));
+        assert!(dot.contains("  \"report\" -> \"say \\\"hi\\\"\";\n"));
+        assert!(!graph.to_dot("run 3", false).contains("fillcolor"));
+
+        let mermaid = graph.to_mermaid(true);
+        assert!(mermaid.contains("  t2[\"say #quot;hi#quot; (Queued)\"]\n"));
+        assert!(mermaid.contains("  t0 --> t1\n  t1 --> t2\n"));
+        assert!(
+            mermaid.contains("  classDef Running fill:#2196f3,color:#ffffff\n  class t1 Running\n")
+        );
+        assert!(!graph.to_mermaid(false).contains("classDef"));
+    }
+}
@@ -6,11 +6,17 @@ extern crate anyhow;
 
 pub use anyhow::Result;
 
+pub mod client;
 pub mod dag;
 pub mod executors;
+pub mod graph;
 pub mod messages;
 pub mod prelude;
 pub mod runner;
+pub mod schedule;
+pub mod semaphores;
+pub mod sensors;
+pub mod specs;
 pub mod structs;
 pub mod trackers;
 pub mod utilities;
@@ -1,12 +1,13 @@
 //! Contains all of the messages passed between different components.
 
 use crate::structs::{
-    DateTime, Deserialize, ExpansionValues, HashMap, HashSet, Parameters, RunID, RunRecord,
-    RunSummary, RunTags, Serialize, State, StateChange, Task, TaskAttempt, TaskID, TaskRecord,
-    TaskSet, TaskSummary, Utc,
+    CacheSource, DateTime, Deserialize, ExpansionValues, HashMap, HashSet, Parameters, RunID,
+    RunPlan, RunRecord, RunSpec, RunSummary, RunTags, RunTemplate, Serialize, State, StateChange,
+    Task, TaskAttempt, TaskFilter, TaskID, TaskRecord, TaskSelection, TaskSet, TaskSummary,
+    TaskUpdate, Utc,
 };
 use crate::Result;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit};
 
 /// `TrackerMessage`s are used to interact with a Run State Tracker actor
 #[derive(Debug)]
@@ -57,6 +58,29 @@ pub enum TrackerMessage {
         response: oneshot::Sender<Result<()>>,
     },
 
+    /// Record a fully specified state change for the task identified by `task_id`
+    /// in the run identified by `run_id`. Used for changes made by an operator,
+    /// where the actor and reason should be kept.
+    /// Errors
+    ///   Will return an error if the tracker was unable to update the state
+    RecordTaskStateChange {
+        run_id: RunID,
+        task_id: TaskID,
+        change: StateChange,
+        response: oneshot::Sender<Result<()>>,
+    },
+
+    /// Link the run `subrun_id`, launched by the sub-run task `task_id`,
+    /// to the task's record in the run identified by `run_id`.
+    /// Errors
+    ///   Will return an error if the tracker was unable to update the task
+    LinkSubrun {
+        run_id: RunID,
+        task_id: TaskID,
+        subrun_id: RunID,
+        response: oneshot::Sender<Result<()>>,
+    },
+
     /// Record the execution attempt for the task `task_id` in run `run_id`.
     /// Errors
     ///   Will return an error if the tracker was unable to record the attempt.
@@ -137,6 +161,48 @@ pub enum TrackerMessage {
         response: oneshot::Sender<Result<TaskRecord>>,
     },
 
+    /// Find the most recent successful attempt recorded with `cache_key`, along
+    /// with the task it belongs to. Responds with `None` if there isn't one.
+    /// Errors
+    ///   Will return an `Err` if the back-end storage can't be queried.
+    GetCachedAttempt {
+        cache_key: String,
+        response: oneshot::Sender<Result<Option<(CacheSource, TaskAttempt)>>>,
+    },
+
+    /// Store a new version of the run template `name`, creating the template
+    /// if needed. Responds with the new version.
+    /// Errors
+    ///   Will return an `Err` if the back-end storage can't be updated.
+    StoreTemplate {
+        name: String,
+        spec: RunSpec,
+        response: oneshot::Sender<Result<usize>>,
+    },
+
+    /// Get a version of the run template `name`, or its latest version if
+    /// `version` is `None`.
+    /// Errors
+    ///   Will return an `Err` if the template or version doesn't exist.
+    GetTemplate {
+        name: String,
+        version: Option<usize>,
+        response: oneshot::Sender<Result<RunTemplate>>,
+    },
+
+    /// Get the latest version of every run template
+    GetTemplates {
+        response: oneshot::Sender<Result<Vec<RunTemplate>>>,
+    },
+
+    /// Delete every version of the run template `name`
+    /// Errors
+    ///   Will return an `Err` if the template doesn't exist.
+    DeleteTemplate {
+        name: String,
+        response: oneshot::Sender<Result<()>>,
+    },
+
     /// Stop a Tracker actor
     Stop {},
 }
@@ -146,27 +212,51 @@ pub enum TrackerMessage {
 pub enum RunnerMessage {
     /// Create a run with the given parameters, returning the RunID after the
     /// run has been validated and properly registered with the tracker.
+    /// After expansion, only the tasks selected by `filter` are kept.
     /// Errors
     ///    Will return Err if the tasks are invalid for the given executor, the
-    ///    tracker can't register the new run, or the run can't be enqueued.
+    ///    tracker can't register the new run, the filter selects no tasks, or
+    ///    the run can't be enqueued.
     Start {
         tags: RunTags,
         tasks: TaskSet,
         parameters: Parameters,
+        filter: TaskFilter,
         tracker: mpsc::UnboundedSender<TrackerMessage>,
         executor: mpsc::UnboundedSender<ExecutorMessage>,
         response: oneshot::Sender<Result<RunID>>,
     },
 
+    /// Expand and check the tasks of a run the same way `Start` would, without
+    /// registering or starting it. The expanded tasks are validated against
+    /// every executor in `pools`, and any problems are reported in the plan.
+    /// Errors
+    ///    Will return Err if the tasks can't be expanded, their dependencies are
+    ///    invalid, or the filter selects no tasks.
+    Plan {
+        tasks: TaskSet,
+        parameters: Parameters,
+        filter: TaskFilter,
+        tracker: mpsc::UnboundedSender<TrackerMessage>,
+        executor: mpsc::UnboundedSender<ExecutorMessage>,
+        pools: HashMap<String, mpsc::UnboundedSender<ExecutorMessage>>,
+        response: oneshot::Sender<Result<RunPlan>>,
+    },
+
     /// Re-queue the Run identified by `run_id`. The run will be loaded from the tracker,
     /// tasks not in the `State::Completed` state will be reset to `State::Queued`, and
     /// the run will be re-queued up for running.
-    /// 
+    ///
+    /// If `selection` is provided, only the selected tasks are reset to `State::Queued`,
+    /// including ones that have completed. All other tasks keep their current state.
+    ///
     /// Errors
     ///    Will return an `Err` if `run_id` is already running, if the tracker doesn't know
-    ///    of the `run_id`, or if the run couldn't be enqueued.
+    ///    of the `run_id`, if `selection` matches no tasks, or if the run couldn't be
+    ///    enqueued.
     Retry {
         run_id: RunID,
+        selection: Option<TaskSelection>,
         tracker: mpsc::UnboundedSender<TrackerMessage>,
         executor: mpsc::UnboundedSender<ExecutorMessage>,
         response: oneshot::Sender<Result<()>>,
@@ -179,12 +269,139 @@ pub enum RunnerMessage {
         attempt: TaskAttempt,
     },
 
+    /// Sent once a task has acquired the permits for all of its semaphores,
+    /// so it can be dispatched.
+    SemaphoresAcquired {
+        run_id: RunID,
+        task_id: TaskID,
+        permits: Vec<OwnedSemaphorePermit>,
+    },
+
+    /// Send the result of a sensor or sub-run task to the Runner, tagged with
+    /// the launch of the task its watcher was started for, so results from
+    /// watchers that were cancelled can be told apart
+    WatcherReport {
+        run_id: RunID,
+        task_id: TaskID,
+        launch: usize,
+        attempt: TaskAttempt,
+    },
+
+    /// Sent once the cache key of a task has been worked out, along with an
+    /// earlier successful attempt with the same key, if there is one.
+    CacheChecked {
+        run_id: RunID,
+        task_id: TaskID,
+        cache_key: String,
+        cached: Option<TaskAttempt>,
+    },
+
+    /// Sent once it's known whether the outputs of a task are newer than its
+    /// inputs, in which case the task is skipped.
+    FreshnessChecked {
+        run_id: RunID,
+        task_id: TaskID,
+        up_to_date: bool,
+    },
+
+    /// Sent once a successful attempt of a task that declares outputs has
+    /// been checked for them. The attempt has failed if any are missing.
+    OutputsChecked {
+        run_id: RunID,
+        task_id: TaskID,
+        attempt: TaskAttempt,
+    },
+
     /// Kill a run. Killing a run that isn't running is a noop.
     StopRun {
         run_id: RunID,
         response: oneshot::Sender<()>,
     },
 
+    /// Pause a running run. Tasks already dispatched to the executor are allowed
+    /// to finish and have their results recorded, but no new tasks will be
+    /// started until the run is resumed.
+    ///
+    /// Errors
+    ///    Will return an `Err` if `run_id` is not currently running.
+    Pause {
+        run_id: RunID,
+        response: oneshot::Sender<Result<()>>,
+    },
+
+    /// Resume a paused run, enqueuing any tasks that became ready while it was paused.
+    ///
+    /// Errors
+    ///    Will return an `Err` if `run_id` is not currently paused.
+    Resume {
+        run_id: RunID,
+        response: oneshot::Sender<Result<()>>,
+    },
+
+    /// Kill a single running task. The task will be recorded as `State::Killed`.
+    ///
+    /// Errors
+    ///    Will return an `Err` if the run isn't running, or the task isn't running.
+    KillTask {
+        run_id: RunID,
+        task_id: TaskID,
+        actor: Option<String>,
+        reason: Option<String>,
+        response: oneshot::Sender<Result<()>>,
+    },
+
+    /// Re-queue a single task, even if it has already completed. If `descendants`
+    /// is true, every task downstream of it is re-queued as well. If the run is not
+    /// currently running, it will be loaded from the tracker and re-queued, as
+    /// with `Retry`.
+    ///
+    /// Errors
+    ///    Will return an `Err` if any of the tasks to re-queue are currently running,
+    ///    or the run can't be loaded from the tracker.
+    RerunTask {
+        run_id: RunID,
+        task_id: TaskID,
+        descendants: bool,
+        actor: Option<String>,
+        reason: Option<String>,
+        tracker: mpsc::UnboundedSender<TrackerMessage>,
+        executor: mpsc::UnboundedSender<ExecutorMessage>,
+        response: oneshot::Sender<Result<()>>,
+    },
+
+    /// Force a task into `State::Completed` or `State::Skipped`, allowing
+    /// downstream tasks to proceed. A running task is killed first.
+    ///
+    /// Errors
+    ///    Will return an `Err` if `state` is not `Completed` or `Skipped`, or if
+    ///    the task doesn't exist.
+    MarkTask {
+        run_id: RunID,
+        task_id: TaskID,
+        state: State,
+        actor: Option<String>,
+        reason: Option<String>,
+        tracker: mpsc::UnboundedSender<TrackerMessage>,
+        response: oneshot::Sender<Result<()>>,
+    },
+
+    /// Edit the definition of a task in a run that isn't currently running. The new
+    /// details are validated by `executor`, and the run's dependencies are re-checked
+    /// before the tracker is updated. The next `Retry` of the run will use the new
+    /// definition.
+    ///
+    /// Errors
+    ///    Will return an `Err` if the run is currently running or paused, the new details
+    ///    are invalid, or the new dependencies refer to unknown tasks or create a cycle.
+    UpdateTask {
+        run_id: RunID,
+        task_id: TaskID,
+        update: TaskUpdate,
+        tracker: mpsc::UnboundedSender<TrackerMessage>,
+        executor: mpsc::UnboundedSender<ExecutorMessage>,
+        response: oneshot::Sender<Result<()>>,
+    },
+
     /// Stop the Runner actor
     Stop {},
 }
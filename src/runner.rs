@@ -1,11 +1,25 @@
 use super::Result;
 use crate::dag::DAG;
+use crate::executors::local_executor::expand_task_details_except;
 use crate::messages::{ExecutorMessage, RunnerMessage, TrackerMessage};
+use crate::semaphores::Semaphores;
+use crate::sensors;
 use crate::structs::{
-    Parameters, RunID, RunTags, State, Task, TaskAttempt, TaskDetails, TaskID, TaskSet, TaskType,
+    ExternalDependency, Parameters, RunID, RunPlan, RunTags, SensorCondition, SensorSpec, State,
+    StateChange, SubrunSpec, Task, TaskAttempt, TaskDetails, TaskFilter, TaskID, TaskOutputs,
+    TaskSelection, TaskSet, TaskType, TaskUpdate, SLOT_TAG,
 };
-use std::collections::HashMap;
-use tokio::sync::{mpsc, oneshot};
+use crate::utilities::{
+    apply_builtin_vars, apply_builtin_vars_to_details, glob_match, parse_output_markers,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit};
+use tokio::time::Duration;
 
 /// A Run comprises all of the runtime information for an
 /// executing task DAG.
@@ -14,36 +28,84 @@ struct Run {
     tasks: TaskSet,
     dag: DAG<TaskID>,
     state: State,
+    /// When the run was first started, used as the base for date macros
+    /// unless the run has a `SLOT_TAG`
+    start_time: DateTime<Utc>,
+    tags: RunTags,
     parameters: Parameters,
+    /// Outputs published by completed tasks
+    outputs: HashMap<TaskID, TaskOutputs>,
+    semaphores: Semaphores,
+    /// Semaphore permits held by running tasks
+    permits: HashMap<TaskID, Vec<OwnedSemaphorePermit>>,
+    /// Cache keys of cached tasks that are running or have completed
+    cache_keys: HashMap<TaskID, String>,
+    /// Tasks that are submitted without checking whether their outputs are
+    /// up to date, because they were just checked or were explicitly rerun
+    freshness_checked: HashSet<TaskID>,
+    /// Cancels the watchers of running sensor and sub-run tasks, along with
+    /// the launch each belongs to. Dropping one cancels its watcher.
+    watchers: HashMap<TaskID, (usize, oneshot::Sender<()>)>,
+    /// How the main DAG ended, set once its hooks have been started
+    outcome: Option<State>,
     tracker: mpsc::UnboundedSender<TrackerMessage>,
     executor: mpsc::UnboundedSender<ExecutorMessage>,
     runner: mpsc::UnboundedSender<RunnerMessage>,
 }
 
 impl Run {
-    async fn new(
-        tags: RunTags,
-        tasks: TaskSet,
+    /// A run that hasn't been registered with the tracker, and has no tasks
+    fn unregistered(
         parameters: Parameters,
         tracker: mpsc::UnboundedSender<TrackerMessage>,
         executor: mpsc::UnboundedSender<ExecutorMessage>,
         runner: mpsc::UnboundedSender<RunnerMessage>,
-    ) -> Result<Self> {
-        let mut run = Run {
+    ) -> Self {
+        Run {
             run_id: 0,
             tasks: TaskSet::new(),
             dag: DAG::new(),
             state: State::Queued,
+            start_time: Utc::now(),
+            tags: RunTags::new(),
             parameters,
-            tracker: tracker.clone(),
+            outputs: HashMap::new(),
+            semaphores: Semaphores::default(),
+            permits: HashMap::new(),
+            watchers: HashMap::new(),
+            cache_keys: HashMap::new(),
+            freshness_checked: HashSet::new(),
+            outcome: None,
+            tracker,
             executor,
             runner,
-        };
+        }
+    }
+
+    async fn new(
+        mut tags: RunTags,
+        tasks: TaskSet,
+        parameters: Parameters,
+        filter: &TaskFilter,
+        tracker: mpsc::UnboundedSender<TrackerMessage>,
+        executor: mpsc::UnboundedSender<ExecutorMessage>,
+        runner: mpsc::UnboundedSender<RunnerMessage>,
+    ) -> Result<Self> {
+        let mut run = Run::unregistered(parameters, tracker.clone(), executor, runner);
+
+        let expanded_tasks = run.select_tasks(tasks, filter).await?;
+        run.validate_tasks(&expanded_tasks).await?;
 
-        // Expand the tasks
-        let expanded_tasks = run.expand_tasks(tasks).await?;
+        // Record the selection with the run
+        if !filter.include.is_empty() {
+            tags.insert("daggyr.include".to_owned(), filter.include.join(","));
+        }
+        if !filter.exclude.is_empty() {
+            tags.insert("daggyr.exclude".to_owned(), filter.exclude.join(","));
+        }
 
         // Create the run ID and update the tracker
+        run.tags = tags.clone();
         let (tx, rx) = oneshot::channel();
         tracker
             .send(TrackerMessage::CreateRun {
@@ -79,7 +141,79 @@ impl Run {
         Ok(run)
     }
 
+    /// Expands the tasks, and prunes out anything not selected. Hooks are
+    /// always kept, and can only depend on each other.
+    async fn select_tasks(&self, tasks: TaskSet, filter: &TaskFilter) -> Result<TaskSet> {
+        let (hooks, tasks): (TaskSet, TaskSet) = self
+            .expand_tasks(tasks)
+            .await?
+            .into_iter()
+            .partition(|(_, task)| task.hook.is_some());
+        build_dag(&hooks)?;
+        let mut selected = filter_tasks(tasks, filter)?;
+        selected.extend(hooks);
+        Ok(selected)
+    }
+
+    /// Works out what the tasks would expand into, and checks that each of
+    /// `pools` could run them, without registering the run
+    async fn plan(
+        &self,
+        tasks: TaskSet,
+        filter: &TaskFilter,
+        pools: &HashMap<String, mpsc::UnboundedSender<ExecutorMessage>>,
+    ) -> Result<RunPlan> {
+        let tasks = self.select_tasks(tasks, filter).await?;
+        let main_tasks: TaskSet = tasks
+            .iter()
+            .filter(|(_, task)| task.hook.is_none())
+            .map(|(task_id, task)| (task_id.clone(), task.clone()))
+            .collect();
+        let dag = build_dag(&main_tasks)?;
+
+        let mut problems = HashMap::new();
+        for (pool, executor) in pools {
+            let pool_problems = validation_problems(executor, &tasks).await?;
+            if !pool_problems.is_empty() {
+                problems.insert(pool.clone(), pool_problems);
+            }
+        }
+
+        let mut roots = dag.roots();
+        roots.sort();
+        let mut leaves = dag.leaves();
+        leaves.sort();
+        Ok(RunPlan {
+            edges: dag.edge_count(),
+            roots,
+            leaves,
+            critical_path_length: dag.longest_path(|task_id| {
+                usize::from(tasks[task_id].task_type != TaskType::Structural)
+            }),
+            problems,
+            tasks,
+        })
+    }
+
+    /// Expands the tasks using the run's parameters, and checks the details
+    /// of the tasks the runner handles itself
     async fn expand_tasks(&self, tasks: TaskSet) -> Result<TaskSet> {
+        let tasks = add_external_sensors(tasks)?;
+        let expanded_tasks = self.expand_task_parameters(tasks).await?;
+        check_task_details(&expanded_tasks)?;
+        Ok(expanded_tasks)
+    }
+
+    /// Has the executor validate the details of the tasks it will run
+    async fn validate_tasks(&self, tasks: &TaskSet) -> Result<()> {
+        let problems = validation_problems(&self.executor, tasks).await?;
+        match problems.into_iter().next() {
+            Some(problem) => Err(anyhow!(problem)),
+            None => Ok(()),
+        }
+    }
+
+    async fn expand_task_parameters(&self, tasks: TaskSet) -> Result<TaskSet> {
         if self.parameters.is_empty() {
             return Ok(tasks);
         }
@@ -87,21 +221,32 @@ impl Run {
         for (task_id, mut task) in tasks {
             let mut task_parameters = self.parameters.clone();
             task_parameters.extend(task.parameters.clone().into_iter());
-            let (tx, rx) = oneshot::channel();
-            self.executor
-                .send(ExecutorMessage::ExpandTaskDetails {
-                    details: task.details.clone(),
-                    parameters: task_parameters,
-                    response: tx,
-                })
-                .unwrap();
-            let exp_tasks = rx.await??;
+            let exp_tasks = if task.task_type == TaskType::Subrun {
+                // The nested run's tasks are expanded with its own parameters
+                // once it starts
+                expand_task_details_except(
+                    task.details.clone(),
+                    &task_parameters,
+                    SubrunSpec::NESTED_FIELDS,
+                )?
+            } else {
+                let (tx, rx) = oneshot::channel();
+                self.executor
+                    .send(ExecutorMessage::ExpandTaskDetails {
+                        details: task.details.clone(),
+                        parameters: task_parameters,
+                        response: tx,
+                    })
+                    .unwrap();
+                rx.await??
+            };
 
             // If this is a simple task, add it directly
             if exp_tasks.len() == 1 {
                 let (details, exp_values) = exp_tasks.first().unwrap();
                 task.details = details.clone();
                 task.expansion_values = exp_values.clone();
+                expand_task_paths(&mut task);
                 expanded_tasks.insert(task_id, task);
             } else {
                 // Need to create a head and tail node
@@ -110,6 +255,7 @@ impl Run {
                 let mut head = Task::new();
                 head.task_type = TaskType::Structural;
                 head.parents = task.parents.clone();
+                head.hook = task.hook;
 
                 let head_id = task_id.clone();
 
@@ -117,6 +263,7 @@ impl Run {
                 let mut tail = Task::new();
                 tail.task_type = TaskType::Structural;
                 tail.children = task.children.clone();
+                tail.hook = task.hook;
                 let tail_id = format!("{}.tail", task_id);
                 expanded_tasks.insert(tail_id.clone(), tail);
 
@@ -129,11 +276,12 @@ impl Run {
                 };
 
                 for (details, expansion_values) in exp_tasks {
-                    let interior = Task {
+                    let mut interior = Task {
                         expansion_values,
                         details,
                         ..template.clone()
                     };
+                    expand_task_paths(&mut interior);
 
                     // Name are: {task_id}[.PARAM:VALUE]+
                     let name = format!(
@@ -155,13 +303,16 @@ impl Run {
                 expanded_tasks.insert(head_id, head);
             }
         }
+
         Ok(expanded_tasks)
     }
 
     /// Retrieve an existing run from the tracker and reset it
-    /// to get it ready to run
+    /// to get it ready to run. If `selection` is provided, only the
+    /// selected tasks are reset.
     async fn from_tracker(
         run_id: RunID,
+        selection: Option<&TaskSelection>,
         tracker: mpsc::UnboundedSender<TrackerMessage>,
         executor: mpsc::UnboundedSender<ExecutorMessage>,
         runner: mpsc::UnboundedSender<RunnerMessage>,
@@ -176,22 +327,37 @@ impl Run {
         let run_record = rx.await??;
 
         // Build the set of tasks and states
-        let mut tasks = TaskSet::new();
+        let tasks: TaskSet = run_record
+            .tasks
+            .iter()
+            .map(|(task_id, tr)| (task_id.clone(), tr.task.clone()))
+            .collect();
+
+        let selected = match selection {
+            Some(sel) => Some(select_subgraph(&tasks, sel)?),
+            None => None,
+        };
 
-        // States for previously run tasks are reset to queued
+        // States for previously run tasks are reset to queued. Only states that
+        // change are sent to the tracker.
         let mut states = HashMap::new();
 
+        let mut outputs = HashMap::new();
+        let mut cache_keys = HashMap::new();
         for (task_id, tr) in run_record.tasks {
-            tasks.insert(task_id.clone(), tr.task);
-            let new_state = match tr.state_changes.last() {
-                Some(change) => match change.state {
-                    State::Completed => State::Completed,
-                    _ => State::Queued,
-                },
-                None => State::Queued,
-            };
-
-            states.insert(task_id, new_state);
+            if !tr.outputs.is_empty() {
+                outputs.insert(task_id.clone(), tr.outputs);
+            }
+            let last_state = tr.state_changes.last().map(|change| change.state);
+            if last_state == Some(State::Completed) {
+                let last_success = tr.attempts.iter().rev().find(|attempt| attempt.succeeded);
+                if let Some(attempt) = last_success.filter(|a| !a.cache_key.is_empty()) {
+                    cache_keys.insert(task_id.clone(), attempt.cache_key.clone());
+                }
+            }
+            let (new_state, record) =
+                reset_state(&task_id, &tr.task, last_state, selected.as_ref());
+            states.insert(task_id, (new_state, record));
         }
 
         // Build the run
@@ -200,7 +366,19 @@ impl Run {
             tasks: TaskSet::new(),
             dag: DAG::new(),
             state: State::Running,
+            start_time: run_record
+                .state_changes
+                .first()
+                .map_or_else(Utc::now, |change| change.datetime),
+            tags: run_record.tags,
             parameters: run_record.parameters,
+            outputs,
+            semaphores: Semaphores::default(),
+            permits: HashMap::new(),
+            watchers: HashMap::new(),
+            cache_keys,
+            freshness_checked: selected.clone().unwrap_or_default(),
+            outcome: None,
             tracker,
             executor,
             runner,
@@ -210,8 +388,18 @@ impl Run {
 
         // Update the task states
         let mut responses = Vec::new();
-        for (task_id, state) in states {
-            run.dag.set_vertex_state(&task_id, state)?;
+        for (task_id, (state, record)) in states {
+            let dag_state = if state == State::Skipped {
+                State::Completed
+            } else {
+                state
+            };
+            if run.tasks[&task_id].hook.is_none() {
+                run.dag.set_vertex_state(&task_id, dag_state)?;
+            }
+            if !record {
+                continue;
+            }
             let (response, rx) = oneshot::channel();
             run.tracker
                 .send(TrackerMessage::UpdateTaskState {
@@ -223,6 +411,9 @@ impl Run {
                 .unwrap();
             responses.push(rx);
         }
+        // States were restored in no particular order, so work out what's
+        // ready from the final states
+        run.dag.refresh_ready();
         for rx in responses {
             rx.await??;
         }
@@ -245,14 +436,20 @@ impl Run {
         Ok(())
     }
 
-    /// Adds the tasks and sets up the DAG
+    /// Adds the tasks and sets up the DAG. Hook tasks are left out of the
+    /// DAG until the main tasks are done.
     fn add_tasks(&mut self, tasks: &TaskSet) -> Result<()> {
-        let task_ids: Vec<TaskID> = tasks.keys().cloned().collect();
+        let in_dag = |task: &Task| task.hook.is_none() || self.outcome.is_some();
+        let task_ids: Vec<TaskID> = tasks
+            .iter()
+            .filter(|(_, task)| in_dag(task))
+            .map(|(task_id, _)| task_id.clone())
+            .collect();
         // Add vertices
         self.dag.add_vertices(&task_ids)?;
 
         // Insert edges
-        for (task_id, task) in tasks.iter() {
+        for (task_id, task) in tasks.iter().filter(|(_, task)| in_dag(task)) {
             for child in &task.children {
                 self.dag.add_edge(task_id, child)?;
             }
@@ -272,7 +469,147 @@ impl Run {
         self.tasks.len()
     }
 
-    fn submit_task(&self, task_id: TaskID, details: TaskDetails) -> Result<()> {
+    /// Collects the outputs of all of a task's ancestors. Where ancestors
+    /// publish the same key, the one closest to the task wins.
+    fn task_inputs(&self, task_id: &TaskID) -> Result<TaskOutputs> {
+        // Most runs never publish outputs, so skip walking the ancestors
+        if self.outputs.is_empty() {
+            return Ok(TaskOutputs::new());
+        }
+        let mut publishers = Vec::new();
+        for ancestor in self.dag.ancestors(task_id)? {
+            if self.outputs.contains_key(&ancestor) {
+                // An ancestor always has fewer ancestors than its descendants
+                let depth = self.dag.ancestors(&ancestor)?.len();
+                publishers.push((depth, ancestor));
+            }
+        }
+        publishers.sort();
+
+        let mut inputs = TaskOutputs::new();
+        for (_, ancestor) in publishers {
+            inputs.extend(self.outputs[&ancestor].clone());
+        }
+        Ok(inputs)
+    }
+
+    /// The time date macros are relative to: the slot of a scheduled run,
+    /// or when the run was first started
+    fn date_base(&self) -> DateTime<Utc> {
+        self.tags
+            .get(SLOT_TAG)
+            .and_then(|slot| DateTime::parse_from_rfc3339(slot).ok())
+            .map_or(self.start_time, |slot| slot.with_timezone(&Utc))
+    }
+
+    /// The built-in template variables available to a task
+    fn builtin_vars(&self, task_id: &TaskID) -> HashMap<String, String> {
+        HashMap::from([
+            ("RUN_ID".to_owned(), self.run_id.to_string()),
+            ("TASK_ID".to_owned(), task_id.clone()),
+            (
+                "ATTEMPT".to_owned(),
+                (self.tasks[task_id].retries + 1).to_string(),
+            ),
+            (
+                "RUN_START_DATE".to_owned(),
+                self.start_time.format("%Y-%m-%d").to_string(),
+            ),
+            ("RUN_START_TIME".to_owned(), self.start_time.to_rfc3339()),
+        ])
+    }
+
+    /// The environment variables describing the run context of a task
+    fn task_environment(&self, task_id: &TaskID) -> Result<HashMap<String, String>> {
+        let task = &self.tasks[task_id];
+        let expansion_values: HashMap<&String, &String> =
+            task.expansion_values.iter().map(|(k, v)| (k, v)).collect();
+
+        let mut env = HashMap::from([
+            ("DAGGY_RUN_ID".to_owned(), self.run_id.to_string()),
+            ("DAGGY_TASK_ID".to_owned(), task_id.clone()),
+            ("DAGGY_ATTEMPT".to_owned(), (task.retries + 1).to_string()),
+            (
+                "DAGGY_RUN_TAGS".to_owned(),
+                serde_json::to_string(&self.tags)?,
+            ),
+            (
+                "DAGGY_EXPANSION_VALUES".to_owned(),
+                serde_json::to_string(&expansion_values)?,
+            ),
+        ]);
+
+        // Outputs of ancestors are passed along as DAGGY_INPUT_<KEY>
+        for (key, value) in self.task_inputs(task_id)? {
+            env.insert(format!("DAGGY_INPUT_{key}"), value);
+        }
+        Ok(env)
+    }
+
+    /// Gathers what the cache key of a task is computed from. Input files
+    /// are only read once the key is computed, away from the runner.
+    fn cache_key_source(&self, task_id: &TaskID, details: &TaskDetails) -> Result<CacheKeySource> {
+        let mut ancestors = self.dag.ancestors(task_id)?;
+        ancestors.sort();
+        let ancestor_keys = ancestors
+            .into_iter()
+            .filter_map(|ancestor| {
+                let key = self.cache_keys.get(&ancestor)?.clone();
+                Some((ancestor, key))
+            })
+            .collect();
+        Ok(CacheKeySource {
+            details: details.clone(),
+            input_paths: self.task_paths(task_id, &self.tasks[task_id].inputs),
+            ancestor_keys,
+            inputs: self.task_inputs(task_id)?.into_iter().collect(),
+        })
+    }
+
+    /// The details of a task, with its built-in template variables applied
+    fn task_details(&self, task_id: &TaskID) -> TaskDetails {
+        let mut details = self.tasks[task_id].details.clone();
+        apply_builtin_vars_to_details(&mut details, &self.builtin_vars(task_id), self.date_base());
+        details
+    }
+
+    /// Expands the built-in template variables in a task's declared paths
+    fn task_paths(&self, task_id: &TaskID, paths: &[String]) -> Vec<String> {
+        let vars = self.builtin_vars(task_id);
+        paths
+            .iter()
+            .map(|path| apply_builtin_vars(path, &vars, self.date_base()))
+            .collect()
+    }
+
+    /// Sends an attempt the runner didn't need the executor for back to
+    /// the runner
+    fn report_attempt(&self, task_id: TaskID, attempt: TaskAttempt) {
+        self.runner
+            .send(RunnerMessage::ExecutionReport {
+                run_id: self.run_id,
+                task_id,
+                attempt,
+            })
+            .unwrap_or(());
+    }
+
+    /// Submits a task to the executor, adding the run context and the
+    /// outputs of its ancestors to the task's environment. Variables set
+    /// explicitly in the task's environment take precedence.
+    fn submit_task(&self, task_id: TaskID, mut details: TaskDetails) -> Result<()> {
+        let task_env = self.task_environment(&task_id)?;
+        if let Some(obj) = details.as_object_mut() {
+            let env = obj
+                .entry("environment")
+                .or_insert_with(|| serde_json::json!({}));
+            if let Some(env) = env.as_object_mut() {
+                for (key, value) in task_env {
+                    env.entry(key)
+                        .or_insert_with(|| serde_json::Value::String(value));
+                }
+            }
+        }
         if let Err(e) = self.executor.send(ExecutorMessage::ExecuteTask {
             run_id: self.run_id,
             task_id,
@@ -286,6 +623,17 @@ impl Run {
         }
     }
 
+    async fn log_attempt(&self, task_id: &TaskID, attempt: TaskAttempt) -> Result<()> {
+        let (response, rx) = oneshot::channel();
+        self.tracker.send(TrackerMessage::LogTaskAttempt {
+            run_id: self.run_id,
+            task_id: task_id.clone(),
+            attempt,
+            response,
+        })?;
+        rx.await?
+    }
+
     async fn update_task_state(&self, task_id: TaskID, state: State) -> Result<()> {
         let (response, rx) = oneshot::channel();
         self.tracker.send(TrackerMessage::UpdateTaskState {
@@ -297,17 +645,155 @@ impl Run {
         rx.await?
     }
 
+    async fn record_task_state_change(&self, task_id: TaskID, change: StateChange) -> Result<()> {
+        let (response, rx) = oneshot::channel();
+        self.tracker.send(TrackerMessage::RecordTaskStateChange {
+            run_id: self.run_id,
+            task_id,
+            change,
+            response,
+        })?;
+        rx.await?
+    }
+
+    /// Asks the executor to stop a task. Any attempt it reports afterwards is
+    /// logged, but won't change the state of the task.
+    async fn stop_executor_task(&self, task_id: &TaskID) {
+        let (response, cancel_rx) = oneshot::channel();
+        self.executor
+            .send(ExecutorMessage::StopTask {
+                run_id: self.run_id,
+                task_id: task_id.clone(),
+                response,
+            })
+            .unwrap_or(());
+        cancel_rx.await.unwrap_or(());
+    }
+
+    /// Numbers a new launch of a sensor or sub-run task, returning the launch
+    /// and the receiver its watcher is cancelled through
+    fn watch(&mut self, task_id: &TaskID) -> (usize, oneshot::Receiver<()>) {
+        let launch = WATCHER_LAUNCH.fetch_add(1, Ordering::Relaxed);
+        let (cancel, cancelled) = oneshot::channel();
+        self.watchers.insert(task_id.clone(), (launch, cancel));
+        (launch, cancelled)
+    }
+
+    /// Stops a running task. Sensors and sub-runs are stopped by cancelling
+    /// their watcher, which stops the sub-run in turn, and anything else by
+    /// the executor.
+    async fn stop_running_task(&mut self, task_id: &TaskID) {
+        if self.watchers.remove(task_id).is_none() {
+            self.stop_executor_task(task_id).await;
+        }
+    }
+
+    async fn kill_task(
+        &mut self,
+        task_id: &TaskID,
+        actor: Option<String>,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let vertex = self
+            .dag
+            .get_vertex(task_id)
+            .ok_or_else(|| anyhow!("No task with ID {task_id:?}"))?;
+        if vertex.state != State::Running {
+            return Err(anyhow!("Task {task_id:?} is not running"));
+        }
+
+        self.stop_running_task(task_id).await;
+        self.permits.remove(task_id);
+        self.dag.set_vertex_state(task_id, State::Killed)?;
+        self.record_task_state_change(
+            task_id.clone(),
+            StateChange::with_actor(State::Killed, actor, reason),
+        )
+        .await
+    }
+
+    /// Forces a task into `Completed` or `Skipped`. Either state will
+    /// let downstream tasks proceed.
+    async fn mark_task(
+        &mut self,
+        task_id: &TaskID,
+        state: State,
+        actor: Option<String>,
+        reason: Option<String>,
+    ) -> Result<()> {
+        check_mark_state(state)?;
+        let vertex = self
+            .dag
+            .get_vertex(task_id)
+            .ok_or_else(|| anyhow!("No task with ID {task_id:?}"))?;
+
+        if vertex.state == State::Running {
+            self.stop_running_task(task_id).await;
+            self.permits.remove(task_id);
+        }
+        self.cache_keys.remove(task_id);
+        self.dag.set_vertex_state(task_id, State::Completed)?;
+        self.record_task_state_change(
+            task_id.clone(),
+            StateChange::with_actor(state, actor, reason),
+        )
+        .await
+    }
+
+    /// Re-queues a task, and optionally everything downstream of it.
+    async fn rerun_task(
+        &mut self,
+        task_id: &TaskID,
+        descendants: bool,
+        actor: Option<String>,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let mut targets = vec![task_id.clone()];
+        if descendants {
+            targets.extend(self.dag.descendants(task_id)?);
+        }
+
+        for target in &targets {
+            let vertex = self
+                .dag
+                .get_vertex(target)
+                .ok_or_else(|| anyhow!("No task with ID {target:?}"))?;
+            if vertex.state == State::Running {
+                return Err(anyhow!(
+                    "Task {target:?} is currently running, it must be killed before it can be rerun"
+                ));
+            }
+        }
+
+        self.dag.requeue_vertices(&targets)?;
+        for target in targets {
+            self.tasks.get_mut(&target).unwrap().retries = 0;
+            self.freshness_checked.insert(target.clone());
+            self.record_task_state_change(
+                target,
+                StateChange::with_actor(State::Queued, actor.clone(), reason.clone()),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
     /// Enqueues as many tasks as possible, returns end state
     pub async fn run(&mut self) -> Result<State> {
         if !(self.state == State::Queued || self.state == State::Running) {
             return Ok(self.state);
         }
-        if !self.dag.can_progress() {
-            self.state = if self.dag.is_complete() {
+        if !self.dag.can_progress() && self.outcome.is_none() {
+            let outcome = if self.dag.is_complete() {
                 State::Completed
             } else {
                 State::Errored
             };
+            self.start_hooks(outcome).await?;
+        }
+        if !self.dag.can_progress() {
+            // Hooks don't change how the run ended
+            self.state = self.outcome.unwrap_or(State::Errored);
 
             let (response, rx) = oneshot::channel();
             self.tracker
@@ -323,37 +809,280 @@ impl Run {
 
         // Enqueue as many tasks as possible
         while let Some(task_id) = self.dag.visit_next() {
-            let task = self.tasks.get(&task_id).unwrap();
-            match task.task_type {
-                TaskType::Normal => {
-                    self.submit_task(task_id.clone(), task.details.clone())?;
-                }
-                TaskType::Structural => {
-                    let mut attempt = TaskAttempt::new();
-                    attempt.succeeded = true;
-                    self.runner
-                        .send(RunnerMessage::ExecutionReport {
-                            run_id: self.run_id,
-                            task_id,
-                            attempt,
-                        })
-                        .unwrap_or(());
+            self.start_task(task_id).await?;
+        }
+        Ok(self.state)
+    }
+
+    /// Starts an attempt of the task, according to its type
+    async fn start_task(&mut self, task_id: TaskID) -> Result<()> {
+        let task = self.tasks.get(&task_id).unwrap();
+        if !task.semaphores.is_empty() && !self.permits.contains_key(&task_id) {
+            tokio::spawn(acquire_semaphores(
+                self.run_id,
+                task_id,
+                task.semaphores.clone(),
+                self.semaphores.clone(),
+                self.runner.clone(),
+            ));
+            return Ok(());
+        }
+        match task.task_type {
+            TaskType::Normal => {
+                // Retries always run, since a failed attempt may have
+                // written its outputs
+                if !task.outputs.is_empty()
+                    && task.retries == 0
+                    && !self.freshness_checked.contains(&task_id)
+                {
+                    tokio::spawn(check_freshness(
+                        self.run_id,
+                        task_id.clone(),
+                        self.task_paths(&task_id, &task.inputs),
+                        self.task_paths(&task_id, &task.outputs),
+                        self.runner.clone(),
+                    ));
+                    return Ok(());
                 }
+                let cache = task.cache;
+                let details = self.task_details(&task_id);
+                if cache {
+                    let source = self.cache_key_source(&task_id, &details)?;
+                    self.cache_keys.remove(&task_id);
+                    tokio::spawn(check_cache(
+                        self.run_id,
+                        task_id,
+                        source,
+                        self.tracker.clone(),
+                        self.runner.clone(),
+                    ));
+                    return Ok(());
+                }
+                self.submit_task(task_id, details)?;
+            }
+            TaskType::Structural => {
+                let mut attempt = TaskAttempt::new();
+                attempt.succeeded = true;
+                self.report_attempt(task_id, attempt);
+            }
+            TaskType::Subrun => {
+                let details = task.details.clone();
+                self.update_task_state(task_id.clone(), State::Running)
+                    .await?;
+                let (launch, cancelled) = self.watch(&task_id);
+                tokio::spawn(watch_subrun(
+                    self.run_id,
+                    task_id,
+                    launch,
+                    cancelled,
+                    details,
+                    self.runner.clone(),
+                    self.tracker.clone(),
+                    self.executor.clone(),
+                ));
+            }
+            TaskType::Sensor => {
+                let mut details = task.details.clone();
+                apply_builtin_vars_to_details(
+                    &mut details,
+                    &self.builtin_vars(&task_id),
+                    self.date_base(),
+                );
+                self.update_task_state(task_id.clone(), State::Running)
+                    .await?;
+                let (launch, cancelled) = self.watch(&task_id);
+                tokio::spawn(watch_sensor(
+                    self.run_id,
+                    task_id,
+                    launch,
+                    cancelled,
+                    details,
+                    self.runner.clone(),
+                    self.tracker.clone(),
+                ));
             }
         }
-        Ok(self.state)
+        Ok(())
+    }
+
+    /// Starts a task once it holds its semaphore permits. The permits are
+    /// dropped if the task was killed or marked while waiting for them.
+    async fn semaphores_acquired(
+        &mut self,
+        task_id: TaskID,
+        permits: Vec<OwnedSemaphorePermit>,
+    ) -> Result<()> {
+        let waiting = self
+            .dag
+            .get_vertex(&task_id)
+            .is_some_and(|vertex| vertex.state == State::Running);
+        if !waiting || self.permits.contains_key(&task_id) {
+            return Ok(());
+        }
+        self.permits.insert(task_id.clone(), permits);
+        self.start_task(task_id).await
+    }
+
+    /// Submits a task once its cache key is known, unless an earlier attempt
+    /// with the same key can be reused. The result is dropped if the task was
+    /// killed or marked, or its cache was already checked.
+    fn cache_checked(
+        &mut self,
+        task_id: TaskID,
+        cache_key: String,
+        cached: Option<TaskAttempt>,
+    ) -> Result<()> {
+        let waiting = self
+            .dag
+            .get_vertex(&task_id)
+            .is_some_and(|vertex| vertex.state == State::Running);
+        if !waiting || self.cache_keys.contains_key(&task_id) {
+            return Ok(());
+        }
+        self.cache_keys.insert(task_id.clone(), cache_key);
+        if let Some(attempt) = cached {
+            self.report_attempt(task_id, attempt);
+            return Ok(());
+        }
+        let details = self.task_details(&task_id);
+        self.submit_task(task_id, details)
+    }
+
+    /// Skips a task whose outputs are newer than its inputs, or starts it
+    /// otherwise. The result is dropped if the task was killed or marked, or
+    /// was already checked.
+    async fn freshness_checked(&mut self, task_id: TaskID, up_to_date: bool) -> Result<()> {
+        let waiting = self
+            .dag
+            .get_vertex(&task_id)
+            .is_some_and(|vertex| vertex.state == State::Running);
+        if !waiting || !self.freshness_checked.insert(task_id.clone()) {
+            return Ok(());
+        }
+        if !up_to_date {
+            return self.start_task(task_id).await;
+        }
+        self.permits.remove(&task_id);
+        self.dag.set_vertex_state(&task_id, State::Completed)?;
+        self.record_task_state_change(
+            task_id,
+            StateChange::with_actor(
+                State::Skipped,
+                None,
+                Some("Outputs are newer than inputs".to_owned()),
+            ),
+        )
+        .await
     }
 
-    pub async fn handle_generator(&mut self, task_id: TaskID, attempt: &TaskAttempt) -> Result<()> {
-        let tasks = serde_json::from_str::<TaskSet>(&attempt.output)?;
+    /// Returns the attempt if it can be completed right away. Successful
+    /// attempts of tasks that declare outputs are instead held until the
+    /// outputs are found, and come back as `RunnerMessage::OutputsChecked`.
+    fn check_outputs(&self, task_id: &TaskID, attempt: TaskAttempt) -> Option<TaskAttempt> {
+        let outputs = &self.tasks.get(task_id)?.outputs;
+        if !attempt.succeeded || outputs.is_empty() {
+            return Some(attempt);
+        }
+        tokio::spawn(check_outputs_exist(
+            self.run_id,
+            task_id.clone(),
+            self.task_paths(task_id, outputs),
+            attempt,
+            self.runner.clone(),
+        ));
+        None
+    }
+
+    /// Adds the tasks emitted by a generator to the run. Dependencies among
+    /// the generated tasks are kept, with the generated roots depending on
+    /// the generator, and the generator's children depending on the
+    /// generated leaves.
+    pub async fn handle_generator(&mut self, task_id: TaskID, output: &str) -> Result<()> {
+        let tasks = parse_generated_tasks(output)?;
+        self.semaphores.validate_tasks(&tasks)?;
+
+        for (gen_id, task) in &tasks {
+            if self.tasks.contains_key(gen_id) {
+                return Err(anyhow!("Generated task {gen_id} already exists in the run"));
+            }
+            for dep in task.parents.iter().chain(task.children.iter()) {
+                if !tasks.contains_key(dep) {
+                    return Err(anyhow!(
+                        "Generated task {gen_id} depends on unknown task {dep}"
+                    ));
+                }
+            }
+        }
+        build_dag(&tasks)?;
+
+        // Find the roots and leaves of the generated graph
+        let mut has_parents = HashSet::new();
+        let mut has_children = HashSet::new();
+        for (gen_id, task) in &tasks {
+            if !task.parents.is_empty() {
+                has_parents.insert(gen_id.clone());
+            }
+            if !task.children.is_empty() {
+                has_children.insert(gen_id.clone());
+            }
+            has_parents.extend(task.children.iter().cloned());
+            has_children.extend(task.parents.iter().cloned());
+        }
+        let roots: Vec<TaskID> = tasks
+            .keys()
+            .filter(|id| !has_parents.contains(*id))
+            .cloned()
+            .collect();
+        let leaves: Vec<TaskID> = tasks
+            .keys()
+            .filter(|id| !has_children.contains(*id))
+            .cloned()
+            .collect();
 
         let mut exp_tasks = self.expand_tasks(tasks).await?;
-        let gen_task = self.tasks.get(&task_id).unwrap();
-        let children = gen_task.children.clone();
-        let parents = vec![task_id.clone()];
+        self.validate_tasks(&exp_tasks).await?;
+
+        // Generated tasks usually list their parents, so a generated task
+        // that depends on an expanded one waits on its tail, not its head
+        let tails: HashSet<TaskID> = exp_tasks
+            .iter()
+            .filter(|(_, task)| task.task_type == TaskType::Structural)
+            .map(|(gen_id, _)| format!("{gen_id}.tail"))
+            .filter(|tail_id| exp_tasks.contains_key(tail_id))
+            .collect();
+        for task in exp_tasks.values_mut() {
+            for parent in &mut task.parents {
+                let tail_id = format!("{parent}.tail");
+                if tails.contains(&tail_id) {
+                    *parent = tail_id;
+                }
+            }
+        }
+        let hook = self.tasks[&task_id].hook;
         for task in exp_tasks.values_mut() {
-            task.children = children.clone();
-            task.parents = parents.clone();
+            task.hook = hook;
+        }
+        let children = self.tasks[&task_id].children.clone();
+        for root in roots {
+            exp_tasks
+                .get_mut(&root)
+                .unwrap()
+                .parents
+                .push(task_id.clone());
+        }
+        for leaf in leaves {
+            // Expanded tasks feed their children through the tail
+            let tail_id = format!("{leaf}.tail");
+            let leaf_id = if exp_tasks.contains_key(&tail_id) {
+                tail_id
+            } else {
+                leaf
+            };
+            exp_tasks
+                .get_mut(&leaf_id)
+                .unwrap()
+                .children
+                .extend(children.iter().cloned());
         }
 
         // Set the parent and children for each task
@@ -372,22 +1101,74 @@ impl Run {
         Ok(())
     }
 
-    async fn stop(&mut self) -> Result<()> {
+    /// Replaces the finished main DAG with one made of the hooks that run
+    /// after `outcome`. The other hooks are recorded as skipped.
+    async fn start_hooks(&mut self, outcome: State) -> Result<()> {
+        self.outcome = Some(outcome);
+        let mut hooks = TaskSet::new();
+        let mut skipped = Vec::new();
+        for (task_id, task) in &self.tasks {
+            match task.hook {
+                Some(hook) if hook.runs_after(outcome) => {
+                    hooks.insert(task_id.clone(), task.clone());
+                }
+                Some(_) => skipped.push(task_id.clone()),
+                None => {}
+            }
+        }
+        // Hooks only depend on each other, so they're added alongside the
+        // main tasks, which operators can still act on while hooks run
+        build_dag(&hooks)?;
+        self.add_tasks(&hooks)?;
+        for task_id in skipped {
+            self.update_task_state(task_id, State::Skipped).await?;
+        }
+        Ok(())
+    }
+
+    /// Kills any running tasks, then starts the run's hooks if they haven't
+    /// run yet. Returns the state of the run, which is `Running` while the
+    /// hooks are running.
+    async fn stop(&mut self) -> Result<State> {
+        let mut killed = Vec::new();
         for vertex in &self.dag.vertices {
             if vertex.state == State::Running {
-                let (response, cancel_rx) = oneshot::channel();
-                self.executor
-                    .send(ExecutorMessage::StopTask {
-                        run_id: self.run_id,
-                        task_id: vertex.id.clone(),
-                        response,
-                    })
-                    .unwrap();
-                cancel_rx.await.unwrap_or(());
+                killed.push(vertex.id.clone());
+                if self.watchers.remove(&vertex.id).is_none() {
+                    let (response, cancel_rx) = oneshot::channel();
+                    self.executor
+                        .send(ExecutorMessage::StopTask {
+                            run_id: self.run_id,
+                            task_id: vertex.id.clone(),
+                            response,
+                        })
+                        .unwrap();
+                    cancel_rx.await.unwrap_or(());
+                }
                 self.update_task_state(vertex.id.clone(), State::Killed)
                     .await?;
             }
         }
+        self.permits.clear();
+        // Tasks that were ready to start never will
+        while let Some(task_id) = self.dag.visit_next() {
+            killed.push(task_id);
+        }
+        for task_id in killed {
+            self.dag.set_vertex_state(&task_id, State::Killed)?;
+        }
+
+        if self.outcome.is_none() {
+            self.start_hooks(State::Killed).await?;
+            if self.dag.can_progress() {
+                if self.state != State::Running {
+                    self.update_state(State::Running).await?;
+                }
+                return self.run().await;
+            }
+        }
+
+        self.state = State::Killed;
         let (response, rx) = oneshot::channel();
         self.tracker
             .send(TrackerMessage::UpdateState {
@@ -397,7 +1178,28 @@ impl Run {
             })
             .unwrap();
         rx.await??;
-        Ok(())
+        Ok(State::Killed)
+    }
+
+    /// Stops new tasks from being enqueued. Tasks currently running are
+    /// left alone, and their results will be recorded as normal.
+    async fn pause(&mut self) -> Result<()> {
+        if self.state != State::Running {
+            return Err(anyhow!(
+                "Run {} is in state {:?}, cannot pause",
+                self.run_id,
+                self.state
+            ));
+        }
+        self.update_state(State::Paused).await
+    }
+
+    /// Allows tasks to be enqueued again after a `pause`
+    async fn resume(&mut self) -> Result<()> {
+        if self.state != State::Paused {
+            return Err(anyhow!("Run {} is not paused", self.run_id));
+        }
+        self.update_state(State::Running).await
     }
 
     async fn handle_killed_task(&mut self, task_id: TaskID) -> Result<()> {
@@ -411,17 +1213,52 @@ impl Run {
         Ok(())
     }
 
-    async fn complete_task(&mut self, task_id: &TaskID, attempt: TaskAttempt) -> Result<()> {
-        let (response, rx) = oneshot::channel();
-        self.tracker
-            .send(TrackerMessage::LogTaskAttempt {
-                run_id: self.run_id,
-                task_id: task_id.clone(),
-                attempt: attempt.clone(),
-                response,
-            })
-            .unwrap();
-        rx.await??;
+    /// Completes a sensor or sub-run task with the attempt its watcher
+    /// reported. Attempts from watchers that were cancelled, e.g. because the
+    /// task was killed and has since been rerun, are only logged.
+    async fn watcher_finished(
+        &mut self,
+        task_id: &TaskID,
+        launch: usize,
+        attempt: TaskAttempt,
+    ) -> Result<()> {
+        let current = self
+            .watchers
+            .get(task_id)
+            .is_some_and(|(current, _)| *current == launch);
+        if !current {
+            return self.log_attempt(task_id, attempt).await;
+        }
+        self.watchers.remove(task_id);
+        self.complete_task(task_id, attempt).await
+    }
+
+    async fn complete_task(&mut self, task_id: &TaskID, mut attempt: TaskAttempt) -> Result<()> {
+        self.permits.remove(task_id);
+        self.freshness_checked.remove(task_id);
+
+        if attempt.succeeded {
+            if let Some(cache_key) = self.cache_keys.get(task_id) {
+                attempt.cache_key.clone_from(cache_key);
+            }
+        } else {
+            self.cache_keys.remove(task_id);
+        }
+
+        // Outputs written to the output file take precedence over stdout markers
+        let mut outputs = parse_output_markers(&attempt.output);
+        outputs.extend(attempt.outputs.drain());
+        attempt.outputs = outputs;
+
+        // Generators can write their tasks to a file instead of stdout
+        let generated_tasks = std::mem::take(&mut attempt.generated_tasks);
+
+        self.log_attempt(task_id, attempt.clone()).await?;
+
+        // The task was killed or marked by an operator while it was running
+        if self.dag.get_vertex(task_id).map(|v| v.state) != Some(State::Running) {
+            return Ok(());
+        }
 
         let mut new_state = if attempt.succeeded {
             State::Completed
@@ -432,31 +1269,40 @@ impl Run {
         };
 
         if new_state == State::Completed && self.tasks[task_id].is_generator {
-            if let Err(e) = self.handle_generator(task_id.clone(), &attempt).await {
+            let output = if generated_tasks.is_empty() {
+                &attempt.output
+            } else {
+                &generated_tasks
+            };
+            if let Err(e) = self.handle_generator(task_id.clone(), output).await {
                 new_state = State::Errored;
                 let mut generator_attempt = TaskAttempt::new();
                 generator_attempt.executor.push(format!("{:?}", e));
-                let (response, rx) = oneshot::channel();
-                self.tracker
-                    .send(TrackerMessage::LogTaskAttempt {
-                        run_id: self.run_id,
-                        task_id: task_id.clone(),
-                        attempt: generator_attempt,
-                        response,
-                    })
-                    .unwrap();
-                rx.await??;
+                self.log_attempt(task_id, generator_attempt).await?;
             }
         }
 
-        // Update the state
+        if new_state == State::Completed {
+            self.outputs
+                .insert(task_id.clone(), attempt.outputs.clone());
+        }
+
+        // Update the state
         self.update_task_state(task_id.clone(), new_state).await?;
 
         // TODO implement retry logic here
         let task = self.tasks.get(task_id).unwrap().clone();
         if new_state == State::Errored && task.retries < task.max_retries {
             self.tasks.get_mut(task_id).unwrap().retries += 1;
-            self.submit_task(task_id.clone(), task.details.clone())?;
+            if self.state == State::Paused {
+                // Hold the retry until the run is resumed
+                self.dag.complete_visit(task_id, true)?;
+                self.dag.set_vertex_state(task_id, State::Queued)?;
+                self.update_task_state(task_id.clone(), State::Queued)
+                    .await?;
+            } else {
+                self.start_task(task_id.clone()).await?;
+            }
         } else {
             self.dag
                 .complete_visit(task_id, new_state != State::Completed)?;
@@ -465,130 +1311,3716 @@ impl Run {
     }
 }
 
+/// Numbers the launches of sensor and sub-run watchers across every run, as
+/// a run reloaded from the tracker can still get reports from the watchers of
+/// the run it replaced
+static WATCHER_LAUNCH: AtomicUsize = AtomicUsize::new(0);
+
+/// How often a sub-run task checks on the run it launched
+const SUBRUN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Fetches a stored template for a sub-run task, tagging the spec with the
+/// template's name and version
+async fn load_subrun_template(
+    name: &str,
+    version: Option<usize>,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+) -> Result<(SubrunSpec, TaskFilter)> {
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::GetTemplate {
+        name: name.to_owned(),
+        version,
+        response,
+    })?;
+    let template = rx
+        .await?
+        .map_err(|e| anyhow!("Unable to fetch sub-run template {name}: {e}"))?;
+
+    let mut tags = template.spec.tags;
+    tags.insert("daggyr.template".to_owned(), template.name);
+    tags.insert(
+        "daggyr.template_version".to_owned(),
+        template.version.to_string(),
+    );
+    let spec = SubrunSpec {
+        tags,
+        tasks: template.spec.tasks,
+        parameters: template.spec.parameters,
+        hooks: template.spec.hooks,
+        ..SubrunSpec::default()
+    };
+    let filter = TaskFilter {
+        include: template.spec.include,
+        exclude: template.spec.exclude,
+    };
+    Ok((spec, filter))
+}
+
+/// Reads the spec of the run a sub-run task launches, along with the filter
+/// of a stored template. Tags and parameters given in the details are applied
+/// over those from a spec file or template.
+async fn load_subrun_spec(
+    details: &TaskDetails,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+) -> Result<(SubrunSpec, TaskFilter)> {
+    let spec: SubrunSpec = serde_json::from_value(details.clone())?;
+    spec.validate()?;
+
+    let (mut loaded, filter) = if let Some(name) = &spec.template {
+        load_subrun_template(name, spec.template_version, tracker).await?
+    } else if let Some(path) = &spec.file {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow!("Unable to read sub-run spec {path}: {e}"))?;
+        let file_spec: SubrunSpec = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid sub-run spec {path}: {e}"))?;
+        if file_spec.file.is_some() || file_spec.template.is_some() {
+            return Err(anyhow!(
+                "Sub-run spec {path} cannot refer to another file or template"
+            ));
+        }
+        (file_spec, TaskFilter::default())
+    } else {
+        return Ok((spec, TaskFilter::default()));
+    };
+
+    loaded
+        .tags
+        .extend(spec.tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+    loaded.parameters.extend(spec.parameters);
+    Ok((loaded, filter))
+}
+
+async fn get_run_state(
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+    run_id: RunID,
+) -> Result<State> {
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::GetState { run_id, response })?;
+    Ok(rx.await??.state)
+}
+
+/// Launches the run for a sub-run task, and waits for it to finish. If the
+/// watcher is cancelled in the meantime, the sub-run is stopped.
+#[allow(clippy::too_many_arguments)]
+async fn run_subrun(
+    run_id: RunID,
+    task_id: &TaskID,
+    cancelled: &mut oneshot::Receiver<()>,
+    details: TaskDetails,
+    runner: &mpsc::UnboundedSender<RunnerMessage>,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+    executor: mpsc::UnboundedSender<ExecutorMessage>,
+    attempt: &mut TaskAttempt,
+) -> Result<()> {
+    let (spec, filter) = load_subrun_spec(&details, tracker).await?;
+    let mut tags = spec.tags;
+    tags.insert("daggyr.parent_run".to_owned(), run_id.to_string());
+    tags.insert("daggyr.parent_task".to_owned(), task_id.clone());
+    let mut tasks = spec.tasks;
+    spec.hooks.add_to(&mut tasks)?;
+
+    let (response, rx) = oneshot::channel();
+    runner.send(RunnerMessage::Start {
+        tags,
+        tasks,
+        parameters: spec.parameters,
+        filter,
+        tracker: tracker.clone(),
+        executor,
+        response,
+    })?;
+    let subrun_id = rx.await??;
+    attempt
+        .executor
+        .push(format!("Launched sub-run {subrun_id}"));
+
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::LinkSubrun {
+        run_id,
+        task_id: task_id.clone(),
+        subrun_id,
+        response,
+    })?;
+    rx.await??;
+
+    loop {
+        tokio::select! {
+            () = tokio::time::sleep(SUBRUN_POLL_INTERVAL) => {}
+            _ = &mut *cancelled => {
+                let (response, rx) = oneshot::channel();
+                runner.send(RunnerMessage::StopRun {
+                    run_id: subrun_id,
+                    response,
+                })?;
+                rx.await?;
+                attempt.killed = true;
+                attempt
+                    .executor
+                    .push(format!("Sub-run {subrun_id} was stopped"));
+                return Ok(());
+            }
+        }
+
+        let state = get_run_state(tracker, subrun_id).await?;
+        if matches!(state, State::Completed | State::Errored | State::Killed) {
+            attempt.succeeded = state == State::Completed;
+            attempt.killed = state == State::Killed;
+            attempt
+                .executor
+                .push(format!("Sub-run {subrun_id} finished in state {state:?}"));
+            return Ok(());
+        }
+    }
+}
+
+/// Runs a sub-run task, reporting the outcome of the launch back to the
+/// runner
+#[allow(clippy::too_many_arguments)]
+async fn watch_subrun(
+    run_id: RunID,
+    task_id: TaskID,
+    launch: usize,
+    mut cancelled: oneshot::Receiver<()>,
+    details: TaskDetails,
+    runner: mpsc::UnboundedSender<RunnerMessage>,
+    tracker: mpsc::UnboundedSender<TrackerMessage>,
+    executor: mpsc::UnboundedSender<ExecutorMessage>,
+) {
+    let mut attempt = TaskAttempt::new();
+    if let Err(e) = run_subrun(
+        run_id,
+        &task_id,
+        &mut cancelled,
+        details,
+        &runner,
+        &tracker,
+        executor,
+        &mut attempt,
+    )
+    .await
+    {
+        attempt.succeeded = false;
+        attempt.executor.push(format!("{e:?}"));
+    }
+    attempt.stop_time = Utc::now();
+    runner
+        .send(RunnerMessage::WatcherReport {
+            run_id,
+            task_id,
+            launch,
+            attempt,
+        })
+        .unwrap_or(());
+}
+
+/// Applies a task's expansion values to its declared input and output paths
+fn expand_task_paths(task: &mut Task) {
+    for path in task.inputs.iter_mut().chain(task.outputs.iter_mut()) {
+        *path = task
+            .expansion_values
+            .iter()
+            .fold(path.clone(), |p, (var, val)| p.replace(var, val));
+    }
+}
+
+/// Works out whether a task's outputs are up to date, and tells the runner
+async fn check_freshness(
+    run_id: RunID,
+    task_id: TaskID,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    runner: mpsc::UnboundedSender<RunnerMessage>,
+) {
+    let up_to_date = outputs_up_to_date(&inputs, &outputs).await;
+    runner
+        .send(RunnerMessage::FreshnessChecked {
+            run_id,
+            task_id,
+            up_to_date,
+        })
+        .unwrap_or(());
+}
+
+/// Fails a successful attempt if the task didn't produce the files it
+/// declares, and hands it back to the runner
+async fn check_outputs_exist(
+    run_id: RunID,
+    task_id: TaskID,
+    outputs: Vec<String>,
+    mut attempt: TaskAttempt,
+    runner: mpsc::UnboundedSender<RunnerMessage>,
+) {
+    let mut missing = Vec::new();
+    for path in outputs {
+        if tokio::fs::metadata(&path).await.is_err() {
+            missing.push(path);
+        }
+    }
+    if !missing.is_empty() {
+        attempt.succeeded = false;
+        attempt.executor.push(format!(
+            "Task did not produce its outputs: {}",
+            missing.join(", ")
+        ));
+    }
+    runner
+        .send(RunnerMessage::OutputsChecked {
+            run_id,
+            task_id,
+            attempt,
+        })
+        .unwrap_or(());
+}
+
+/// Returns true if a task declares outputs, and they all exist and are
+/// newer than every input, in which case the task doesn't need to run
+async fn outputs_up_to_date(inputs: &[String], outputs: &[String]) -> bool {
+    async fn modified(path: &str) -> Option<std::time::SystemTime> {
+        tokio::fs::metadata(path).await.ok()?.modified().ok()
+    }
+
+    let mut output_times = Vec::with_capacity(outputs.len());
+    for path in outputs {
+        match modified(path).await {
+            Some(time) => output_times.push(time),
+            None => return false,
+        }
+    }
+    let Some(oldest_output) = output_times.into_iter().min() else {
+        return false;
+    };
+
+    for path in inputs {
+        // Missing inputs are left for the task to deal with
+        match modified(path).await {
+            Some(time) if time <= oldest_output => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Waits for a task's semaphore permits, and hands them to the runner
+async fn acquire_semaphores(
+    run_id: RunID,
+    task_id: TaskID,
+    names: Vec<String>,
+    semaphores: Semaphores,
+    runner: mpsc::UnboundedSender<RunnerMessage>,
+) {
+    let msg = match semaphores.acquire(&task_id, &names).await {
+        Ok(permits) => RunnerMessage::SemaphoresAcquired {
+            run_id,
+            task_id,
+            permits,
+        },
+        Err(e) => {
+            let mut attempt = TaskAttempt::new();
+            attempt.executor.push(format!("{e:?}"));
+            RunnerMessage::ExecutionReport {
+                run_id,
+                task_id,
+                attempt,
+            }
+        }
+    };
+    runner.send(msg).unwrap_or(());
+}
+
+/// What the cache key of a task is computed from
+struct CacheKeySource {
+    details: TaskDetails,
+    input_paths: Vec<String>,
+    /// Cache keys of the task's ancestors, sorted by task ID
+    ancestor_keys: Vec<(TaskID, String)>,
+    inputs: BTreeMap<String, String>,
+}
+
+impl CacheKeySource {
+    /// Computes the cache key of a task from its expanded details, the
+    /// contents of its input files, and the cache keys and outputs of its
+    /// ancestors.
+    async fn cache_key(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_vec(&self.details)?);
+        for path in &self.input_paths {
+            let contents = tokio::fs::read(path)
+                .await
+                .map_err(|e| anyhow!("Unable to read input {path}: {e}"))?;
+            hasher.update(path.as_bytes());
+            hasher.update(Sha256::digest(contents));
+        }
+        for (ancestor, key) in &self.ancestor_keys {
+            hasher.update(ancestor.as_bytes());
+            hasher.update(key.as_bytes());
+        }
+        hasher.update(serde_json::to_vec(&self.inputs)?);
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// Looks for an earlier successful attempt with the same cache key
+async fn get_cached_attempt(
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+    cache_key: String,
+) -> Result<Option<TaskAttempt>> {
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::GetCachedAttempt {
+        cache_key,
+        response,
+    })?;
+    Ok(rx.await??.map(|(source, mut attempt)| {
+        attempt.start_time = Utc::now();
+        attempt.stop_time = attempt.start_time;
+        attempt.executor = vec![format!(
+            "Reused the results of task {} in run {}",
+            source.task_id, source.run_id
+        )];
+        attempt.cached_from = Some(source);
+        attempt
+    }))
+}
+
+/// Works out the cache key of a task, looks for an earlier attempt to reuse,
+/// and hands both to the runner
+async fn check_cache(
+    run_id: RunID,
+    task_id: TaskID,
+    source: CacheKeySource,
+    tracker: mpsc::UnboundedSender<TrackerMessage>,
+    runner: mpsc::UnboundedSender<RunnerMessage>,
+) {
+    let checked = match source.cache_key().await {
+        Ok(cache_key) => get_cached_attempt(&tracker, cache_key.clone())
+            .await
+            .map(|cached| (cache_key, cached)),
+        Err(e) => Err(e),
+    };
+    let msg = match checked {
+        Ok((cache_key, cached)) => RunnerMessage::CacheChecked {
+            run_id,
+            task_id,
+            cache_key,
+            cached,
+        },
+        Err(e) => {
+            let mut attempt = TaskAttempt::new();
+            attempt.executor.push(format!("{e:?}"));
+            RunnerMessage::ExecutionReport {
+                run_id,
+                task_id,
+                attempt,
+            }
+        }
+    };
+    runner.send(msg).unwrap_or(());
+}
+
+/// How often a dependency on a task in another run is checked
+const EXTERNAL_POKE_INTERVAL: u64 = 30;
+
+/// Replaces the external parents of each task with `Sensor` tasks that wait
+/// on them, named `{task_id}.wait.{n}`
+fn add_external_sensors(mut tasks: TaskSet) -> Result<TaskSet> {
+    let mut sensors = TaskSet::new();
+    for (task_id, task) in &mut tasks {
+        for (i, dependency) in std::mem::take(&mut task.external_parents)
+            .into_iter()
+            .enumerate()
+        {
+            let sensor_id = format!("{task_id}.wait.{i}");
+            let spec = SensorSpec {
+                timeout_seconds: dependency.timeout_seconds,
+                condition: SensorCondition::Task(ExternalDependency {
+                    timeout_seconds: 0,
+                    ..dependency
+                }),
+                poke_interval_seconds: EXTERNAL_POKE_INTERVAL,
+            };
+            let mut sensor = Task::new();
+            sensor.task_type = TaskType::Sensor;
+            sensor.details = serde_json::to_value(spec)?;
+            sensor.hook = task.hook;
+            task.parents.push(sensor_id.clone());
+            sensors.insert(sensor_id, sensor);
+        }
+    }
+    for (sensor_id, sensor) in sensors {
+        if tasks.contains_key(&sensor_id) {
+            return Err(anyhow!(
+                "Task {sensor_id} conflicts with an external dependency"
+            ));
+        }
+        tasks.insert(sensor_id, sensor);
+    }
+    Ok(tasks)
+}
+
+/// Checks a sensor's condition until it holds, the sensor times out, or its
+/// watcher is cancelled. Nothing is held on the executor in between.
+async fn run_sensor(
+    cancelled: &mut oneshot::Receiver<()>,
+    details: TaskDetails,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+    attempt: &mut TaskAttempt,
+) -> Result<()> {
+    let spec: SensorSpec = serde_json::from_value(details)?;
+    let interval = Duration::from_secs(spec.poke_interval_seconds);
+    let deadline = (spec.timeout_seconds > 0)
+        .then(|| tokio::time::Instant::now() + Duration::from_secs(spec.timeout_seconds));
+
+    loop {
+        if sensors::poke(&spec.condition, tracker).await? {
+            attempt.succeeded = true;
+            return Ok(());
+        }
+        let mut wait = interval;
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                return Err(anyhow!(
+                    "Sensor timed out after {} seconds",
+                    spec.timeout_seconds
+                ));
+            }
+            wait = wait.min(remaining);
+        }
+        tokio::select! {
+            () = tokio::time::sleep(wait) => {}
+            _ = &mut *cancelled => {
+                attempt.killed = true;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Runs a sensor task, reporting the outcome of the launch back to the
+/// runner
+async fn watch_sensor(
+    run_id: RunID,
+    task_id: TaskID,
+    launch: usize,
+    mut cancelled: oneshot::Receiver<()>,
+    details: TaskDetails,
+    runner: mpsc::UnboundedSender<RunnerMessage>,
+    tracker: mpsc::UnboundedSender<TrackerMessage>,
+) {
+    let mut attempt = TaskAttempt::new();
+    if let Err(e) = run_sensor(&mut cancelled, details, &tracker, &mut attempt).await {
+        attempt.succeeded = false;
+        attempt.executor.push(format!("{e:?}"));
+    }
+    attempt.stop_time = Utc::now();
+    runner
+        .send(RunnerMessage::WatcherReport {
+            run_id,
+            task_id,
+            launch,
+            attempt,
+        })
+        .unwrap_or(());
+}
+
+/// A single task in NDJSON generator output
+#[derive(Deserialize)]
+struct GeneratedTask {
+    task_id: TaskID,
+    #[serde(flatten)]
+    task: Task,
+}
+
+/// Parses the tasks emitted by a generator. These are either a JSON
+/// `TaskSet`, or NDJSON with one task per line, identified by `task_id`.
+fn parse_generated_tasks(output: &str) -> Result<TaskSet> {
+    let json_err = match serde_json::from_str::<TaskSet>(output) {
+        Ok(tasks) => return Ok(tasks),
+        Err(e) => e,
+    };
+
+    let mut tasks = TaskSet::new();
+    for (lineno, line) in output.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let generated: GeneratedTask = serde_json::from_str(line).map_err(|e| {
+            anyhow!(
+                "Generator output is neither a JSON task set ({json_err}), \
+                 nor NDJSON (line {}: {e})",
+                lineno + 1
+            )
+        })?;
+        if tasks.contains_key(&generated.task_id) {
+            return Err(anyhow!(
+                "Generated task {} is defined more than once",
+                generated.task_id
+            ));
+        }
+        tasks.insert(generated.task_id, generated.task);
+    }
+    if tasks.is_empty() {
+        return Err(anyhow!("Invalid generator output: {json_err}"));
+    }
+    Ok(tasks)
+}
+
+fn check_mark_state(state: State) -> Result<()> {
+    match state {
+        State::Completed | State::Skipped => Ok(()),
+        _ => Err(anyhow!(
+            "Tasks can only be marked Completed or Skipped, not {state:?}"
+        )),
+    }
+}
+
+/// Marks a task belonging to a run that isn't currently running. The
+/// change will be picked up the next time the run is retried.
+async fn mark_inactive_task(
+    run_id: RunID,
+    task_id: TaskID,
+    change: StateChange,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+) -> Result<()> {
+    check_mark_state(change.state)?;
+
+    // Ensure the task exists
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::GetTask {
+        run_id,
+        task_id: task_id.clone(),
+        response,
+    })?;
+    rx.await??;
+
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::RecordTaskStateChange {
+        run_id,
+        task_id,
+        change,
+        response,
+    })?;
+    rx.await?
+}
+
+/// Checks the details of the tasks the runner handles itself
+fn check_task_details(tasks: &TaskSet) -> Result<()> {
+    for (task_id, task) in tasks {
+        if task.task_type == TaskType::Subrun {
+            serde_json::from_value::<SubrunSpec>(task.details.clone())
+                .map_err(|e| anyhow!(e))
+                .and_then(|spec| spec.validate())
+                .map_err(|e| anyhow!("Invalid details for task {task_id}: {e}"))?;
+        }
+        if task.cache && (task.is_generator || task.task_type != TaskType::Normal) {
+            return Err(anyhow!(
+                "Task {task_id} cannot be cached, only normal tasks that aren't generators can"
+            ));
+        }
+        if task.task_type == TaskType::Sensor {
+            serde_json::from_value::<SensorSpec>(task.details.clone())
+                .map_err(|e| anyhow!(e))
+                .and_then(|spec| spec.validate())
+                .map_err(|e| anyhow!("Invalid details for task {task_id}: {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Has `executor` validate the details of the normal tasks, returning a
+/// description of each one it rejects
+async fn validation_problems(
+    executor: &mpsc::UnboundedSender<ExecutorMessage>,
+    tasks: &TaskSet,
+) -> Result<Vec<String>> {
+    let mut responses = Vec::new();
+    for (task_id, task) in tasks {
+        if task.task_type != TaskType::Normal {
+            continue;
+        }
+        let (response, rx) = oneshot::channel();
+        executor.send(ExecutorMessage::ValidateTask {
+            details: task.details.clone(),
+            response,
+        })?;
+        responses.push((task_id, rx));
+    }
+    let mut problems = Vec::new();
+    for (task_id, rx) in responses {
+        if let Err(e) = rx.await? {
+            problems.push(format!("Invalid details for task {task_id}: {e}"));
+        }
+    }
+    problems.sort();
+    Ok(problems)
+}
+
+/// Builds the dependency graph for `tasks`, ensuring that every dependency
+/// refers to a known task, and that the dependencies don't contain any cycles.
+fn build_dag(tasks: &TaskSet) -> Result<DAG<TaskID>> {
+    let mut dag = DAG::new();
+    let task_ids: Vec<TaskID> = tasks.keys().cloned().collect();
+    dag.add_vertices(&task_ids)?;
+
+    for (task_id, task) in tasks {
+        for child in &task.children {
+            dag.add_edge(task_id, child)
+                .map_err(|e| anyhow!("Invalid dependency {task_id:?} -> {child:?}: {e}"))?;
+        }
+        for parent in &task.parents {
+            dag.add_edge(parent, task_id)
+                .map_err(|e| anyhow!("Invalid dependency {parent:?} -> {task_id:?}: {e}"))?;
+        }
+    }
+    Ok(dag)
+}
+
+/// Returns the state a task is reset to when a run is retried, and whether
+/// the reset should be recorded. Unselected tasks keep their final states,
+/// and hooks always run again once the main tasks are done.
+fn reset_state(
+    task_id: &TaskID,
+    task: &Task,
+    last_state: Option<State>,
+    selected: Option<&HashSet<TaskID>>,
+) -> (State, bool) {
+    let new_state = match selected {
+        _ if task.hook.is_some() => State::Queued,
+        None => match last_state {
+            Some(State::Completed | State::Skipped) => last_state.unwrap(),
+            _ => State::Queued,
+        },
+        Some(selected) if selected.contains(task_id) => State::Queued,
+        Some(_) => match last_state {
+            Some(State::Completed | State::Skipped | State::Errored | State::Killed) => {
+                last_state.unwrap()
+            }
+            _ => State::Queued,
+        },
+    };
+    let record = task.hook.is_some() || selected.is_none_or(|selected| selected.contains(task_id));
+    (new_state, record)
+}
+
+fn matches_any(patterns: &[String], task_id: &str) -> bool {
+    patterns.iter().any(|pattern| glob_match(pattern, task_id))
+}
+
+/// Returns the IDs of the tasks selected by `selection`
+fn select_subgraph(tasks: &TaskSet, selection: &TaskSelection) -> Result<HashSet<TaskID>> {
+    let dag = build_dag(tasks)?;
+    let matched: Vec<TaskID> = tasks
+        .keys()
+        .filter(|task_id| matches_any(&selection.patterns, task_id))
+        .cloned()
+        .collect();
+    if matched.is_empty() {
+        return Err(anyhow!(
+            "No tasks match the patterns {:?}",
+            selection.patterns
+        ));
+    }
+
+    let mut selected: HashSet<TaskID> = matched.iter().cloned().collect();
+    for task_id in &matched {
+        if selection.upstream {
+            selected.extend(dag.ancestors(task_id)?);
+        }
+        if selection.downstream {
+            selected.extend(dag.descendants(task_id)?);
+        }
+    }
+    Ok(selected)
+}
+
+/// Prunes an expanded set of tasks down to those selected by `filter`. The
+/// structural head and tail of an expanded task are treated as the whole
+/// expanded task, so selecting either one selects all of it.
+fn filter_tasks(mut tasks: TaskSet, filter: &TaskFilter) -> Result<TaskSet> {
+    if filter.is_empty() {
+        return Ok(tasks);
+    }
+
+    // All the tasks making up each expanded task, keyed by the head and tail
+    let mut groups: HashMap<TaskID, Vec<TaskID>> = HashMap::new();
+    for (task_id, task) in &tasks {
+        let tail_id = format!("{task_id}.tail");
+        if task.task_type == TaskType::Structural && tasks.contains_key(&tail_id) {
+            let mut members = task.children.clone();
+            members.push(task_id.clone());
+            members.push(tail_id.clone());
+            groups.insert(task_id.clone(), members.clone());
+            groups.insert(tail_id, members);
+        }
+    }
+    let matching = |patterns: &[String]| {
+        let mut ids: HashSet<TaskID> = HashSet::new();
+        for task_id in tasks
+            .keys()
+            .filter(|task_id| matches_any(patterns, task_id))
+        {
+            match groups.get(task_id) {
+                Some(members) => ids.extend(members.iter().cloned()),
+                None => {
+                    ids.insert(task_id.clone());
+                }
+            }
+        }
+        ids
+    };
+
+    let mut keep: HashSet<TaskID> = if filter.include.is_empty() {
+        tasks.keys().cloned().collect()
+    } else {
+        let dag = build_dag(&tasks)?;
+        let included = matching(&filter.include);
+        let mut keep = included.clone();
+        for task_id in &included {
+            keep.extend(dag.ancestors(task_id)?);
+        }
+        keep
+    };
+    let excluded = matching(&filter.exclude);
+    keep.retain(|task_id| !excluded.contains(task_id));
+
+    if keep.is_empty() {
+        return Err(anyhow!("No tasks were selected to run"));
+    }
+
+    // Dropped tasks are bypassed, so their kept descendants still wait on
+    // their kept ancestors
+    let mut children: HashMap<&TaskID, HashSet<&TaskID>> = HashMap::new();
+    for (task_id, task) in &tasks {
+        for child in task.children.iter().filter(|id| tasks.contains_key(*id)) {
+            children.entry(task_id).or_default().insert(child);
+        }
+        for parent in task.parents.iter().filter(|id| tasks.contains_key(*id)) {
+            children.entry(parent).or_default().insert(task_id);
+        }
+    }
+    let mut bridges: HashMap<TaskID, Vec<TaskID>> = HashMap::new();
+    for task_id in &keep {
+        let mut stack: Vec<&TaskID> = children
+            .get(task_id)
+            .into_iter()
+            .flatten()
+            .filter(|child| !keep.contains(**child))
+            .copied()
+            .collect();
+        let mut seen = HashSet::new();
+        while let Some(dropped) = stack.pop() {
+            if !seen.insert(dropped) {
+                continue;
+            }
+            for child in children.get(dropped).into_iter().flatten() {
+                if keep.contains(*child) {
+                    bridges
+                        .entry(task_id.clone())
+                        .or_default()
+                        .push((*child).clone());
+                } else {
+                    stack.push(child);
+                }
+            }
+        }
+    }
+
+    tasks.retain(|task_id, _| keep.contains(task_id));
+    for (task_id, task) in &mut tasks {
+        task.parents.retain(|task_id| keep.contains(task_id));
+        task.children.retain(|task_id| keep.contains(task_id));
+        for child in bridges.remove(task_id).unwrap_or_default() {
+            if !task.children.contains(&child) {
+                task.children.push(child);
+            }
+        }
+    }
+    Ok(tasks)
+}
+
+/// Edits the definition of a task belonging to a run that isn't currently
+/// running.
+async fn update_inactive_task(
+    run_id: RunID,
+    task_id: TaskID,
+    update: &TaskUpdate,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+    executor: &mpsc::UnboundedSender<ExecutorMessage>,
+) -> Result<()> {
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::GetTasks { run_id, response })?;
+    let mut tasks: TaskSet = rx
+        .await??
+        .into_iter()
+        .map(|(task_id, record)| (task_id, record.task))
+        .collect();
+
+    let task = tasks
+        .get_mut(&task_id)
+        .ok_or_else(|| anyhow!("No task with ID {task_id:?}"))?;
+    update.apply(task);
+    let task = task.clone();
+
+    if update.details.is_some() {
+        check_task_details(&TaskSet::from([(task_id.clone(), task.clone())]))?;
+        if task.task_type == TaskType::Normal {
+            let (response, rx) = oneshot::channel();
+            executor.send(ExecutorMessage::ValidateTask {
+                details: task.details.clone(),
+                response,
+            })?;
+            rx.await??;
+        }
+    }
+
+    // Edges can be recorded on either end, so the other end of each edge
+    // is made to match the new parents and children
+    let mut changed = vec![task_id.clone()];
+    for (other_id, other) in &mut tasks {
+        if *other_id == task_id {
+            continue;
+        }
+        let mut edited = false;
+        if let Some(parents) = &update.parents {
+            edited |= set_edge(&mut other.children, &task_id, parents.contains(other_id));
+        }
+        if let Some(children) = &update.children {
+            edited |= set_edge(&mut other.parents, &task_id, children.contains(other_id));
+        }
+        if edited {
+            changed.push(other_id.clone());
+        }
+    }
+    if update.parents.is_some() || update.children.is_some() {
+        build_dag(&tasks)?;
+    }
+
+    let mut responses = Vec::new();
+    for changed_id in changed {
+        let (response, rx) = oneshot::channel();
+        tracker.send(TrackerMessage::UpdateTask {
+            run_id,
+            task: tasks[&changed_id].clone(),
+            task_id: changed_id,
+            response,
+        })?;
+        responses.push(rx);
+    }
+    for rx in responses {
+        rx.await??;
+    }
+    Ok(())
+}
+
+/// Adds `task_id` to `edges` if `present`, or removes it otherwise,
+/// returning true if `edges` changed
+fn set_edge(edges: &mut Vec<TaskID>, task_id: &TaskID, present: bool) -> bool {
+    let found = edges.contains(task_id);
+    if present && !found {
+        edges.push(task_id.clone());
+    } else if !present && found {
+        edges.retain(|edge| edge != task_id);
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Enqueues whatever tasks are ready in an active run, and drops the
+/// run once it has finished.
+async fn advance_run(runs: &mut HashMap<RunID, Run>, run_id: RunID) {
+    if let Some(run) = runs.get_mut(&run_id) {
+        // TODO NOT SURE ABOUT THIS
+        if let Ok(state) = run.run().await {
+            if state != State::Running && state != State::Paused {
+                runs.remove(&run_id);
+            }
+        }
+    }
+}
+
 pub fn start(
     msg_tx: mpsc::UnboundedSender<RunnerMessage>,
     msg_rx: mpsc::UnboundedReceiver<RunnerMessage>,
 ) {
+    start_with_semaphores(&HashMap::new(), msg_tx, msg_rx);
+}
+
+/// Starts a runner with named semaphores, given as the number of tasks that
+/// can hold each at once
+pub fn start_with_semaphores<S: BuildHasher>(
+    limits: &HashMap<String, usize, S>,
+    msg_tx: mpsc::UnboundedSender<RunnerMessage>,
+    msg_rx: mpsc::UnboundedReceiver<RunnerMessage>,
+) {
+    let semaphores = Semaphores::new(limits);
     tokio::spawn(async move {
-        start_dag_runner(msg_tx, msg_rx).await;
+        start_dag_runner(semaphores, msg_tx, msg_rx).await;
     });
 }
 
-async fn start_dag_runner(
-    msg_tx: mpsc::UnboundedSender<RunnerMessage>,
-    mut msg_rx: mpsc::UnboundedReceiver<RunnerMessage>,
-) {
-    let mut runs = HashMap::<RunID, Run>::new();
+/// Gives a run that's being started the server-wide semaphores, and queues
+/// its ready tasks. The run is returned if it's still running.
+async fn enqueue_run(mut run: Run, semaphores: &Semaphores) -> Result<Run> {
+    run.semaphores = semaphores.clone();
+    match run.run().await {
+        Ok(State::Running) => Ok(run),
+        Ok(state) => Err(anyhow!("Run in state {state:?} after enqueuing")),
+        Err(e) => Err(anyhow!("Error enqueing run: {e:?}")),
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+async fn start_dag_runner(
+    semaphores: Semaphores,
+    msg_tx: mpsc::UnboundedSender<RunnerMessage>,
+    mut msg_rx: mpsc::UnboundedReceiver<RunnerMessage>,
+) {
+    let mut runs = HashMap::<RunID, Run>::new();
+
+    while let Some(msg) = msg_rx.recv().await {
+        use RunnerMessage::{
+            CacheChecked, ExecutionReport, FreshnessChecked, KillTask, MarkTask, OutputsChecked,
+            Pause, Plan, RerunTask, Resume, Retry, SemaphoresAcquired, Start, Stop, StopRun,
+            UpdateTask, WatcherReport,
+        };
+        match msg {
+            Start {
+                tags,
+                tasks,
+                response,
+                parameters,
+                filter,
+                tracker,
+                executor,
+            } => {
+                // Queue all pending tasks
+                let result = match semaphores.validate_tasks(&tasks) {
+                    Ok(()) => {
+                        Run::new(
+                            tags,
+                            tasks,
+                            parameters,
+                            &filter,
+                            tracker,
+                            executor,
+                            msg_tx.clone(),
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                let result = match result {
+                    Ok(run) => enqueue_run(run, &semaphores).await.map(|run| {
+                        let run_id = run.run_id;
+                        runs.insert(run_id, run);
+                        run_id
+                    }),
+                    Err(e) => Err(e),
+                };
+                response.send(result).unwrap_or(());
+            }
+            Plan {
+                tasks,
+                parameters,
+                filter,
+                tracker,
+                executor,
+                pools,
+                response,
+            } => {
+                if let Err(e) = semaphores.validate_tasks(&tasks) {
+                    response.send(Err(e)).unwrap_or(());
+                    continue;
+                }
+                // Nothing is registered, so the plan can be worked out alongside
+                // other messages
+                let run = Run::unregistered(parameters, tracker, executor, msg_tx.clone());
+                tokio::spawn(async move {
+                    let plan = run.plan(tasks, &filter, &pools).await;
+                    response.send(plan).unwrap_or(());
+                });
+            }
+            StopRun { run_id, response } => match runs.get_mut(&run_id) {
+                Some(run) => {
+                    // Keep the run around while its hooks run
+                    if !matches!(run.stop().await, Ok(State::Running)) {
+                        runs.remove(&run_id);
+                    }
+                    response.send(()).unwrap_or(());
+                }
+                None => {
+                    response.send(()).unwrap_or(());
+                }
+            },
+            Retry {
+                run_id,
+                selection,
+                tracker,
+                executor,
+                response,
+            } => {
+                //if let std::collections::hash_map::Entry::Vacant(e) = runs.entry(run_id) {
+                use std::collections::hash_map::Entry::{Occupied, Vacant};
+                let result = match runs.entry(run_id) {
+                    Occupied(_) => Err(anyhow!("Run ID is currently running, cannot retry.")),
+                    Vacant(e) => {
+                        match Run::from_tracker(
+                            run_id,
+                            selection.as_ref(),
+                            tracker,
+                            executor,
+                            msg_tx.clone(),
+                        )
+                        .await
+                        {
+                            Ok(run) => enqueue_run(run, &semaphores).await.map(|run| {
+                                e.insert(run);
+                            }),
+                            Err(e) => Err(e),
+                        }
+                    }
+                };
+                response.send(result).unwrap_or(());
+            }
+            Pause { run_id, response } => {
+                let result = match runs.get_mut(&run_id) {
+                    Some(run) => run.pause().await,
+                    None => Err(anyhow!("Run {run_id} is not currently running")),
+                };
+                response.send(result).unwrap_or(());
+            }
+            Resume { run_id, response } => {
+                let result = match runs.get_mut(&run_id) {
+                    Some(run) => match run.resume().await {
+                        Ok(()) => match run.run().await {
+                            Ok(State::Running) => Ok(()),
+                            Ok(_) => {
+                                runs.remove(&run_id);
+                                Ok(())
+                            }
+                            Err(e) => Err(anyhow!("Error enqueing run: {e:?}")),
+                        },
+                        Err(e) => Err(e),
+                    },
+                    None => Err(anyhow!("Run {run_id} is not currently running")),
+                };
+                response.send(result).unwrap_or(());
+            }
+            KillTask {
+                run_id,
+                task_id,
+                actor,
+                reason,
+                response,
+            } => {
+                let result = match runs.get_mut(&run_id) {
+                    Some(run) => run.kill_task(&task_id, actor, reason).await,
+                    None => Err(anyhow!("Run {run_id} is not currently running")),
+                };
+                if result.is_ok() {
+                    advance_run(&mut runs, run_id).await;
+                }
+                response.send(result).unwrap_or(());
+            }
+            MarkTask {
+                run_id,
+                task_id,
+                state,
+                actor,
+                reason,
+                tracker,
+                response,
+            } => {
+                let result = if let Some(run) = runs.get_mut(&run_id) {
+                    run.mark_task(&task_id, state, actor, reason).await
+                } else {
+                    let change = StateChange::with_actor(state, actor, reason);
+                    mark_inactive_task(run_id, task_id, change, &tracker).await
+                };
+                if result.is_ok() {
+                    advance_run(&mut runs, run_id).await;
+                }
+                response.send(result).unwrap_or(());
+            }
+            RerunTask {
+                run_id,
+                task_id,
+                descendants,
+                actor,
+                reason,
+                tracker,
+                executor,
+                response,
+            } => {
+                use std::collections::hash_map::Entry::{Occupied, Vacant};
+                let result = match runs.entry(run_id) {
+                    Occupied(mut e) => {
+                        let run = e.get_mut();
+                        match run.rerun_task(&task_id, descendants, actor, reason).await {
+                            Ok(()) => run.run().await.map(|_| ()),
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Vacant(e) => {
+                        match Run::from_tracker(run_id, None, tracker, executor, msg_tx.clone())
+                            .await
+                        {
+                            Ok(mut run) => {
+                                match run.rerun_task(&task_id, descendants, actor, reason).await {
+                                    Ok(()) => enqueue_run(run, &semaphores).await.map(|run| {
+                                        e.insert(run);
+                                    }),
+                                    Err(e) => Err(e),
+                                }
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                };
+                response.send(result).unwrap_or(());
+            }
+            UpdateTask {
+                run_id,
+                task_id,
+                update,
+                tracker,
+                executor,
+                response,
+            } => {
+                let result = if runs.contains_key(&run_id) {
+                    Err(anyhow!(
+                        "Run {run_id} is currently running, it must be stopped before editing"
+                    ))
+                } else {
+                    update_inactive_task(run_id, task_id, &update, &tracker, &executor).await
+                };
+                response.send(result).unwrap_or(());
+            }
+            ExecutionReport {
+                run_id,
+                task_id,
+                attempt,
+            } => {
+                if let Some(run) = runs.get_mut(&run_id) {
+                    if let Some(attempt) = run.check_outputs(&task_id, attempt) {
+                        run.complete_task(&task_id, attempt).await.unwrap_or(());
+                        advance_run(&mut runs, run_id).await;
+                    }
+                }
+            }
+            OutputsChecked {
+                run_id,
+                task_id,
+                attempt,
+            } => {
+                if let Some(run) = runs.get_mut(&run_id) {
+                    run.complete_task(&task_id, attempt).await.unwrap_or(());
+                    advance_run(&mut runs, run_id).await;
+                }
+            }
+            SemaphoresAcquired {
+                run_id,
+                task_id,
+                permits,
+            } => {
+                if let Some(run) = runs.get_mut(&run_id) {
+                    run.semaphores_acquired(task_id, permits)
+                        .await
+                        .unwrap_or(());
+                }
+            }
+            WatcherReport {
+                run_id,
+                task_id,
+                launch,
+                attempt,
+            } => {
+                if let Some(run) = runs.get_mut(&run_id) {
+                    run.watcher_finished(&task_id, launch, attempt)
+                        .await
+                        .unwrap_or(());
+                    advance_run(&mut runs, run_id).await;
+                }
+            }
+            CacheChecked {
+                run_id,
+                task_id,
+                cache_key,
+                cached,
+            } => {
+                if let Some(run) = runs.get_mut(&run_id) {
+                    run.cache_checked(task_id, cache_key, cached).unwrap_or(());
+                }
+            }
+            FreshnessChecked {
+                run_id,
+                task_id,
+                up_to_date,
+            } => {
+                if let Some(run) = runs.get_mut(&run_id) {
+                    run.freshness_checked(task_id, up_to_date)
+                        .await
+                        .unwrap_or(());
+                    advance_run(&mut runs, run_id).await;
+                }
+            }
+            Stop {} => {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executors::local_executor;
+    use crate::executors::noop_executor;
+    use crate::structs::{HookType, RunHooks};
+    use crate::trackers::memory_tracker;
+
+    async fn run(
+        tasks: &TaskSet,
+        parameters: &Parameters,
+    ) -> (RunID, mpsc::UnboundedSender<TrackerMessage>) {
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(10, exe_rx);
+
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        let rtx = run_tx.clone();
+        super::start(rtx, run_rx);
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Start {
+                tags: RunTags::new(),
+                tasks: tasks.clone(),
+                response: tx,
+                parameters: parameters.clone(),
+                filter: TaskFilter::default(),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+            })
+            .unwrap();
+
+        let run_id = rx.await.unwrap().unwrap();
+
+        // Need some way to get
+        loop {
+            let (tx, rx) = oneshot::channel();
+
+            log_tx
+                .send(TrackerMessage::GetState {
+                    run_id,
+                    response: tx,
+                })
+                .unwrap();
+            let state_change = rx.await.unwrap().unwrap();
+
+            if state_change.state == State::Completed || state_change.state == State::Errored {
+                break;
+            }
+
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+
+        // Close off everything except the tracker
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+
+        (run_id, log_tx)
+    }
+
+    #[tokio::test]
+    async fn test_simple_dag_run() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "simple_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "hello", "world" ],
+                        "env": {
+                            "ENVVAR": 16
+                        }
+                    },
+                    "children": [ "other_task" ]
+                },
+                "other_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "task", "$ENVVAR" ],
+                        "env": {
+                            "ENVVAR": 16
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let parameters = HashMap::new();
+
+        let (run_id, log_tx) = run(&tasks, &parameters).await;
+
+        for (task_id, task) in tasks {
+            let (tx, rx) = oneshot::channel();
+            log_tx
+                .send(TrackerMessage::GetTask {
+                    run_id,
+                    task_id: task_id.clone(),
+                    response: tx,
+                })
+                .unwrap();
+
+            let task_record = rx.await.unwrap().unwrap();
+            assert_eq!(task, task_record.task);
+            assert_eq!(task_record.attempts.len(), 1);
+            assert_eq!(
+                task_record.state_changes.last().unwrap().state,
+                State::Completed
+            );
+        }
+
+        // Close off tracker
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dag_with_expansion() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "A": {
+                    "details": {
+                        "command": [ "/bin/echo", "A" ]
+                    },
+                    "children": [ "B" ]
+                },
+                "B": {
+                    "details": {
+                        "command": [ "/bin/echo", "B", "DATE" ]
+                    },
+                    "children": [ "C" ]
+                },
+                "C": {
+                    "details": {
+                        "command": [ "/bin/echo", "NAME" ]
+                    },
+                    "parameters": {
+                        "NAME": [ "ABCD", "DEFG" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let parameters: Parameters = serde_json::from_str(
+            r#"{
+                "DATE": [ "20200101", "20200102", "20200103" ]
+                }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx) = run(&tasks, &parameters).await;
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+
+        let rec = rx.await.unwrap().unwrap();
+        assert_eq!(rec.parameters, parameters);
+        assert_eq!(
+            rec.tasks.len(),
+            1 + parameters["DATE"].len() + 2 + tasks["C"].parameters["NAME"].len() + 2
+        ); // 5 actual tasks, 2 control tasks
+        assert_eq!(rec.state_changes.last().unwrap().state, State::Completed);
+
+        // Close off tracker
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failing_generating_dag_run() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"{
+                "simple_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "hello", "world" ],
+                        "env": {
+                            "ENVVAR": 16
+                        }
+                    },
+                    "children": [ "other_task" ],
+                    "is_generator": true
+                },
+                "other_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "task", "$ENVVAR" ],
+                        "env": {
+                            "ENVVAR": 16
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let parameters = HashMap::new();
+
+        let (run_id, log_tx) = run(&tasks, &parameters).await;
+
+        for (task_id, task) in tasks {
+            let (tx, rx) = oneshot::channel();
+            log_tx
+                .send(TrackerMessage::GetTask {
+                    run_id,
+                    task_id: task_id.clone(),
+                    response: tx,
+                })
+                .unwrap();
+
+            let task_record = rx.await.unwrap().unwrap();
+            assert_eq!(task, task_record.task);
+
+            match task_id.as_ref() {
+                "simple_task" => {
+                    assert_eq!(task_record.state_changes.len(), 3);
+                    assert_eq!(
+                        task_record.state_changes.last().unwrap().state,
+                        State::Errored
+                    );
+                }
+                "other_task" => {
+                    assert_eq!(task_record.state_changes.len(), 1);
+                    assert_eq!(
+                        task_record.state_changes.last().unwrap().state,
+                        State::Queued
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_successful_generating_dag_run() {
+        use serde_json::json;
+
+        let mut tasks: TaskSet = serde_json::from_str(
+            r#"{
+                "other_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "task", "$ENVVAR" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let new_task = r#"{
+            "generated_task": {
+                "details": {
+                    "command": [ "/bin/echo", "hello", "world" ]
+                }
+            }
+        }"#;
+
+        let parameters = HashMap::new();
+
+        let task_id = "fancy_generator".to_owned();
+
+        let mut gen_task = Task::new();
+        gen_task.is_generator = true;
+        gen_task.children.push("other_task".to_owned());
+        gen_task.details = json!({
+            "command": [ "/bin/echo", new_task ]
+        });
+
+        tasks.insert(task_id, gen_task);
+
+        // This will run the tasks and process the generators
+        let (run_id, log_tx) = run(&tasks, &parameters).await;
+
+        // Make tasks match the expected output
+        let mut new_tasks: TaskSet = serde_json::from_str(new_task).unwrap();
+        for (task_id, task) in &mut new_tasks {
+            task.children.push("other_task".to_owned());
+            task.parents.push("fancy_generator".to_owned());
+            tasks.insert(task_id.clone(), task.clone());
+        }
+        assert_eq!(tasks.len(), 3);
+
+        for (task_id, task) in tasks {
+            let (tx, rx) = oneshot::channel();
+            log_tx
+                .send(TrackerMessage::GetTask {
+                    run_id,
+                    task_id,
+                    response: tx,
+                })
+                .unwrap();
+
+            let task_record = rx.await.unwrap().unwrap();
+            assert_eq!(task, task_record.task);
+
+            assert_eq!(task_record.state_changes.len(), 3);
+            assert_eq!(
+                task_record.state_changes.last().unwrap().state,
+                State::Completed
+            );
+        }
+
+        // Close off everything
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_generated_expanded_parent() {
+        use serde_json::json;
+
+        let subgraph = json!({
+            "fetch": {
+                "details": { "command": [ "/bin/echo", "{{PART}}" ] },
+                "parameters": { "PART": [ "1", "2" ] }
+            },
+            "load": { "details": { "command": [ "/bin/echo" ] }, "parents": [ "fetch" ] }
+        });
+        let tasks: TaskSet = serde_json::from_value(json!({
+            "generator": {
+                "details": { "command": [ "/bin/echo", subgraph.to_string() ] },
+                "is_generator": true
+            }
+        }))
+        .unwrap();
+        let parameters: Parameters = HashMap::from([("UNUSED".to_owned(), vec!["x".to_owned()])]);
+
+        let (run_id, log_tx) = run(&tasks, &parameters).await;
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let rec = rx.await.unwrap().unwrap();
+        assert_eq!(rec.state_changes.last().unwrap().state, State::Completed);
+
+        // The generated load waits on every part of fetch
+        assert_eq!(rec.tasks["load"].task.parents, vec!["fetch.tail"]);
+        let fetched = rec.tasks["fetch.tail"]
+            .state_changes
+            .last()
+            .unwrap()
+            .datetime;
+        let loaded = &rec.tasks["load"].attempts[0];
+        assert!(loaded.start_time >= fetched);
+
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_generated_subgraph() {
+        use serde_json::json;
+
+        let subgraph = json!({
+            "extract": { "details": { "command": [ "/bin/echo" ] }, "children": [ "clean_a", "clean_b" ] },
+            "clean_a": { "details": { "command": [ "/bin/echo" ] } },
+            "clean_b": { "details": { "command": [ "/bin/echo" ] } },
+            "merge": { "details": { "command": [ "/bin/echo" ] }, "parents": [ "clean_a", "clean_b" ] }
+        });
+        let cyclic = json!({
+            "a": { "details": {}, "children": [ "b" ] },
+            "b": { "details": {}, "children": [ "a" ] }
+        });
+        let unknown = json!({
+            "a": { "details": {}, "parents": [ "missing" ] }
+        });
+
+        for (output, expect_error) in [
+            (subgraph, None),
+            (cyclic, Some("cycle")),
+            (unknown, Some("unknown task missing")),
+        ] {
+            let tasks: TaskSet = serde_json::from_value(json!({
+                "generator": {
+                    "details": { "command": [ "/bin/echo", output.to_string() ] },
+                    "is_generator": true,
+                    "children": [ "report" ]
+                },
+                "report": { "details": { "command": [ "/bin/echo" ] } }
+            }))
+            .unwrap();
+
+            let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+            let terminal = [State::Completed, State::Errored];
+            let state = wait_for_run(run_id, &log_tx, &terminal).await;
+
+            let (tx, rx) = oneshot::channel();
+            log_tx
+                .send(TrackerMessage::GetTasks {
+                    run_id,
+                    response: tx,
+                })
+                .unwrap();
+            let records = rx.await.unwrap().unwrap();
+
+            if let Some(message) = expect_error {
+                assert_eq!(state, State::Errored);
+                assert_eq!(records.len(), 2);
+                let attempts = &records["generator"].attempts;
+                assert!(attempts.last().unwrap().executor[0].contains(message));
+            } else {
+                assert_eq!(state, State::Completed);
+                assert_eq!(records.len(), 6);
+                assert_eq!(records["extract"].task.parents, vec!["generator"]);
+                assert!(records["clean_a"].task.parents.is_empty());
+                assert_eq!(records["merge"].task.children, vec!["report"]);
+
+                // The report waits on the whole generated graph
+                let merged = records["merge"].attempts[0].stop_time;
+                assert!(records["report"].attempts[0].start_time >= merged);
+            }
+
+            exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+            run_tx.send(RunnerMessage::Stop {}).unwrap();
+            log_tx.send(TrackerMessage::Stop {}).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_generated_tasks() {
+        let tasks = parse_generated_tasks(r#"{ "a": { "details": {} } }"#).unwrap();
+        assert!(tasks.contains_key("a"));
+
+        let ndjson = r#"
+            {"task_id": "a", "details": {}, "children": [ "b" ]}
+            {"task_id": "b", "details": { "command": [ "/bin/true" ] }}
+        "#;
+        let tasks = parse_generated_tasks(ndjson).unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks["a"].children, vec!["b"]);
+
+        let duplicated =
+            "{\"task_id\": \"a\", \"details\": {}}\n{\"task_id\": \"a\", \"details\": {}}";
+        assert!(parse_generated_tasks(duplicated).is_err());
+        assert!(parse_generated_tasks("").is_err());
+        assert!(parse_generated_tasks("starting up\n{}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_generator_file() {
+        let script = r#"echo "Generating tasks"; echo '{"task_id": "generated", "details": { "command": [ "/bin/echo" ] }}' > $DAGGY_GENERATOR_FILE"#;
+        let tasks: TaskSet = serde_json::from_value(serde_json::json!({
+            "generator": {
+                "details": { "command": [ "/bin/sh", "-c", script ] },
+                "is_generator": true
+            }
+        }))
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert_eq!(
+            records["generator"].attempts[0].output,
+            "Generating tasks\n"
+        );
+        assert!(records["generator"].attempts[0].generated_tasks.is_empty());
+        assert_eq!(records["generated"].task.parents, vec!["generator"]);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    /// Starts up a tracker, executor and runner, and submits `tasks` as a new
+    /// run without waiting for it to finish.
+    async fn start_run(
+        tasks: TaskSet,
+    ) -> (
+        RunID,
+        mpsc::UnboundedSender<TrackerMessage>,
+        mpsc::UnboundedSender<ExecutorMessage>,
+        mpsc::UnboundedSender<RunnerMessage>,
+    ) {
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(10, exe_rx);
+
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        super::start(run_tx.clone(), run_rx);
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Start {
+                tags: RunTags::new(),
+                tasks,
+                response: tx,
+                parameters: Parameters::new(),
+                filter: TaskFilter::default(),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+            })
+            .unwrap();
+        let run_id = rx.await.unwrap().unwrap();
+
+        (run_id, log_tx, exe_tx, run_tx)
+    }
+
+    async fn wait_for_run(
+        run_id: RunID,
+        tracker: &mpsc::UnboundedSender<TrackerMessage>,
+        states: &[State],
+    ) -> State {
+        loop {
+            let (tx, rx) = oneshot::channel();
+            tracker
+                .send(TrackerMessage::GetState {
+                    run_id,
+                    response: tx,
+                })
+                .unwrap();
+            let state_change = rx.await.unwrap().unwrap();
+            if states.contains(&state_change.state) {
+                return state_change.state;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "slow_task": {
+                    "details": {
+                        "command": [ "/bin/sleep", "1" ]
+                    },
+                    "children": [ "other_task" ]
+                },
+                "other_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "task" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Pause {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+
+        // The running task should finish, but nothing new should start
+        tokio::time::sleep(tokio::time::Duration::from_millis(1500)).await;
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let rec = rx.await.unwrap().unwrap();
+        assert_eq!(rec.state_changes.last().unwrap().state, State::Paused);
+        assert_eq!(
+            rec.tasks["slow_task"].state_changes.last().unwrap().state,
+            State::Completed
+        );
+        assert_eq!(
+            rec.tasks["other_task"].state_changes.last().unwrap().state,
+            State::Queued
+        );
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Resume {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_task_operator_actions() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "failing_task": {
+                    "details": {
+                        "command": [ "/bin/false" ]
+                    },
+                    "children": [ "other_task" ]
+                },
+                "other_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "task" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+
+        // Skip the failing task, then retry the run
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::MarkTask {
+                run_id,
+                task_id: "failing_task".to_owned(),
+                state: State::Skipped,
+                actor: Some("operator".to_owned()),
+                reason: Some("known bad input".to_owned()),
+                tracker: log_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Retry {
+                run_id,
+                selection: None,
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        // Rerun the downstream task
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::RerunTask {
+                run_id,
+                task_id: "other_task".to_owned(),
+                descendants: true,
+                actor: Some("operator".to_owned()),
+                reason: None,
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let rec = rx.await.unwrap().unwrap();
+
+        let failing = &rec.tasks["failing_task"];
+        assert_eq!(failing.attempts.len(), 1);
+        let skipped = failing
+            .state_changes
+            .iter()
+            .find(|x| x.state == State::Skipped)
+            .unwrap();
+        assert_eq!(skipped.actor.as_deref(), Some("operator"));
+        assert_eq!(skipped.reason.as_deref(), Some("known bad input"));
+
+        let other = &rec.tasks["other_task"];
+        assert_eq!(other.attempts.len(), 2);
+        assert_eq!(other.state_changes.last().unwrap().state, State::Completed);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_edit_task_before_retry() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "failing_task": {
+                    "details": {
+                        "command": [ "/bin/false" ]
+                    },
+                    "children": [ "other_task" ]
+                },
+                "other_task": {
+                    "details": {
+                        "command": [ "/bin/echo", "task" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+
+        // Introducing a cycle is rejected
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::UpdateTask {
+                run_id,
+                task_id: "failing_task".to_owned(),
+                update: TaskUpdate {
+                    parents: Some(vec!["other_task".to_owned()]),
+                    ..TaskUpdate::default()
+                },
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        assert!(rx.await.unwrap().is_err());
+
+        // Fix the command
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::UpdateTask {
+                run_id,
+                task_id: "failing_task".to_owned(),
+                update: TaskUpdate {
+                    details: Some(serde_json::json!({ "command": [ "/bin/true" ] })),
+                    ..TaskUpdate::default()
+                },
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Retry {
+                run_id,
+                selection: None,
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[test]
+    fn test_filter_bypasses_excluded_tasks() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "a": { "details": {}, "children": [ "b" ] },
+                "b": { "details": {}, "children": [ "c" ] },
+                "c": { "details": {} },
+                "d": { "details": {}, "parents": [ "b" ] }
+            }"#,
+        )
+        .unwrap();
+        let filter = TaskFilter {
+            include: Vec::new(),
+            exclude: vec!["b".to_owned()],
+        };
+        let filtered = filter_tasks(tasks, &filter).unwrap();
+        assert!(!filtered.contains_key("b"));
+
+        // c and d still wait on a
+        let mut children = filtered["a"].children.clone();
+        children.sort();
+        assert_eq!(children, vec!["c".to_owned(), "d".to_owned()]);
+        let dag = build_dag(&filtered).unwrap();
+        assert_eq!(
+            dag.ancestors(&"c".to_owned()).unwrap(),
+            vec!["a".to_owned()]
+        );
+        assert!(filtered["d"].parents.is_empty());
+    }
+
+    #[test]
+    fn test_filter_tasks() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "fetch": { "details": {}, "children": [ "load_a", "load_b" ] },
+                "load_a": { "details": {}, "children": [ "report" ] },
+                "load_b": { "details": {}, "children": [ "report" ] },
+                "report": { "details": {} },
+                "cleanup": { "details": {} }
+            }"#,
+        )
+        .unwrap();
+
+        // Included tasks pull in their upstream dependencies
+        let filter = TaskFilter {
+            include: vec!["load_*".to_owned()],
+            exclude: vec!["load_b".to_owned()],
+        };
+        let filtered = filter_tasks(tasks.clone(), &filter).unwrap();
+        let mut task_ids: Vec<&TaskID> = filtered.keys().collect();
+        task_ids.sort();
+        assert_eq!(task_ids, vec!["fetch", "load_a"]);
+        assert_eq!(filtered["fetch"].children, vec!["load_a".to_owned()]);
+
+        let filter = TaskFilter {
+            include: Vec::new(),
+            exclude: vec!["*".to_owned()],
+        };
+        assert!(filter_tasks(tasks, &filter).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_selective_retry() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "first": {
+                    "details": {
+                        "command": [ "/bin/echo", "first" ]
+                    },
+                    "children": [ "second" ]
+                },
+                "second": {
+                    "details": {
+                        "command": [ "/bin/echo", "second" ]
+                    },
+                    "children": [ "third" ]
+                },
+                "third": {
+                    "details": {
+                        "command": [ "/bin/echo", "third" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Retry {
+                run_id,
+                selection: Some(TaskSelection {
+                    patterns: vec!["sec*".to_owned()],
+                    upstream: false,
+                    downstream: true,
+                }),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert_eq!(records["first"].attempts.len(), 1);
+        assert_eq!(records["second"].attempts.len(), 2);
+        assert_eq!(records["third"].attempts.len(), 2);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_task_outputs() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "count": {
+                    "details": {
+                        "command": [ "/bin/sh", "-c", "echo DAGGY_OUTPUT rows=42; echo DAGGY_OUTPUT table=raw" ]
+                    },
+                    "children": [ "load" ]
+                },
+                "load": {
+                    "details": {
+                        "command": [ "/bin/sh", "-c", "echo table=clean > $DAGGY_OUTPUT_FILE" ]
+                    },
+                    "children": [ "report" ]
+                },
+                "report": {
+                    "details": {
+                        "command": [ "/bin/sh", "-c", "echo $DAGGY_INPUT_rows $DAGGY_INPUT_table" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert_eq!(records["count"].outputs["rows"], "42");
+        assert_eq!(records["load"].outputs["table"], "clean");
+        assert_eq!(records["report"].attempts[0].output, "42 clean\n");
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_builtin_template_vars() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "dated": {
+                    "details": {
+                        "command": [ "/bin/echo", "{{RUN_ID}}", "{{TASK_ID}}", "{{DATE-1d:%Y%m%d}}", "{{OTHER}}" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let record = rx.await.unwrap().unwrap();
+        let yesterday = record.state_changes[0].datetime - chrono::Duration::days(1);
+        assert_eq!(
+            record.tasks["dated"].attempts[0].output,
+            format!(
+                "{run_id} dated {} {{{{OTHER}}}}\n",
+                yesterday.format("%Y%m%d")
+            )
+        );
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_date_macros_follow_slot() {
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(10, exe_rx);
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        super::start(run_tx.clone(), run_rx);
+
+        // As submitted by the scheduler, with the slot in both a tag and the
+        // {{DATE}} parameter
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "dated": {
+                    "details": {
+                        "command": [ "/bin/echo", "{{DATE}}", "{{DATE-1d:%Y%m%d}}", "{{DATE:%Y%m}}" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let mut tags = RunTags::new();
+        tags.insert(SLOT_TAG.to_owned(), "2022-03-01T00:00:00+00:00".to_owned());
+        let parameters = Parameters::from([("{{DATE}}".to_owned(), vec!["2022-03-01".to_owned()])]);
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Start {
+                tags,
+                tasks,
+                response: tx,
+                parameters,
+                filter: TaskFilter::default(),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+            })
+            .unwrap();
+        let run_id = rx.await.unwrap().unwrap();
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let record = rx.await.unwrap().unwrap();
+        let task = record.tasks.values().next().unwrap();
+        assert_eq!(task.attempts[0].output, "2022-03-01 20220228 202203\n");
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subrun_tasks() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "child": {
+                    "task_type": "Subrun",
+                    "details": {
+                        "tags": { "team": "data" },
+                        "tasks": {
+                            "hello": { "details": { "command": [ "/bin/echo", "{{NAME}}" ] } }
+                        },
+                        "parameters": { "{{NAME}}": [ "a", "b" ] }
+                    },
+                    "children": [ "failing_child" ]
+                },
+                "failing_child": {
+                    "task_type": "Subrun",
+                    "details": {
+                        "tasks": {
+                            "fail": { "details": { "command": [ "/bin/false" ] } }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert_eq!(
+            records["child"].state_changes.last().unwrap().state,
+            State::Completed
+        );
+        assert_eq!(
+            records["failing_child"].state_changes.last().unwrap().state,
+            State::Errored
+        );
+        assert_eq!(records["child"].subruns.len(), 1);
+
+        let subrun_id = records["child"].subruns[0];
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id: subrun_id,
+                response: tx,
+            })
+            .unwrap();
+        let subrun = rx.await.unwrap().unwrap();
+        assert_eq!(subrun.tags["team"], "data");
+        assert_eq!(subrun.tags["daggyr.parent_run"], run_id.to_string());
+        assert_eq!(subrun.tags["daggyr.parent_task"], "child");
+        assert_eq!(subrun.tasks.len(), 4);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subrun_parameters() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "child": {
+                    "task_type": "Subrun",
+                    "details": {
+                        "tasks": {
+                            "hello": { "details": { "command": [ "/bin/echo", "{{NAME}}" ] } }
+                        },
+                        "parameters": { "{{NAME}}": [ "{{NAME}}_child" ] }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let parameters = HashMap::from([("{{NAME}}".to_owned(), vec!["parent".to_owned()])]);
+
+        let (run_id, log_tx) = run(&tasks, &parameters).await;
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTask {
+                run_id,
+                task_id: "child".to_owned(),
+                response: tx,
+            })
+            .unwrap();
+        let record = rx.await.unwrap().unwrap();
+
+        // The parent's parameters reach the sub-run's parameters, but not
+        // its tasks
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id: record.subruns[0],
+                response: tx,
+            })
+            .unwrap();
+        let subrun = rx.await.unwrap().unwrap();
+        assert_eq!(
+            subrun.tasks["hello"].task.details["command"],
+            serde_json::json!(["/bin/echo", "parent_child"])
+        );
+
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subrun_template() {
+        use crate::structs::RunSpec;
+
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(10, exe_rx);
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        super::start(run_tx.clone(), run_rx);
+
+        let spec: RunSpec = serde_json::from_str(
+            r#"
+            {
+                "tasks": {
+                    "load": { "details": { "command": [ "/bin/echo", "{{DAY}}" ] } },
+                    "skipped": { "details": { "command": [ "/bin/echo" ] } }
+                },
+                "parameters": { "{{DAY}}": [ "template" ] },
+                "exclude": [ "skipped" ]
+            }"#,
+        )
+        .unwrap();
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::StoreTemplate {
+                name: "nightly".to_owned(),
+                spec,
+                response: tx,
+            })
+            .unwrap();
+        assert_eq!(rx.await.unwrap().unwrap(), 1);
+
+        // The parent's parameters are applied over the template's
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "templated": {
+                    "task_type": "Subrun",
+                    "details": {
+                        "template": "nightly",
+                        "parameters": { "{{DAY}}": [ "{{NAME}}" ] }
+                    }
+                },
+                "missing": {
+                    "task_type": "Subrun",
+                    "details": { "template": "missing" }
+                }
+            }"#,
+        )
+        .unwrap();
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Start {
+                tags: RunTags::new(),
+                tasks,
+                response: tx,
+                parameters: HashMap::from([("{{NAME}}".to_owned(), vec!["parent".to_owned()])]),
+                filter: TaskFilter::default(),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+            })
+            .unwrap();
+        let run_id = rx.await.unwrap().unwrap();
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert!(records["missing"].subruns.is_empty());
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRun {
+                run_id: records["templated"].subruns[0],
+                response: tx,
+            })
+            .unwrap();
+        let subrun = rx.await.unwrap().unwrap();
+        assert_eq!(subrun.tags["daggyr.template"], "nightly");
+        assert_eq!(subrun.tags["daggyr.template_version"], "1");
+        assert_eq!(subrun.tasks.len(), 1);
+        assert_eq!(
+            subrun.tasks["load"].task.details["command"],
+            serde_json::json!(["/bin/echo", "parent"])
+        );
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stopping_subrun_parent() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "child": {
+                    "task_type": "Subrun",
+                    "details": {
+                        "tasks": {
+                            "slow": { "details": { "command": [ "/bin/sleep", "5" ] } }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::StopRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTask {
+                run_id,
+                task_id: "child".to_owned(),
+                response: tx,
+            })
+            .unwrap();
+        let record = rx.await.unwrap().unwrap();
+        assert_eq!(record.subruns.len(), 1);
+
+        let terminal = [State::Completed, State::Errored, State::Killed];
+        assert_eq!(
+            wait_for_run(record.subruns[0], &log_tx, &terminal).await,
+            State::Killed
+        );
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sensor_tasks() {
+        let flag_file = std::path::Path::new("./runner_sensor_test.flag");
+        if flag_file.exists() {
+            std::fs::remove_file(flag_file).unwrap();
+        }
+
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "make_flag": {
+                    "details": {
+                        "command": [ "/bin/sh", "-c", "sleep 1; touch ./runner_sensor_test.flag" ]
+                    }
+                },
+                "wait_for_flag": {
+                    "task_type": "Sensor",
+                    "details": {
+                        "file": "./runner_sensor_*.flag",
+                        "poke_interval_seconds": 1,
+                        "timeout_seconds": 10
+                    },
+                    "children": [ "use_flag" ]
+                },
+                "use_flag": {
+                    "details": {
+                        "command": [ "/bin/ls", "./runner_sensor_test.flag" ]
+                    }
+                },
+                "never": {
+                    "task_type": "Sensor",
+                    "details": {
+                        "command": [ "/bin/false" ],
+                        "poke_interval_seconds": 1,
+                        "timeout_seconds": 1
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        for task_id in ["wait_for_flag", "use_flag"] {
+            assert_eq!(
+                records[task_id].state_changes.last().unwrap().state,
+                State::Completed
+            );
+        }
+        let never = &records["never"];
+        assert_eq!(never.state_changes.last().unwrap().state, State::Errored);
+        assert!(never.attempts[0].executor[0].contains("timed out"));
+
+        std::fs::remove_file(flag_file).unwrap();
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rerun_killed_sensor() {
+        let flag_file = std::path::Path::new("./runner_sensor_rerun_test.flag");
+        if flag_file.exists() {
+            std::fs::remove_file(flag_file).unwrap();
+        }
+
+        // The slow task keeps the run going, so every attempt is logged
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "wait_for_flag": {
+                    "task_type": "Sensor",
+                    "details": {
+                        "file": "./runner_sensor_rerun_test.flag",
+                        "poke_interval_seconds": 1,
+                        "timeout_seconds": 10
+                    }
+                },
+                "slow": { "details": { "command": [ "/bin/sleep", "3" ] } }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+        kill_and_rerun(run_id, "wait_for_flag", &log_tx, &exe_tx, &run_tx).await;
+
+        // Raise the flag between the rerun's first poke and the first
+        // poller's next one
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        std::fs::write(flag_file, "").unwrap();
+
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        // The first poller stopped when it was killed, rather than going on
+        // to report for the rerun
+        let mut attempts = task_attempts(run_id, "wait_for_flag", &log_tx).await;
+        attempts.sort_by_key(|attempt| attempt.start_time);
+        assert_eq!(attempts.len(), 2, "{attempts:?}");
+        assert!(attempts[0].killed && !attempts[0].succeeded);
+        assert!(attempts[1].succeeded);
+
+        std::fs::remove_file(flag_file).unwrap();
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_external_dependencies() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"{ "load": { "details": { "command": [ "/bin/echo", "loaded" ] } } }"#,
+        )
+        .unwrap();
+        let (load_run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        wait_for_run(load_run_id, &log_tx, &terminal).await;
+
+        let mut tags = RunTags::new();
+        tags.insert("pipeline".to_owned(), "load".to_owned());
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Start {
+                tags,
+                tasks: serde_json::from_str(
+                    r#"{ "load": { "details": { "command": [ "/bin/echo", "loaded" ] } } }"#,
+                )
+                .unwrap(),
+                response: tx,
+                parameters: Parameters::new(),
+                filter: TaskFilter::default(),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+            })
+            .unwrap();
+        let tagged_run_id = rx.await.unwrap().unwrap();
+        wait_for_run(tagged_run_id, &log_tx, &terminal).await;
+
+        let tasks: TaskSet = serde_json::from_str(&format!(
+            r#"
+            {{
+                "by_id": {{
+                    "external_parents": [ {{ "run_id": {load_run_id}, "task_id": "load" }} ],
+                    "details": {{ "command": [ "/bin/echo", "by_id" ] }}
+                }},
+                "by_tags": {{
+                    "external_parents": [ {{ "tags": {{ "pipeline": "load" }}, "task_id": "load" }} ],
+                    "details": {{ "command": [ "/bin/echo", "by_tags" ] }}
+                }},
+                "missing": {{
+                    "external_parents": [
+                        {{ "tags": {{ "pipeline": "other" }}, "task_id": "load", "timeout_seconds": 1 }}
+                    ],
+                    "details": {{ "command": [ "/bin/echo", "missing" ] }}
+                }}
+            }}"#
+        ))
+        .unwrap();
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Start {
+                tags: RunTags::new(),
+                tasks,
+                response: tx,
+                parameters: Parameters::new(),
+                filter: TaskFilter::default(),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+            })
+            .unwrap();
+        let run_id = rx.await.unwrap().unwrap();
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        let state = |task_id: &str| records[task_id].state_changes.last().unwrap().state;
+        assert_eq!(state("by_id.wait.0"), State::Completed);
+        assert_eq!(state("by_id"), State::Completed);
+        assert_eq!(state("by_tags"), State::Completed);
+        assert_eq!(state("missing.wait.0"), State::Errored);
+        assert_eq!(state("missing"), State::Queued);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_semaphores() {
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(10, exe_rx);
+
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        let limits = HashMap::from([("db".to_owned(), 1)]);
+        super::start_with_semaphores(&limits, run_tx.clone(), run_rx);
+
+        let start = |tasks: &str| {
+            let (tx, rx) = oneshot::channel();
+            run_tx
+                .send(RunnerMessage::Start {
+                    tags: RunTags::new(),
+                    tasks: serde_json::from_str(tasks).unwrap(),
+                    response: tx,
+                    parameters: Parameters::new(),
+                    filter: TaskFilter::default(),
+                    tracker: log_tx.clone(),
+                    executor: exe_tx.clone(),
+                })
+                .unwrap();
+            rx
+        };
+
+        let tasks = r#"
+            {
+                "a": { "semaphores": [ "db" ], "details": { "command": [ "/bin/sleep", "0.3" ] } },
+                "b": { "semaphores": [ "db" ], "details": { "command": [ "/bin/sleep", "0.3" ] } }
+            }"#;
+        let run_ids = [
+            start(tasks).await.unwrap().unwrap(),
+            start(tasks).await.unwrap().unwrap(),
+        ];
+
+        let mut attempts = Vec::new();
+        for run_id in run_ids {
+            let terminal = [State::Completed, State::Errored];
+            assert_eq!(
+                wait_for_run(run_id, &log_tx, &terminal).await,
+                State::Completed
+            );
+            let (tx, rx) = oneshot::channel();
+            log_tx
+                .send(TrackerMessage::GetTasks {
+                    run_id,
+                    response: tx,
+                })
+                .unwrap();
+            for record in rx.await.unwrap().unwrap().into_values() {
+                attempts.extend(record.attempts);
+            }
+        }
+
+        // No two tasks holding the semaphore should have overlapped
+        attempts.sort_by_key(|attempt| attempt.start_time);
+        assert_eq!(attempts.len(), 4);
+        for pair in attempts.windows(2) {
+            assert!(pair[0].stop_time <= pair[1].start_time);
+        }
+
+        let missing =
+            r#"{ "a": { "semaphores": [ "other" ], "details": { "command": [ "/bin/true" ] } } }"#;
+        assert!(start(missing).await.unwrap().is_err());
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cached_tasks() {
+        let input_file = std::path::Path::new("./runner_cache_test.input");
+        std::fs::write(input_file, "1").unwrap();
+
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(10, exe_rx);
+
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        super::start(run_tx.clone(), run_rx);
+
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "extract": {
+                    "cache": true,
+                    "inputs": [ "./runner_cache_test.input" ],
+                    "details": {
+                        "command": [ "/bin/sh", "-c", "echo DAGGY_OUTPUT rows=$(cat ./runner_cache_test.input)" ]
+                    },
+                    "children": [ "transform" ]
+                },
+                "transform": {
+                    "cache": true,
+                    "details": { "command": [ "/bin/echo", "transform" ] },
+                    "children": [ "report" ]
+                },
+                "report": {
+                    "details": { "command": [ "/bin/sh", "-c", "echo $DAGGY_INPUT_rows" ] }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        // Runs the tasks, returning the task that each attempt was cached from
+        let run_tasks = || async {
+            let (tx, rx) = oneshot::channel();
+            run_tx
+                .send(RunnerMessage::Start {
+                    tags: RunTags::new(),
+                    tasks: tasks.clone(),
+                    response: tx,
+                    parameters: Parameters::new(),
+                    filter: TaskFilter::default(),
+                    tracker: log_tx.clone(),
+                    executor: exe_tx.clone(),
+                })
+                .unwrap();
+            let run_id = rx.await.unwrap().unwrap();
+            let terminal = [State::Completed, State::Errored];
+            assert_eq!(
+                wait_for_run(run_id, &log_tx, &terminal).await,
+                State::Completed
+            );
+
+            let (tx, rx) = oneshot::channel();
+            log_tx
+                .send(TrackerMessage::GetTasks {
+                    run_id,
+                    response: tx,
+                })
+                .unwrap();
+            let records = rx.await.unwrap().unwrap();
+            assert_eq!(
+                records["report"].attempts[0].output,
+                format!("{}\n", std::fs::read_to_string(input_file).unwrap())
+            );
+            records
+                .into_iter()
+                .map(|(task_id, record)| {
+                    let attempt = record.attempts.last().unwrap();
+                    (task_id, attempt.cached_from.as_ref().map(|src| src.run_id))
+                })
+                .collect::<HashMap<TaskID, Option<RunID>>>()
+        };
+
+        let first = run_tasks().await;
+        assert!(first.values().all(Option::is_none));
+
+        let second = run_tasks().await;
+        assert_eq!(second["extract"], Some(0));
+        assert_eq!(second["transform"], Some(0));
+        assert_eq!(second["report"], None);
+
+        // Changing an input invalidates the task, and everything downstream
+        std::fs::write(input_file, "2").unwrap();
+        let third = run_tasks().await;
+        assert!(third.values().all(Option::is_none));
+
+        std::fs::remove_file(input_file).unwrap();
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_output_freshness() {
+        let input_file = std::path::Path::new("./runner_freshness_test.input");
+        let output_file = std::path::Path::new("./runner_freshness_test.output");
+        std::fs::write(input_file, "data").unwrap();
+        if output_file.exists() {
+            std::fs::remove_file(output_file).unwrap();
+        }
+
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(10, exe_rx);
+
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        super::start(run_tx.clone(), run_rx);
+
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "build": {
+                    "inputs": [ "./runner_freshness_test.input" ],
+                    "outputs": [ "./runner_freshness_test.output" ],
+                    "details": {
+                        "command": [ "/bin/cp", "./runner_freshness_test.input", "./runner_freshness_test.output" ]
+                    }
+                },
+                "forgetful": {
+                    "outputs": [ "./runner_freshness_test.missing" ],
+                    "details": { "command": [ "/bin/true" ] }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        // Runs the tasks, returning the run and the state of the build task
+        let run_tasks = || async {
+            let (tx, rx) = oneshot::channel();
+            run_tx
+                .send(RunnerMessage::Start {
+                    tags: RunTags::new(),
+                    tasks: tasks.clone(),
+                    response: tx,
+                    parameters: Parameters::new(),
+                    filter: TaskFilter::default(),
+                    tracker: log_tx.clone(),
+                    executor: exe_tx.clone(),
+                })
+                .unwrap();
+            let run_id = rx.await.unwrap().unwrap();
+            let terminal = [State::Completed, State::Errored];
+            wait_for_run(run_id, &log_tx, &terminal).await;
+
+            let states = task_states(run_id, &log_tx).await;
+            assert_eq!(states["forgetful"], State::Errored);
+            let attempts = task_attempts(run_id, "forgetful", &log_tx).await;
+            assert!(attempts[0]
+                .executor
+                .iter()
+                .any(|msg| msg.contains("runner_freshness_test.missing")));
+            (run_id, states["build"])
+        };
+
+        assert_eq!(run_tasks().await.1, State::Completed);
+        let (run_id, state) = run_tasks().await;
+        assert_eq!(state, State::Skipped);
+        assert!(task_attempts(run_id, "build", &log_tx).await.is_empty());
+
+        // Updating the input makes the output stale
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(input_file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let (run_id, state) = run_tasks().await;
+        assert_eq!(state, State::Completed);
+        assert_eq!(task_attempts(run_id, "build", &log_tx).await.len(), 1);
+
+        std::fs::remove_file(input_file).unwrap();
+        std::fs::remove_file(output_file).unwrap();
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_hooks() {
+        let terminal = [State::Completed, State::Errored, State::Killed];
+
+        // A failing run triggers the failure and final hooks, and a failing
+        // hook doesn't change the outcome
+        let mut tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "main": { "details": { "command": [ "/bin/false" ] } }
+            }"#,
+        )
+        .unwrap();
+        let hooks: RunHooks = serde_json::from_str(
+            r#"
+            {
+                "finally": {
+                    "cleanup": { "details": { "command": [ "/bin/false" ] } }
+                },
+                "on_success": {
+                    "celebrate": { "details": { "command": [ "/bin/true" ] } }
+                },
+                "on_failure": {
+                    "notify": { "details": { "command": [ "/bin/true" ] } },
+                    "page": {
+                        "details": { "command": [ "/bin/true" ] },
+                        "parents": [ "notify" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        hooks.add_to(&mut tasks).unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+        let states = task_states(run_id, &log_tx).await;
+        assert_eq!(states["main"], State::Errored);
+        assert_eq!(states["cleanup"], State::Errored);
+        assert_eq!(states["notify"], State::Completed);
+        assert_eq!(states["page"], State::Completed);
+        assert_eq!(states["celebrate"], State::Skipped);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_operator_actions_during_hooks() {
+        let mut tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "main": { "details": { "command": [ "/bin/false" ] } }
+            }"#,
+        )
+        .unwrap();
+        let hooks: RunHooks = serde_json::from_str(
+            r#"
+            {
+                "finally": {
+                    "cleanup": { "details": { "command": [ "/bin/sleep", "1" ] } }
+                }
+            }"#,
+        )
+        .unwrap();
+        hooks.add_to(&mut tasks).unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        assert_eq!(
+            task_states(run_id, &log_tx).await["cleanup"],
+            State::Running
+        );
+
+        // The main tasks are still part of the run while its hooks run
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::MarkTask {
+                run_id,
+                task_id: "main".to_owned(),
+                state: State::Completed,
+                actor: None,
+                reason: None,
+                tracker: log_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+
+        let terminal = [State::Completed, State::Errored, State::Killed];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
+        let states = task_states(run_id, &log_tx).await;
+        assert_eq!(states["main"], State::Completed);
+        assert_eq!(states["cleanup"], State::Completed);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hooks_after_stop() {
+        let terminal = [State::Completed, State::Errored, State::Killed];
+        let mut tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "slow": { "details": { "command": [ "/bin/sleep", "5" ] } }
+            }"#,
+        )
+        .unwrap();
+        let hooks: RunHooks = serde_json::from_str(
+            r#"
+            {
+                "finally": {
+                    "cleanup": { "details": { "command": [ "/bin/true" ] } }
+                },
+                "on_success": {
+                    "celebrate": { "details": { "command": [ "/bin/true" ] } }
+                }
+            }"#,
+        )
+        .unwrap();
+        hooks.add_to(&mut tasks).unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::StopRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap();
+
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Killed
+        );
+        let states = task_states(run_id, &log_tx).await;
+        assert_eq!(states["slow"], State::Killed);
+        assert_eq!(states["cleanup"], State::Completed);
+        assert_eq!(states["celebrate"], State::Skipped);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+
+        // Hooks can't depend on the main tasks
+        let mut tasks = TaskSet::new();
+        let hooks: RunHooks = serde_json::from_str(
+            r#"
+            {
+                "finally": {
+                    "cleanup": { "details": {}, "parents": [ "slow" ] }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(hooks.add_to(&mut tasks).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plan_run() {
+        let mut tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "extract": {
+                    "details": { "command": [ "/bin/echo", "extract" ] },
+                    "children": [ "transform" ]
+                },
+                "transform": {
+                    "details": { "command": [ "/bin/echo", "DATE" ] },
+                    "children": [ "load" ]
+                },
+                "load": {
+                    "details": { "command": [ "/bin/true" ], "timeout": "soon" }
+                },
+                "report": {
+                    "details": { "command": [ "/bin/echo", "report" ] },
+                    "parents": [ "extract" ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let hooks: RunHooks = serde_json::from_str(
+            r#"{ "finally": { "cleanup": { "details": { "command": [ "/bin/true" ] } } } }"#,
+        )
+        .unwrap();
+        hooks.add_to(&mut tasks).unwrap();
+        let parameters: Parameters =
+            serde_json::from_str(r#"{ "DATE": [ "20200101", "20200102", "20200103" ] }"#).unwrap();
+
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        memory_tracker::start(log_rx);
+        let (exe_tx, exe_rx) = mpsc::unbounded_channel();
+        local_executor::start(1, exe_rx);
+        let (noop_tx, noop_rx) = mpsc::unbounded_channel();
+        tokio::spawn(noop_executor::start_local_executor(noop_rx));
+        let (run_tx, run_rx) = mpsc::unbounded_channel();
+        super::start(run_tx.clone(), run_rx);
+
+        let pools = HashMap::from([
+            ("local".to_owned(), exe_tx.clone()),
+            ("noop".to_owned(), noop_tx.clone()),
+        ]);
+        let (response, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Plan {
+                tasks,
+                parameters,
+                filter: TaskFilter::default(),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                pools,
+                response,
+            })
+            .unwrap();
+        let plan = rx.await.unwrap().unwrap();
+
+        // extract -> transform -> 3 expanded tasks -> transform.tail -> load,
+        // and extract -> report
+        assert_eq!(plan.tasks.len(), 9);
+        assert!(plan.tasks.contains_key("transform.DATE:20200102"));
+        assert_eq!(plan.tasks["cleanup"].hook, Some(HookType::Finally));
+        assert_eq!(plan.edges, 9);
+        assert_eq!(plan.roots, vec!["extract"]);
+        assert_eq!(plan.leaves, vec!["load", "report"]);
+        assert_eq!(plan.critical_path_length, 3);
+        assert_eq!(plan.problems.len(), 1);
+        assert_eq!(plan.problems["local"].len(), 1);
+        assert!(plan.problems["local"][0].contains("task load"));
+
+        // Nothing was registered with the tracker
+        let (response, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetRuns {
+                tags: None,
+                states: None,
+                start_time: None,
+                end_time: None,
+                response,
+            })
+            .unwrap();
+        assert!(rx.await.unwrap().unwrap().is_empty());
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        noop_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rerun_fresh_task() {
+        std::fs::write("./runner_rerun_fresh_test.input", "data").unwrap();
+        std::fs::write("./runner_rerun_fresh_test.output", "data").unwrap();
+
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "build": {
+                    "inputs": [ "./runner_rerun_fresh_test.input" ],
+                    "outputs": [ "./runner_rerun_fresh_test.output" ],
+                    "details": {
+                        "command": [ "/bin/cp", "./runner_rerun_fresh_test.input", "./runner_rerun_fresh_test.output" ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+        assert_eq!(task_states(run_id, &log_tx).await["build"], State::Skipped);
+        assert!(task_attempts(run_id, "build", &log_tx).await.is_empty());
+
+        // Explicit reruns run fresh tasks anyway
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::RerunTask {
+                run_id,
+                task_id: "build".to_owned(),
+                descendants: false,
+                actor: None,
+                reason: None,
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        wait_for_run(run_id, &log_tx, &terminal).await;
+        assert_eq!(task_attempts(run_id, "build", &log_tx).await.len(), 1);
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Retry {
+                run_id,
+                selection: Some(TaskSelection {
+                    patterns: vec!["build".to_owned()],
+                    upstream: false,
+                    downstream: false,
+                }),
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        wait_for_run(run_id, &log_tx, &terminal).await;
+        assert_eq!(task_attempts(run_id, "build", &log_tx).await.len(), 2);
 
-    while let Some(msg) = msg_rx.recv().await {
-        use RunnerMessage::{ExecutionReport, Retry, Start, Stop, StopRun};
-        match msg {
-            Start {
-                tags,
-                tasks,
-                response,
-                parameters,
-                tracker,
-                executor,
-            } => {
-                // Queue all pending tasks
-                let result = match Run::new(
-                    tags,
-                    tasks,
-                    parameters,
-                    tracker,
-                    executor,
-                    msg_tx.clone(),
-                )
-                .await
-                {
-                    Ok(mut run) => {
-                        let run_id = run.run_id;
+        std::fs::remove_file("./runner_rerun_fresh_test.input").unwrap();
+        std::fs::remove_file("./runner_rerun_fresh_test.output").unwrap();
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
 
-                        // Update the state of the run
-                        match run.run().await {
-                            Ok(State::Running) => {
-                                runs.insert(run_id, run);
-                                Ok(run_id)
-                            }
-                            Ok(state) => Err(anyhow!("Run in state {:?} after enqueuing", state)),
-                            Err(e) => Err(anyhow!("Error enqueing run: {:?}", e)),
+    async fn task_states(
+        run_id: RunID,
+        tracker: &mpsc::UnboundedSender<TrackerMessage>,
+    ) -> HashMap<TaskID, State> {
+        let (tx, rx) = oneshot::channel();
+        tracker
+            .send(TrackerMessage::GetRun {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let record = rx.await.unwrap().unwrap();
+        record
+            .tasks
+            .into_iter()
+            .map(|(task_id, tr)| (task_id, tr.state_changes.last().unwrap().state))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_killing_subrun_task() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "child": {
+                    "task_type": "Subrun",
+                    "details": {
+                        "tasks": {
+                            "slow": { "details": { "command": [ "/bin/sleep", "2" ] } }
                         }
                     }
-                    Err(e) => Err(e),
-                };
-                response.send(result).unwrap_or(());
-            }
-            StopRun { run_id, response } => match runs.get_mut(&run_id) {
-                Some(run) => {
-                    run.stop().await.unwrap_or(());
-                    runs.remove(&run_id);
-                    response.send(()).unwrap_or(());
-                }
-                None => {
-                    response.send(()).unwrap_or(());
                 }
-            },
-            Retry {
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        kill_and_rerun(run_id, "child", &log_tx, &exe_tx, &run_tx).await;
+
+        let terminal = [State::Completed, State::Errored, State::Killed];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        // Killing the task stopped its sub-run, and the rerun finished with
+        // its own
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTask {
                 run_id,
-                tracker,
-                executor,
-                response,
-            } => {
-                //if let std::collections::hash_map::Entry::Vacant(e) = runs.entry(run_id) {
-                use std::collections::hash_map::Entry::{Occupied, Vacant};
-                let result = match runs.entry(run_id) {
-                    Occupied(_) => Err(anyhow!("Run ID is currently running, cannot retry.")),
-                    Vacant(e) => {
-                        match Run::from_tracker(run_id, tracker, executor, msg_tx.clone()).await {
-                            Ok(mut run) => match run.run().await {
-                                Ok(State::Running) => {
-                                    e.insert(run);
-                                    Ok(())
-                                }
-                                Ok(state) => {
-                                    Err(anyhow!("Run in state {:?} after enqueuing", state))
-                                }
-                                Err(e) => Err(anyhow!("Error enqueing run: {:?}", e)),
-                            },
-                            Err(e) => Err(e),
-                        }
-                    }
-                };
-                response.send(result).unwrap_or(());
-            }
-            ExecutionReport {
+                task_id: "child".to_owned(),
+                response: tx,
+            })
+            .unwrap();
+        let record = rx.await.unwrap().unwrap();
+        assert_eq!(record.subruns.len(), 2);
+        let first = wait_for_run(record.subruns[0], &log_tx, &terminal).await;
+        assert_eq!(first, State::Killed);
+        let last = record.attempts.last().unwrap();
+        assert!(last.executor[1].contains(&record.subruns[1].to_string()));
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    async fn kill_and_rerun(
+        run_id: RunID,
+        task_id: &str,
+        log_tx: &mpsc::UnboundedSender<TrackerMessage>,
+        exe_tx: &mpsc::UnboundedSender<ExecutorMessage>,
+        run_tx: &mpsc::UnboundedSender<RunnerMessage>,
+    ) {
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::KillTask {
                 run_id,
-                task_id,
-                attempt,
-            } => {
-                if let Some(run) = runs.get_mut(&run_id) {
-                    run.complete_task(&task_id, attempt).await.unwrap_or(());
-                    // TODO NOT SURE ABOUT THIS
-                    if let Ok(state) = run.run().await {
-                        if state != State::Running {
-                            runs.remove(&run_id);
-                        }
+                task_id: task_id.to_owned(),
+                actor: None,
+                reason: None,
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::RerunTask {
+                run_id,
+                task_id: task_id.to_owned(),
+                descendants: false,
+                actor: None,
+                reason: None,
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+    }
+
+    async fn task_attempts(
+        run_id: RunID,
+        task_id: &str,
+        tracker: &mpsc::UnboundedSender<TrackerMessage>,
+    ) -> Vec<TaskAttempt> {
+        let (tx, rx) = oneshot::channel();
+        tracker
+            .send(TrackerMessage::GetTask {
+                run_id,
+                task_id: task_id.to_owned(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap().attempts
+    }
+
+    #[tokio::test]
+    async fn test_retry_leaf_task() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "first": {
+                    "details": {
+                        "command": [ "/bin/echo", "first" ]
+                    },
+                    "children": [ "second" ]
+                },
+                "second": {
+                    "details": {
+                        "command": [ "/bin/echo", "second" ]
+                    },
+                    "children": [ "third" ]
+                },
+                "third": {
+                    "details": {
+                        "command": [ "/bin/echo", "third" ]
                     }
                 }
-            }
-            Stop {} => {
-                break;
-            }
+            }"#,
+        )
+        .unwrap();
+
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        // Completed states are restored in whatever order the tracker hands
+        // them back, so retry a few times
+        for retry in 1..=5 {
+            let (tx, rx) = oneshot::channel();
+            run_tx
+                .send(RunnerMessage::Retry {
+                    run_id,
+                    selection: Some(TaskSelection {
+                        patterns: vec!["third".to_owned()],
+                        upstream: false,
+                        downstream: false,
+                    }),
+                    tracker: log_tx.clone(),
+                    executor: exe_tx.clone(),
+                    response: tx,
+                })
+                .unwrap();
+            rx.await.unwrap().unwrap();
+            assert_eq!(
+                wait_for_run(run_id, &log_tx, &terminal).await,
+                State::Completed
+            );
+
+            let (tx, rx) = oneshot::channel();
+            log_tx
+                .send(TrackerMessage::GetTasks {
+                    run_id,
+                    response: tx,
+                })
+                .unwrap();
+            let records = rx.await.unwrap().unwrap();
+            assert_eq!(records["first"].attempts.len(), 1);
+            assert_eq!(records["second"].attempts.len(), 1);
+            assert_eq!(records["third"].attempts.len(), 1 + retry);
         }
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::executors::local_executor;
-    use crate::trackers::memory_tracker;
+    #[tokio::test]
+    async fn test_edit_inactive_task_edges() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "a": {
+                    "details": {
+                        "command": [ "/bin/echo", "a" ]
+                    },
+                    "children": [ "b" ]
+                },
+                "b": {
+                    "details": {
+                        "command": [ "/bin/echo", "b" ]
+                    }
+                },
+                "c": {
+                    "details": {
+                        "command": [ "/bin/echo", "c" ]
+                    }
+                },
+                "wait": {
+                    "task_type": "Sensor",
+                    "details": {
+                        "file": "./Cargo.toml",
+                        "poke_interval_seconds": 1
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
 
-    async fn run(
-        tasks: &TaskSet,
-        parameters: &Parameters,
-    ) -> (RunID, mpsc::UnboundedSender<TrackerMessage>) {
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
+
+        let update_task = |task_id: &str, update: TaskUpdate| {
+            let (tx, rx) = oneshot::channel();
+            run_tx
+                .send(RunnerMessage::UpdateTask {
+                    run_id,
+                    task_id: task_id.to_owned(),
+                    update,
+                    tracker: log_tx.clone(),
+                    executor: exe_tx.clone(),
+                    response: tx,
+                })
+                .unwrap();
+            rx
+        };
+
+        // Details of tasks the runner handles are checked too
+        let rx = update_task(
+            "wait",
+            TaskUpdate {
+                details: Some(serde_json::json!({ "file": "x", "poke_interval_seconds": 0 })),
+                ..TaskUpdate::default()
+            },
+        );
+        assert!(rx.await.unwrap().is_err());
+
+        // Moving b from under a to under c updates both a and c
+        let rx = update_task(
+            "b",
+            TaskUpdate {
+                parents: Some(vec!["c".to_owned()]),
+                ..TaskUpdate::default()
+            },
+        );
+        rx.await.unwrap().unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert!(records["a"].task.children.is_empty());
+        assert_eq!(records["b"].task.parents, vec!["c".to_owned()]);
+        assert_eq!(records["c"].task.children, vec!["b".to_owned()]);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_semaphores_on_rerun() {
         let (log_tx, log_rx) = mpsc::unbounded_channel();
         memory_tracker::start(log_rx);
 
@@ -596,294 +5028,362 @@ mod tests {
         local_executor::start(10, exe_rx);
 
         let (run_tx, run_rx) = mpsc::unbounded_channel();
-        let rtx = run_tx.clone();
-        super::start(rtx, run_rx);
+        let limits = HashMap::from([("db".to_owned(), 1)]);
+        super::start_with_semaphores(&limits, run_tx.clone(), run_rx);
 
         let (tx, rx) = oneshot::channel();
         run_tx
             .send(RunnerMessage::Start {
                 tags: RunTags::new(),
-                tasks: tasks.clone(),
+                tasks: serde_json::from_str(
+                    r#"
+                    {
+                        "a": { "semaphores": [ "db" ], "details": { "command": [ "/bin/sleep", "0.3" ] } },
+                        "b": { "semaphores": [ "db" ], "details": { "command": [ "/bin/sleep", "0.3" ] } }
+                    }"#,
+                )
+                .unwrap(),
                 response: tx,
-                parameters: parameters.clone(),
+                parameters: Parameters::new(),
+                filter: TaskFilter::default(),
                 tracker: log_tx.clone(),
                 executor: exe_tx.clone(),
             })
             .unwrap();
-
         let run_id = rx.await.unwrap().unwrap();
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
 
-        // Need some way to get
-        loop {
+        // The first rerun reloads the run from the tracker, the second joins it
+        for task_id in ["a", "b"] {
             let (tx, rx) = oneshot::channel();
-
-            log_tx
-                .send(TrackerMessage::GetState {
+            run_tx
+                .send(RunnerMessage::RerunTask {
                     run_id,
+                    task_id: task_id.to_owned(),
+                    descendants: false,
+                    actor: None,
+                    reason: None,
+                    tracker: log_tx.clone(),
+                    executor: exe_tx.clone(),
                     response: tx,
                 })
                 .unwrap();
-            let state_change = rx.await.unwrap().unwrap();
+            rx.await.unwrap().unwrap();
+        }
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
 
-            if state_change.state == State::Completed || state_change.state == State::Errored {
-                break;
-            }
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let mut attempts: Vec<TaskAttempt> = rx
+            .await
+            .unwrap()
+            .unwrap()
+            .into_values()
+            .flat_map(|record| record.attempts)
+            .collect();
 
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        // Reruns still take turns holding the semaphore
+        attempts.sort_by_key(|attempt| attempt.start_time);
+        assert_eq!(attempts.len(), 4);
+        assert!(attempts.iter().all(|attempt| attempt.succeeded));
+        for pair in attempts.windows(2) {
+            assert!(pair[0].stop_time <= pair[1].start_time);
         }
 
-        // Close off everything except the tracker
         exe_tx.send(ExecutorMessage::Stop {}).unwrap();
         run_tx.send(RunnerMessage::Stop {}).unwrap();
-
-        (run_id, log_tx)
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
     }
 
     #[tokio::test]
-    async fn test_simple_dag_run() {
+    async fn test_cached_task_missing_input() {
         let tasks: TaskSet = serde_json::from_str(
             r#"
             {
-                "simple_task": {
-                    "details": {
-                        "command": [ "/bin/echo", "hello", "world" ],
-                        "env": {
-                            "ENVVAR": 16
-                        }
-                    },
-                    "children": [ "other_task" ]
+                "extract": {
+                    "cache": true,
+                    "inputs": [ "./runner_cache_test.missing" ],
+                    "details": { "command": [ "/bin/echo", "extract" ] },
+                    "children": [ "report" ]
                 },
-                "other_task": {
-                    "details": {
-                        "command": [ "/bin/echo", "task", "$ENVVAR" ],
-                        "env": {
-                            "ENVVAR": 16
-                        }
-                    }
-                }
+                "report": { "details": { "command": [ "/bin/echo", "report" ] } }
             }"#,
         )
         .unwrap();
 
-        let parameters = HashMap::new();
-
-        let (run_id, log_tx) = run(&tasks, &parameters).await;
-
-        for (task_id, task) in tasks {
-            let (tx, rx) = oneshot::channel();
-            log_tx
-                .send(TrackerMessage::GetTask {
-                    run_id,
-                    task_id: task_id.clone(),
-                    response: tx,
-                })
-                .unwrap();
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
 
-            let task_record = rx.await.unwrap().unwrap();
-            assert_eq!(task, task_record.task);
-            assert_eq!(task_record.attempts.len(), 1);
-            assert_eq!(
-                task_record.state_changes.last().unwrap().state,
-                State::Completed
-            );
-        }
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        let attempt = &records["extract"].attempts[0];
+        assert!(!attempt.succeeded);
+        assert!(attempt.executor[0].contains("Unable to read input"));
+        assert!(records["report"].attempts.is_empty());
 
-        // Close off tracker
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
         log_tx.send(TrackerMessage::Stop {}).unwrap();
     }
 
     #[tokio::test]
-    async fn test_dag_with_expansion() {
+    async fn test_mark_waiting_task() {
         let tasks: TaskSet = serde_json::from_str(
             r#"
             {
-                "A": {
+                "first": {
                     "details": {
-                        "command": [ "/bin/echo", "A" ]
+                        "command": [ "/bin/sleep", "1" ]
                     },
-                    "children": [ "B" ]
+                    "children": [ "second" ]
                 },
-                "B": {
+                "second": {
                     "details": {
-                        "command": [ "/bin/echo", "B", "DATE" ]
+                        "command": [ "/bin/echo", "second" ]
                     },
-                    "children": [ "C" ]
+                    "children": [ "third" ]
                 },
-                "C": {
+                "third": {
                     "details": {
-                        "command": [ "/bin/echo", "NAME" ]
-                    },
-                    "parameters": {
-                        "NAME": [ "ABCD", "DEFG" ]
+                        "command": [ "/bin/echo", "third" ]
                     }
                 }
             }"#,
         )
         .unwrap();
 
-        let parameters: Parameters = serde_json::from_str(
-            r#"{
-                "DATE": [ "20200101", "20200102", "20200103" ]
-                }"#,
-        )
-        .unwrap();
-
-        let (run_id, log_tx) = run(&tasks, &parameters).await;
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
 
+        // Skip a task that's still waiting on its parent
         let (tx, rx) = oneshot::channel();
-        log_tx
-            .send(TrackerMessage::GetRun {
+        run_tx
+            .send(RunnerMessage::MarkTask {
                 run_id,
+                task_id: "second".to_owned(),
+                state: State::Skipped,
+                actor: None,
+                reason: None,
+                tracker: log_tx.clone(),
                 response: tx,
             })
             .unwrap();
+        rx.await.unwrap().unwrap();
 
-        let rec = rx.await.unwrap().unwrap();
-        assert_eq!(rec.parameters, parameters);
+        let terminal = [State::Completed, State::Errored];
         assert_eq!(
-            rec.tasks.len(),
-            1 + parameters["DATE"].len() + 2 + tasks["C"].parameters["NAME"].len() + 2
-        ); // 5 actual tasks, 2 control tasks
-        assert_eq!(rec.state_changes.last().unwrap().state, State::Completed);
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
 
-        // Close off tracker
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert_eq!(records["first"].attempts.len(), 1);
+        assert!(records["second"].attempts.is_empty());
+        assert_eq!(records["third"].attempts.len(), 1);
+
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
         log_tx.send(TrackerMessage::Stop {}).unwrap();
     }
 
     #[tokio::test]
-    async fn test_failing_generating_dag_run() {
+    async fn test_rerun_inactive_task() {
         let tasks: TaskSet = serde_json::from_str(
-            r#"{
-                "simple_task": {
+            r#"
+            {
+                "first": {
                     "details": {
-                        "command": [ "/bin/echo", "hello", "world" ],
-                        "env": {
-                            "ENVVAR": 16
-                        }
+                        "command": [ "/bin/echo", "first" ]
                     },
-                    "children": [ "other_task" ],
-                    "is_generator": true
+                    "children": [ "second" ]
                 },
-                "other_task": {
+                "second": {
                     "details": {
-                        "command": [ "/bin/echo", "task", "$ENVVAR" ],
-                        "env": {
-                            "ENVVAR": 16
-                        }
+                        "command": [ "/bin/echo", "second" ]
+                    },
+                    "children": [ "third" ]
+                },
+                "third": {
+                    "details": {
+                        "command": [ "/bin/echo", "third" ]
                     }
                 }
             }"#,
         )
         .unwrap();
 
-        let parameters = HashMap::new();
-
-        let (run_id, log_tx) = run(&tasks, &parameters).await;
-
-        for (task_id, task) in tasks {
-            let (tx, rx) = oneshot::channel();
-            log_tx
-                .send(TrackerMessage::GetTask {
-                    run_id,
-                    task_id: task_id.clone(),
-                    response: tx,
-                })
-                .unwrap();
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
 
-            let task_record = rx.await.unwrap().unwrap();
-            assert_eq!(task, task_record.task);
+        // The run is rebuilt from the tracker, restoring completed tasks in
+        // any order
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::RerunTask {
+                run_id,
+                task_id: "third".to_owned(),
+                descendants: false,
+                actor: None,
+                reason: None,
+                tracker: log_tx.clone(),
+                executor: exe_tx.clone(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Completed
+        );
 
-            match task_id.as_ref() {
-                "simple_task" => {
-                    assert_eq!(task_record.state_changes.len(), 3);
-                    assert_eq!(
-                        task_record.state_changes.last().unwrap().state,
-                        State::Errored
-                    );
-                }
-                "other_task" => {
-                    assert_eq!(task_record.state_changes.len(), 1);
-                    assert_eq!(
-                        task_record.state_changes.last().unwrap().state,
-                        State::Queued
-                    );
-                }
-                _ => {}
-            }
-        }
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTasks {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        let records = rx.await.unwrap().unwrap();
+        assert_eq!(records["first"].attempts.len(), 1);
+        assert_eq!(records["second"].attempts.len(), 1);
+        assert_eq!(records["third"].attempts.len(), 2);
 
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
         log_tx.send(TrackerMessage::Stop {}).unwrap();
     }
 
     #[tokio::test]
-    async fn test_successful_generating_dag_run() {
-        use serde_json::json;
-
-        let mut tasks: TaskSet = serde_json::from_str(
-            r#"{
-                "other_task": {
+    async fn test_retry_held_while_paused() {
+        let tasks: TaskSet = serde_json::from_str(
+            r#"
+            {
+                "flaky_task": {
                     "details": {
-                        "command": [ "/bin/echo", "task", "$ENVVAR" ]
-                    }
+                        "command": [ "/bin/sh", "-c", "sleep 0.5; exit 1" ]
+                    },
+                    "max_retries": 1
                 }
             }"#,
         )
         .unwrap();
 
-        let new_task = r#"{
-            "generated_task": {
-                "details": {
-                    "command": [ "/bin/echo", "hello", "world" ]
-                }
-            }
-        }"#;
+        let (run_id, log_tx, exe_tx, run_tx) = start_run(tasks).await;
 
-        let parameters = HashMap::new();
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Pause {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
 
-        let task_id = "fancy_generator".to_owned();
+        // The held retry shows up as pending, not as a failure
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTask {
+                run_id,
+                task_id: "flaky_task".to_owned(),
+                response: tx,
+            })
+            .unwrap();
+        let record = rx.await.unwrap().unwrap();
+        let states: Vec<State> = record.state_changes.iter().map(|x| x.state).collect();
+        assert_eq!(
+            states,
+            vec![State::Queued, State::Running, State::Errored, State::Queued]
+        );
 
-        let mut gen_task = Task::new();
-        gen_task.is_generator = true;
-        gen_task.children.push("other_task".to_owned());
-        gen_task.details = json!({
-            "command": [ "/bin/echo", new_task ]
-        });
+        let (tx, rx) = oneshot::channel();
+        run_tx
+            .send(RunnerMessage::Resume {
+                run_id,
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
 
-        tasks.insert(task_id, gen_task);
+        let terminal = [State::Completed, State::Errored];
+        assert_eq!(
+            wait_for_run(run_id, &log_tx, &terminal).await,
+            State::Errored
+        );
 
-        // This will run the tasks and process the generators
-        let (run_id, log_tx) = run(&tasks, &parameters).await;
+        exe_tx.send(ExecutorMessage::Stop {}).unwrap();
+        run_tx.send(RunnerMessage::Stop {}).unwrap();
+        log_tx.send(TrackerMessage::Stop {}).unwrap();
+    }
 
-        // Make tasks match the expected output
-        let mut new_tasks: TaskSet = serde_json::from_str(new_task).unwrap();
-        for (task_id, task) in &mut new_tasks {
-            task.children.push("other_task".to_owned());
-            task.parents.push("fancy_generator".to_owned());
-            tasks.insert(task_id.clone(), task.clone());
-        }
-        assert_eq!(tasks.len(), 3);
+    #[tokio::test]
+    async fn test_task_environment() {
+        use serde_json::json;
 
-        for (task_id, task) in tasks {
-            let (tx, rx) = oneshot::channel();
-            log_tx
-                .send(TrackerMessage::GetTask {
-                    run_id,
-                    task_id,
-                    response: tx,
-                })
-                .unwrap();
+        // Fails on the first attempt, succeeds on the second
+        let mut tasks = TaskSet::new();
+        let mut task = Task::new();
+        task.details = json!({
+            "command": [
+                "/bin/sh", "-c",
+                "echo \"$DAGGY_TASK_ID $DAGGY_ATTEMPT $DAGGY_RUN_ID\"; [ \"$DAGGY_ATTEMPT\" = 2 ]"
+            ],
+            "environment": { "DAGGY_RUN_ID": "mine" }
+        });
+        task.max_retries = 1;
+        tasks.insert("env_task".to_owned(), task);
 
-            let task_record = rx.await.unwrap().unwrap();
-            assert_eq!(task, task_record.task);
+        let (run_id, log_tx) = run(&tasks, &HashMap::new()).await;
 
-            assert_eq!(task_record.state_changes.len(), 3);
-            assert_eq!(
-                task_record.state_changes.last().unwrap().state,
-                State::Completed
-            );
-        }
+        let (tx, rx) = oneshot::channel();
+        log_tx
+            .send(TrackerMessage::GetTask {
+                run_id,
+                task_id: "env_task".to_owned(),
+                response: tx,
+            })
+            .unwrap();
+        let task_record = rx.await.unwrap().unwrap();
+        let outputs: Vec<&str> = task_record
+            .attempts
+            .iter()
+            .map(|attempt| attempt.output.as_str())
+            .collect();
+        // Variables set by the task take precedence
+        assert_eq!(outputs, vec!["env_task 1 mine\n", "env_task 2 mine\n"]);
 
-        // Close off everything
         log_tx.send(TrackerMessage::Stop {}).unwrap();
     }
 
@@ -0,0 +1,318 @@
+use super::Result;
+use chrono::format::{Item, StrftimeItems};
+use chrono::prelude::*;
+use chrono::Duration;
+use std::str::FromStr;
+
+/// The furthest ahead to look for the next slot of a schedule, so schedules
+/// that can never match, like `0 0 30 2 *`, don't loop forever
+const MAX_LOOKAHEAD_DAYS: i64 = 366 * 5;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// The values allowed for one field of a cron expression, as a bit set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Field(u64);
+
+impl Field {
+    fn contains(self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+
+    /// Parses a field made of comma separated `*`, `N` or `N-M` items, each
+    /// optionally followed by a `/STEP`
+    fn parse(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Self> {
+        let value = |text: &str| -> Result<u32> {
+            let lower = text.to_lowercase();
+            let value = match names.iter().position(|name| *name == lower) {
+                // Names line up with the first value of the field
+                Some(pos) => u32::try_from(pos)? + min,
+                None => text
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid value {text:?}"))?,
+            };
+            if value < min || value > max {
+                return Err(anyhow!("{value} is outside of {min}-{max}"));
+            }
+            Ok(value)
+        };
+
+        let mut bits = 0u64;
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>()?),
+                None => (item, 1),
+            };
+            if step == 0 {
+                return Err(anyhow!("Step in {item:?} must be positive"));
+            }
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((start, end)) => (value(start)?, value(end)?),
+                    // A single value with a step runs to the end of the range
+                    None if item.contains('/') => (value(range)?, max),
+                    None => {
+                        let value = value(range)?;
+                        (value, value)
+                    }
+                },
+            };
+            if start > end {
+                return Err(anyhow!("Range {range:?} is backwards"));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(Field(bits))
+    }
+}
+
+/// A cron schedule of the form `MINUTE HOUR DAY_OF_MONTH MONTH DAY_OF_WEEK`,
+/// evaluated in UTC. Months and days of the week can be given by their
+/// three letter names, and Sunday is both 0 and 7. As with cron, if both the
+/// day of the month and day of the week are restricted, a day matching
+/// either is used.
+///
+/// The shortcuts `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+/// are also accepted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let spec = match spec.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            spec => spec,
+        };
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(anyhow!(
+                "Expected 5 fields in schedule {spec:?}, found {}",
+                fields.len()
+            ));
+        };
+
+        let field = |name: &str, text: &str, min, max, names: &[&str]| {
+            Field::parse(text, min, max, names)
+                .map_err(|e| anyhow!("Invalid {name} {text:?} in schedule {spec:?}: {e}"))
+        };
+        let mut weekday_field = field("day of week", weekdays, 0, 7, &WEEKDAY_NAMES)?;
+        if weekday_field.contains(7) {
+            weekday_field.0 |= 1;
+        }
+
+        Ok(Schedule {
+            minutes: field("minute", minutes, 0, 59, &[])?,
+            hours: field("hour", hours, 0, 23, &[])?,
+            days: field("day of month", days, 1, 31, &[])?,
+            months: field("month", months, 1, 12, &MONTH_NAMES)?,
+            weekdays: weekday_field,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+}
+
+impl Schedule {
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days.contains(date.day());
+        let weekday = self
+            .weekdays
+            .contains(date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            (false, true) => day,
+            (true, false) => weekday,
+            (true, true) => true,
+        }
+    }
+
+    /// Returns the first slot of the schedule strictly after `after`, or
+    /// `None` if there isn't one in the next few years
+    #[must_use]
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.naive_utc().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = start + Duration::days(MAX_LOOKAHEAD_DAYS);
+
+        let mut time = start;
+        while time < limit {
+            let date = time.date();
+            if !self.months.contains(time.month()) {
+                let next_month = if time.month() == 12 {
+                    NaiveDate::from_ymd_opt(time.year() + 1, 1, 1)
+                } else {
+                    NaiveDate::from_ymd_opt(time.year(), time.month() + 1, 1)
+                };
+                time = next_month?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !self.hours.contains(time.hour()) {
+                time = date.and_hms_opt(time.hour(), 0, 0)? + Duration::hours(1);
+            } else if !self.minutes.contains(time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(Utc.from_utc_datetime(&time));
+            }
+        }
+        None
+    }
+}
+
+/// Parses a step of the form `N(h|d|w)`, e.g. `6h` or `1d`
+/// # Errors
+/// Will return `Err` if the step is malformed or isn't positive
+pub fn parse_step(step: &str) -> Result<Duration> {
+    let step = step.trim();
+    let (amount, unit) = step.split_at(step.len().saturating_sub(1));
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| anyhow!("Invalid step {step:?}, expected Nh, Nd or Nw"))?;
+    let duration = match unit {
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => return Err(anyhow!("Invalid step {step:?}, expected Nh, Nd or Nw")),
+    };
+    if amount <= 0 {
+        return Err(anyhow!("Step {step:?} must be positive"));
+    }
+    duration.ok_or_else(|| anyhow!("Step {step:?} is too large"))
+}
+
+/// Parses a time given either as `YYYY-MM-DD`, taken as midnight UTC, or in
+/// RFC 3339
+/// # Errors
+/// Will return `Err` if the time is in neither format
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN)));
+    }
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| anyhow!("Invalid time {time:?}, expected YYYY-MM-DD or RFC 3339"))
+}
+
+/// Formats a time with a `strftime` style format
+/// # Errors
+/// Will return `Err` if the format is invalid
+pub fn format_time(time: DateTime<Utc>, format: &str) -> Result<String> {
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(anyhow!("Invalid date format {format:?}"));
+    }
+    Ok(time.format_with_items(items.into_iter()).to_string())
+}
+
+/// Splits `[start, end)` into intervals of `step`, returning the start of
+/// each one
+#[must_use]
+pub fn intervals(start: DateTime<Utc>, end: DateTime<Utc>, step: Duration) -> Vec<DateTime<Utc>> {
+    let mut slots = Vec::new();
+    let mut slot = start;
+    while slot < end && step > Duration::zero() {
+        slots.push(slot);
+        slot += step;
+    }
+    slots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> DateTime<Utc> {
+        parse_time(text).unwrap()
+    }
+
+    fn slots(spec: &str, after: &str, count: usize) -> Vec<String> {
+        let schedule: Schedule = spec.parse().unwrap();
+        let mut slots = Vec::new();
+        let mut slot = time(after);
+        for _ in 0..count {
+            slot = schedule.next_after(slot).unwrap();
+            slots.push(slot.format("%Y-%m-%d %H:%M %a").to_string());
+        }
+        slots
+    }
+
+    #[test]
+    fn test_cron_schedules() {
+        assert_eq!(
+            slots("*/20 9-10 * * *", "2022-03-01T10:30:00Z", 3),
+            vec![
+                "2022-03-01 10:40 Tue",
+                "2022-03-02 09:00 Wed",
+                "2022-03-02 09:20 Wed"
+            ]
+        );
+        assert_eq!(
+            slots("@daily", "2022-12-31T00:00:00Z", 2),
+            vec!["2023-01-01 00:00 Sun", "2023-01-02 00:00 Mon"]
+        );
+        assert_eq!(
+            slots("30 2 * FEB,mar mon-fri", "2022-02-25T03:00:00Z", 2),
+            vec!["2022-02-28 02:30 Mon", "2022-03-01 02:30 Tue"]
+        );
+        // Restricting both days matches either, and 7 is Sunday
+        assert_eq!(
+            slots("0 0 13 * 7", "2022-03-01", 3),
+            vec![
+                "2022-03-06 00:00 Sun",
+                "2022-03-13 00:00 Sun",
+                "2022-03-20 00:00 Sun"
+            ]
+        );
+        assert_eq!(
+            slots("0 12 29 2 *", "2022-01-01", 1),
+            vec!["2024-02-29 12:00 Thu"]
+        );
+
+        let never: Schedule = "0 0 30 2 *".parse().unwrap();
+        assert!(never.next_after(time("2022-01-01")).is_none());
+
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("* * * * funday".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn test_intervals() {
+        let slots = intervals(
+            time("2022-01-01"),
+            time("2022-01-02"),
+            parse_step("6h").unwrap(),
+        );
+        assert_eq!(slots.len(), 4);
+        assert_eq!(slots[3], time("2022-01-01T18:00:00Z"));
+        assert_eq!(
+            format_time(slots[1], "%Y%m%d%H").unwrap(),
+            "2022010106".to_owned()
+        );
+
+        assert!(parse_step("0d").is_err());
+        assert!(parse_step("1y").is_err());
+        assert!(parse_step("9999999999999w").is_err());
+        assert!(parse_time("01/02/2022").is_err());
+        assert!(format_time(slots[0], "%Q").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use super::Result;
+use crate::structs::{TaskID, TaskSet};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Named semaphores shared by every run, each limiting how many tasks using
+/// it can run at once.
+#[derive(Clone, Default)]
+pub struct Semaphores {
+    limits: HashMap<String, usize>,
+    semaphores: HashMap<String, Arc<Semaphore>>,
+}
+
+impl Semaphores {
+    #[must_use]
+    pub fn new<S: BuildHasher>(limits: &HashMap<String, usize, S>) -> Self {
+        Semaphores {
+            limits: limits
+                .iter()
+                .map(|(name, limit)| (name.clone(), *limit))
+                .collect(),
+            semaphores: limits
+                .iter()
+                .map(|(name, limit)| (name.clone(), Arc::new(Semaphore::new(*limit))))
+                .collect(),
+        }
+    }
+
+    /// Checks that every semaphore a task uses exists, and that the task
+    /// doesn't need more permits than the semaphore has.
+    /// # Errors
+    /// Will return `Err` naming the first task that can never acquire its
+    /// semaphores
+    pub fn validate(&self, task_id: &TaskID, names: &[String]) -> Result<()> {
+        for name in names {
+            let limit = self
+                .limits
+                .get(name)
+                .ok_or_else(|| anyhow!("Task {task_id} uses unknown semaphore {name}"))?;
+            let needed = names.iter().filter(|n| *n == name).count();
+            if needed > *limit {
+                return Err(anyhow!(
+                    "Task {task_id} needs {needed} permits of semaphore {name}, which only has {limit}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the semaphores used by all of `tasks`
+    /// # Errors
+    /// Will return `Err` if any task fails `validate`
+    pub fn validate_tasks(&self, tasks: &TaskSet) -> Result<()> {
+        for (task_id, task) in tasks {
+            self.validate(task_id, &task.semaphores)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for one permit from each of the named semaphores. Permits are
+    /// released when dropped.
+    /// # Errors
+    /// Will return `Err` if the semaphores aren't valid for the task
+    pub async fn acquire(
+        &self,
+        task_id: &TaskID,
+        names: &[String],
+    ) -> Result<Vec<OwnedSemaphorePermit>> {
+        self.validate(task_id, names)?;
+
+        // Always acquire in the same order, so tasks can't deadlock
+        let mut names = names.to_vec();
+        names.sort();
+
+        let mut permits = Vec::with_capacity(names.len());
+        for name in names {
+            permits.push(self.semaphores[&name].clone().acquire_owned().await?);
+        }
+        Ok(permits)
+    }
+}
@@ -0,0 +1,106 @@
+use super::Result;
+use crate::messages::TrackerMessage;
+use crate::structs::{ExternalDependency, SensorCondition, State};
+use crate::utilities::glob_match;
+use std::path::Path;
+use tokio::sync::{mpsc, oneshot};
+
+/// Checks a sensor's condition once
+/// # Errors
+/// Will return `Err` if the condition can't be checked, e.g. a command that
+/// can't be started
+pub async fn poke(
+    condition: &SensorCondition,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+) -> Result<bool> {
+    match condition {
+        SensorCondition::File(pattern) => file_exists(pattern).await,
+        SensorCondition::Http(url) => {
+            // Connection errors just mean the endpoint isn't up yet
+            Ok(reqwest::get(url)
+                .await
+                .is_ok_and(|res| res.status() == reqwest::StatusCode::OK))
+        }
+        SensorCondition::Command(command) => {
+            let status = tokio::process::Command::new(&command[0])
+                .args(&command[1..])
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .status()
+                .await
+                .map_err(|e| anyhow!("Unable to run sensor command {command:?}: {e}"))?;
+            Ok(status.success())
+        }
+        SensorCondition::Task(dependency) => task_completed(dependency, tracker).await,
+    }
+}
+
+/// Returns true if a file matching `pattern` exists. Wildcards are only
+/// supported in the file name.
+async fn file_exists(pattern: &str) -> Result<bool> {
+    if !pattern.contains(['*', '?']) {
+        return Ok(tokio::fs::metadata(pattern).await.is_ok());
+    }
+
+    let path = Path::new(pattern);
+    let name_pattern = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("Invalid file pattern {pattern}"))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return Ok(false);
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str() {
+            if glob_match(name_pattern, name) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Returns true if the task in the other run has completed
+async fn task_completed(
+    dependency: &ExternalDependency,
+    tracker: &mpsc::UnboundedSender<TrackerMessage>,
+) -> Result<bool> {
+    let run_id = if let Some(run_id) = dependency.run_id {
+        run_id
+    } else {
+        let (response, rx) = oneshot::channel();
+        tracker.send(TrackerMessage::GetRuns {
+            tags: Some(dependency.tags.clone()),
+            states: None,
+            start_time: None,
+            end_time: None,
+            response,
+        })?;
+        let latest = rx
+            .await??
+            .into_iter()
+            .max_by_key(|run| (run.start_time, run.run_id));
+        match latest {
+            Some(run) => run.run_id,
+            None => return Ok(false),
+        }
+    };
+
+    let (response, rx) = oneshot::channel();
+    tracker.send(TrackerMessage::GetTask {
+        run_id,
+        task_id: dependency.task_id.clone(),
+        response,
+    })?;
+    // The run may not have the task yet, e.g. if a generator adds it
+    Ok(rx.await?.is_ok_and(|record| {
+        record.state_changes.last().map(|change| change.state) == Some(State::Completed)
+    }))
+}
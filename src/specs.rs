@@ -0,0 +1,445 @@
+use super::Result;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Fields of a run spec holding tasks that `defaults` and `templates` apply to
+const TASK_SETS: [&str; 4] = ["tasks", "finally", "on_success", "on_failure"];
+
+/// The formats run specifications can be written in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl SpecFormat {
+    /// Picks the format from a `Content-Type` header. A missing header is
+    /// treated as JSON.
+    /// # Errors
+    /// Will return `Err` if the content type isn't a supported format
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Self> {
+        let Some(content_type) = content_type else {
+            return Ok(SpecFormat::Json);
+        };
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        match mime.as_str() {
+            "application/json" => Ok(SpecFormat::Json),
+            "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => {
+                Ok(SpecFormat::Yaml)
+            }
+            "application/toml" | "text/toml" => Ok(SpecFormat::Toml),
+            _ => Err(anyhow!("Unsupported Content-Type {content_type}")),
+        }
+    }
+
+    /// Picks the format from a file's extension, treating anything that isn't
+    /// YAML or TOML as JSON
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => SpecFormat::Yaml,
+            Some("toml") => SpecFormat::Toml,
+            _ => SpecFormat::Json,
+        }
+    }
+}
+
+/// Parses a spec written in `format`
+/// # Errors
+/// Will return `Err` with the line and column of the problem if the spec
+/// can't be parsed
+pub fn parse<T: DeserializeOwned>(spec: &str, format: SpecFormat) -> Result<T> {
+    match format {
+        SpecFormat::Json => serde_json::from_str(spec).map_err(|e| anyhow!("Invalid JSON: {e}")),
+        SpecFormat::Yaml => serde_yaml::from_str(spec).map_err(|e| anyhow!("Invalid YAML: {e}")),
+        SpecFormat::Toml => toml::from_str(spec).map_err(|e| match e.span() {
+            Some(span) => {
+                let (line, column) = line_and_column(spec, span.start);
+                anyhow!(
+                    "Invalid TOML: {} at line {line} column {column}",
+                    e.message()
+                )
+            }
+            None => anyhow!("Invalid TOML: {}", e.message()),
+        }),
+    }
+}
+
+/// Parses a run spec, applying its `defaults` and `templates` to its tasks.
+/// Tasks pick a template with `extends`, and templates can extend each
+/// other. Objects are merged recursively, so a task only needs to give the
+/// parts of its `details` that differ, while any other value replaces the
+/// one it's merged over.
+/// # Errors
+/// Will return `Err` if the spec can't be parsed, or refers to an unknown
+/// template
+pub fn parse_run<T: DeserializeOwned>(spec: &str, format: SpecFormat) -> Result<T> {
+    let mut value: Value = parse(spec, format)?;
+    if !apply_templates(&mut value)? {
+        // Parsing the text directly gives errors their positions
+        return parse(spec, format);
+    }
+    T::deserialize(&value).map_err(|e| {
+        // Errors from a value have no position, so find where the merged
+        // spec fails when written out instead
+        let pretty = serde_json::to_string_pretty(&value).unwrap_or_default();
+        match serde_json::from_str::<T>(&pretty) {
+            Err(located) if located.line() > 0 => {
+                anyhow!(
+                    "Invalid spec: {e} at {}",
+                    path_at_line(&pretty, located.line())
+                )
+            }
+            _ => anyhow!("Invalid spec: {e}"),
+        }
+    })
+}
+
+/// Reads a run spec from a file, picking its format from its extension
+/// # Errors
+/// Will return `Err` if the file can't be read or parsed
+pub fn read_run<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let spec = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to read {}: {e}", path.display()))?;
+    parse_run(&spec, SpecFormat::from_path(path))
+        .map_err(|e| anyhow!("Unable to parse {}: {e}", path.display()))
+}
+
+/// Applies the `defaults` and `templates` of a run spec to its tasks,
+/// returning false if it has neither
+fn apply_templates(spec: &mut Value) -> Result<bool> {
+    let Some(spec) = spec.as_object_mut() else {
+        return Ok(false);
+    };
+    let defaults = spec.remove("defaults");
+    let templates = match spec.remove("templates") {
+        Some(Value::Object(templates)) => Some(templates),
+        Some(_) => return Err(anyhow!("templates must be a map of names to tasks")),
+        None => None,
+    };
+    if defaults.is_none() && templates.is_none() {
+        return Ok(false);
+    }
+    let defaults = defaults.unwrap_or_else(|| Value::Object(Map::new()));
+    let templates = templates.unwrap_or_default();
+
+    for field in TASK_SETS {
+        let Some(tasks) = spec.get_mut(field).and_then(Value::as_object_mut) else {
+            continue;
+        };
+        for (task_id, task) in tasks.iter_mut() {
+            let resolved = resolve_template(task_id, task.take(), &templates)?;
+            *task = defaults.clone();
+            merge(task, resolved);
+        }
+    }
+    Ok(true)
+}
+
+/// Merges a task over the templates it extends
+fn resolve_template(task_id: &str, task: Value, templates: &Map<String, Value>) -> Result<Value> {
+    let mut chain = vec![task];
+    let mut seen = HashSet::new();
+    while let Some(name) = extends(chain.last().unwrap())? {
+        if !seen.insert(name.clone()) {
+            return Err(anyhow!(
+                "Templates used by {task_id} form a cycle at {name}"
+            ));
+        }
+        let template = templates
+            .get(&name)
+            .ok_or_else(|| anyhow!("Task {task_id} extends unknown template {name}"))?;
+        chain.push(template.clone());
+    }
+
+    let mut resolved = chain.pop().unwrap();
+    while let Some(next) = chain.pop() {
+        merge(&mut resolved, next);
+    }
+    if let Some(task) = resolved.as_object_mut() {
+        task.remove("extends");
+    }
+    Ok(resolved)
+}
+
+fn extends(task: &Value) -> Result<Option<String>> {
+    match task.get("extends") {
+        None => Ok(None),
+        Some(Value::String(name)) => Ok(Some(name.clone())),
+        Some(_) => Err(anyhow!("extends must be the name of a template")),
+    }
+}
+
+/// Recursively merges `over` into `base`. Objects are merged key by key,
+/// anything else in `over` replaces what's in `base`.
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Gives the path of keys leading to a one-based line of pretty-printed
+/// JSON, such as `tasks.train.max_retries`
+fn path_at_line(pretty: &str, line: usize) -> String {
+    let mut path: Vec<(usize, String)> = Vec::new();
+    for text in pretty.lines().take(line) {
+        let trimmed = text.trim_start();
+        let indent = text.len() - trimmed.len();
+        let closing = trimmed.starts_with('}') || trimmed.starts_with(']');
+        path.retain(|(depth, _)| *depth < indent || (closing && *depth == indent));
+        let key = trimmed
+            .split_once("\": ")
+            .and_then(|(key, _)| serde_json::from_str::<String>(&format!("{key}\"")).ok());
+        if let Some(key) = key {
+            path.push((indent, key));
+        }
+    }
+    if path.is_empty() {
+        return "the top level".to_owned();
+    }
+    path.into_iter()
+        .map(|(_, key)| key)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Converts a byte offset into a one-based line and column
+fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{RunSpec, TaskSet};
+
+    #[test]
+    fn test_formats_are_equivalent() {
+        let json: TaskSet = parse(
+            r#"
+            {
+                "extract": {
+                    "details": { "command": [ "/bin/echo", "hello" ] },
+                    "max_retries": 2
+                },
+                "load": {
+                    "details": { "command": [ "/bin/true" ] },
+                    "parents": [ "extract" ]
+                }
+            }"#,
+            SpecFormat::Json,
+        )
+        .unwrap();
+
+        let yaml: TaskSet = parse(
+            r"
+# Comments are allowed
+extract:
+  details:
+    command: [ /bin/echo, hello ]
+  max_retries: 2
+load:
+  details:
+    command:
+      - /bin/true
+  parents: [ extract ]
+",
+            SpecFormat::Yaml,
+        )
+        .unwrap();
+
+        let toml: TaskSet = parse(
+            r#"
+# Comments are allowed
+[extract]
+max_retries = 2
+details.command = [ "/bin/echo", "hello" ]
+
+[load]
+parents = [ "extract" ]
+details.command = [ "/bin/true" ]
+"#,
+            SpecFormat::Toml,
+        )
+        .unwrap();
+
+        assert_eq!(json, yaml);
+        assert_eq!(json, toml);
+    }
+
+    #[test]
+    fn test_templates() {
+        let spec: Value = parse_run(
+            r"
+defaults:
+  max_retries: 1
+  details:
+    resources: { cores: 1, memory_mb: 100 }
+    environment: { STAGE: prod }
+templates:
+  python:
+    details:
+      command: [ python3, job.py ]
+      resources: { memory_mb: 2048 }
+  big_python:
+    extends: python
+    max_retries: 3
+    details:
+      resources: { cores: 8 }
+tasks:
+  plain:
+    details:
+      command: [ /bin/true ]
+  train:
+    extends: big_python
+    details:
+      environment: { MODEL: large }
+finally:
+  cleanup:
+    extends: python
+",
+            SpecFormat::Yaml,
+        )
+        .unwrap();
+
+        assert!(spec.get("defaults").is_none());
+        assert!(spec.get("templates").is_none());
+        assert_eq!(
+            spec["tasks"]["plain"],
+            serde_json::json!({
+                "max_retries": 1,
+                "details": {
+                    "command": [ "/bin/true" ],
+                    "resources": { "cores": 1, "memory_mb": 100 },
+                    "environment": { "STAGE": "prod" }
+                }
+            })
+        );
+        assert_eq!(
+            spec["tasks"]["train"],
+            serde_json::json!({
+                "max_retries": 3,
+                "details": {
+                    "command": [ "python3", "job.py" ],
+                    "resources": { "cores": 8, "memory_mb": 2048 },
+                    "environment": { "STAGE": "prod", "MODEL": "large" }
+                }
+            })
+        );
+        assert_eq!(
+            spec["finally"]["cleanup"]["details"]["resources"]["memory_mb"],
+            2048
+        );
+
+        let err = parse_run::<Value>(
+            r#"{ "templates": {}, "tasks": { "a": { "extends": "nope" } } }"#,
+            SpecFormat::Json,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown template nope"));
+
+        let err = parse_run::<Value>(
+            r#"{
+                "templates": { "x": { "extends": "y" }, "y": { "extends": "x" } },
+                "tasks": { "a": { "extends": "x" } }
+            }"#,
+            SpecFormat::Json,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("form a cycle"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse::<TaskSet>("{\n  \"a\": {\n    \"details\": }\n}", SpecFormat::Json)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 3 column 16"), "{err}");
+
+        let err = parse::<TaskSet>("a:\n  details: {}\n  parents: 3\n", SpecFormat::Yaml)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 3 column 12"), "{err}");
+
+        let err = parse::<TaskSet>("[a]\ndetails = {}\nparents = 3\n", SpecFormat::Toml)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 3 column 11"), "{err}");
+    }
+
+    #[test]
+    fn test_template_errors_have_paths() {
+        let err = parse_run::<RunSpec>(
+            r"
+templates:
+  python:
+    details:
+      command: [ python3, job.py ]
+tasks:
+  plain:
+    details:
+      command: [ /bin/true ]
+  train:
+    extends: python
+    max_retries: lots
+",
+            SpecFormat::Yaml,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("at tasks.train.max_retries"), "{err}");
+
+        let err = parse_run::<RunSpec>(
+            r#"{
+                "defaults": { "max_retries": 1 },
+                "tasks": { "a": { "parents": [ 3 ] } }
+            }"#,
+            SpecFormat::Json,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("at tasks.a.parents"), "{err}");
+    }
+
+    #[test]
+    fn test_format_detection() {
+        assert_eq!(
+            SpecFormat::from_content_type(None).unwrap(),
+            SpecFormat::Json
+        );
+        assert_eq!(
+            SpecFormat::from_content_type(Some("application/yaml; charset=utf-8")).unwrap(),
+            SpecFormat::Yaml
+        );
+        assert_eq!(
+            SpecFormat::from_content_type(Some("application/toml")).unwrap(),
+            SpecFormat::Toml
+        );
+        assert!(SpecFormat::from_content_type(Some("text/plain")).is_err());
+        assert_eq!(
+            SpecFormat::from_path(Path::new("jobs/daily.yml")),
+            SpecFormat::Yaml
+        );
+    }
+}
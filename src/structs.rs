@@ -9,10 +9,13 @@ pub type RunID = usize;
 pub type TaskID = String;
 pub type TaskDetails = serde_json::Value;
 
+/// Small key/value outputs published by a task for its descendants
+pub type TaskOutputs = HashMap<String, String>;
+
 pub type Parameters = HashMap<String, Vec<String>>;
 pub type ExpansionValues = Vec<(String, String)>;
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
 pub struct RunTags(HashMap<String, String>);
 
 impl RunTags {
@@ -53,8 +56,15 @@ impl DerefMut for RunTags {
     }
 }
 
+/// The tag holding the time a scheduled or backfilled run was submitted for.
+/// Date macros in the run's tasks are relative to it instead of the run's
+/// start time.
+pub const SLOT_TAG: &str = "daggyr.slot";
+
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
-pub struct TaskResources(HashMap<String, i64>);
+pub struct TaskResources(
+    #[serde(deserialize_with = "crate::utilities::number_or_string_map")] HashMap<String, i64>,
+);
 
 impl Deref for TaskResources {
     type Target = HashMap<String, i64>;
@@ -118,12 +128,18 @@ pub enum State {
     Errored,
     Completed,
     Killed,
+    Paused,
+    Skipped,
 }
 
 #[derive(Clone, Serialize, Deserialize, Copy, Debug, PartialEq, Hash, Eq)]
 pub enum TaskType {
     Normal,
     Structural,
+    /// Launches a nested run, described by a `SubrunSpec` in the details
+    Subrun,
+    /// Waits for a condition, described by a `SensorSpec` in the details
+    Sensor,
 }
 
 impl Default for TaskType {
@@ -161,6 +177,32 @@ pub struct Task {
     #[serde(default)]
     pub parents: Vec<String>,
 
+    /// Reuse the results of an earlier successful attempt with the same
+    /// details, `inputs` and upstream results, instead of running the task
+    #[serde(default)]
+    pub cache: bool,
+
+    /// Files the task reads, included in its cache key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+
+    /// Files the task produces. The task is skipped if they're all newer
+    /// than its `inputs`, and fails if it doesn't produce them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<String>,
+
+    /// Server-wide semaphores to hold a permit of while the task runs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub semaphores: Vec<String>,
+
+    /// Tasks in other runs that must complete before this one can start
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub external_parents: Vec<ExternalDependency>,
+
+    /// Set on tasks that run after the main DAG ends, rather than in it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hook: Option<HookType>,
+
     pub details: TaskDetails,
 }
 
@@ -173,6 +215,354 @@ impl Task {
 
 pub type TaskSet = HashMap<TaskID, Task>;
 
+/// When a hook task runs, relative to the outcome of the main DAG
+#[derive(Clone, Serialize, Deserialize, Copy, Debug, PartialEq, Hash, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookType {
+    Finally,
+    OnSuccess,
+    OnFailure,
+}
+
+impl HookType {
+    /// Returns true if hooks of this type run after a DAG ending in `outcome`
+    #[must_use]
+    pub fn runs_after(self, outcome: State) -> bool {
+        match self {
+            HookType::Finally => true,
+            HookType::OnSuccess => outcome == State::Completed,
+            HookType::OnFailure => outcome != State::Completed,
+        }
+    }
+}
+
+/// Tasks run once the main DAG of a run has finished. `finally` tasks always
+/// run, `on_success` only if every task completed, and `on_failure` if any
+/// task failed or the run was stopped. Each set can only depend on tasks
+/// within itself, and doesn't affect the final state of the run.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RunHooks {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub finally: TaskSet,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub on_success: TaskSet,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub on_failure: TaskSet,
+}
+
+impl RunHooks {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.finally.is_empty() && self.on_success.is_empty() && self.on_failure.is_empty()
+    }
+
+    /// Adds the hook tasks to `tasks`, marking each with its hook type
+    /// # Errors
+    /// Will return `Err` if a hook task ID is already used, or a hook task
+    /// refers to a task outside its own set
+    pub fn add_to(self, tasks: &mut TaskSet) -> Result<()> {
+        for (hook, set) in [
+            (HookType::Finally, self.finally),
+            (HookType::OnSuccess, self.on_success),
+            (HookType::OnFailure, self.on_failure),
+        ] {
+            for (task_id, task) in &set {
+                if let Some(other) = task
+                    .parents
+                    .iter()
+                    .chain(task.children.iter())
+                    .find(|other| !set.contains_key(*other))
+                {
+                    return Err(anyhow!(
+                        "Hook task {task_id} refers to {other}, which isn't in the same hook"
+                    ));
+                }
+            }
+            for (task_id, mut task) in set {
+                if tasks.contains_key(&task_id) {
+                    return Err(anyhow!("Hook task ID {task_id} is already in use"));
+                }
+                task.hook = Some(hook);
+                tasks.insert(task_id, task);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Selects a sub-graph of an existing run by task ID. Tasks whose IDs
+/// match any of the glob `patterns` are selected, along with all of their
+/// ancestors if `upstream` is set, and all of their descendants if
+/// `downstream` is set.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct TaskSelection {
+    #[serde(default)]
+    pub patterns: Vec<String>,
+
+    #[serde(default)]
+    pub upstream: bool,
+
+    #[serde(default)]
+    pub downstream: bool,
+}
+
+/// Restricts a submitted run to a subset of its tasks. Tasks matching any
+/// `include` glob are kept along with their ancestors, then any task matching
+/// an `exclude` glob is dropped. Tasks downstream of a dropped task still wait
+/// on its kept ancestors. An empty `include` keeps every task.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct TaskFilter {
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl TaskFilter {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// A run submitted to the server. When read with `specs::parse_run`, its
+/// `defaults` and `templates`, if any, are applied to the tasks before it's
+/// deserialized.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RunSpec {
+    #[serde(default)]
+    pub tags: RunTags,
+
+    pub tasks: TaskSet,
+
+    #[serde(default)]
+    pub parameters: Parameters,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+
+    /// Glob patterns of tasks to run, along with their upstream dependencies.
+    /// If empty, all tasks are run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Glob patterns of tasks to leave out of the run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+
+    /// Tasks to run once the main tasks are done
+    #[serde(flatten)]
+    pub hooks: RunHooks,
+}
+
+/// A named run spec stored in the tracker, so runs can be submitted by name.
+/// Storing a spec under an existing name adds a new version of it.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RunTemplate {
+    pub name: String,
+
+    /// Versions start at 1, and aren't reused
+    pub version: usize,
+
+    pub created: DateTime<Utc>,
+
+    pub spec: RunSpec,
+}
+
+/// What a run spec expands into, worked out without starting the run
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct RunPlan {
+    /// The expanded tasks, including hooks
+    pub tasks: TaskSet,
+
+    /// The number of dependencies between the main tasks
+    pub edges: usize,
+
+    /// Main tasks without parents, which start straight away
+    pub roots: Vec<TaskID>,
+
+    /// Main tasks without children
+    pub leaves: Vec<TaskID>,
+
+    /// The number of non-structural tasks on the longest chain of
+    /// dependencies through the main tasks
+    pub critical_path_length: usize,
+
+    /// Why each pool can't run some of the tasks, keyed by pool name. Pools
+    /// that can run every task are left out.
+    pub problems: HashMap<String, Vec<String>>,
+}
+
+/// The details of a `Subrun` task: the run it launches, given either inline,
+/// as the path to a JSON file containing the same fields, or as the name of
+/// a stored run template.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SubrunSpec {
+    #[serde(default)]
+    pub tags: RunTags,
+
+    #[serde(default)]
+    pub tasks: TaskSet,
+
+    #[serde(default)]
+    pub parameters: Parameters,
+
+    #[serde(flatten)]
+    pub hooks: RunHooks,
+
+    /// Path to a file containing the spec, read when the task runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+
+    /// Name of a stored run template, fetched when the task runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// Version of the template to use, defaults to the latest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_version: Option<usize>,
+}
+
+impl SubrunSpec {
+    /// Fields holding the nested run's own tasks, which are left alone when
+    /// the parent run expands its parameters
+    pub const NESTED_FIELDS: &'static [&'static str] =
+        &["tasks", "finally", "on_success", "on_failure"];
+
+    /// Checks that the spec defines some tasks, or refers to either a file or
+    /// a template
+    /// # Errors
+    /// Will return `Err` if the spec is empty, or gives more than one of
+    /// tasks, a file and a template
+    pub fn validate(&self) -> Result<()> {
+        let inline = !(self.tasks.is_empty() && self.hooks.is_empty());
+        let sources = [inline, self.file.is_some(), self.template.is_some()];
+        match sources.iter().filter(|source| **source).count() {
+            0 => Err(anyhow!("Sub-run needs either tasks, a file or a template")),
+            1 if self.template_version.is_some() && self.template.is_none() => {
+                Err(anyhow!("Sub-run has a template version but no template"))
+            }
+            1 => Ok(()),
+            _ => Err(anyhow!(
+                "Sub-run can only have one of tasks, a file or a template"
+            )),
+        }
+    }
+}
+
+/// A task in another run. The run is given either by ID, or by tags, in
+/// which case the most recent run with those tags is used.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ExternalDependency {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<RunID>,
+
+    #[serde(default)]
+    pub tags: RunTags,
+
+    pub task_id: TaskID,
+
+    /// How long to wait for the task to complete, 0 waits indefinitely
+    #[serde(default)]
+    pub timeout_seconds: u64,
+}
+
+/// What a `Sensor` task waits for
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorCondition {
+    /// A file exists. The file name may contain `*` and `?` wildcards.
+    File(String),
+    /// A GET request to the URL returns 200
+    Http(String),
+    /// The command exits successfully
+    Command(Vec<String>),
+    /// A task in another run has completed
+    Task(ExternalDependency),
+}
+
+/// The details of a `Sensor` task. The runner checks the condition every
+/// `poke_interval_seconds` until it holds, or `timeout_seconds` have passed.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SensorSpec {
+    #[serde(flatten)]
+    pub condition: SensorCondition,
+
+    #[serde(default = "default_poke_interval")]
+    pub poke_interval_seconds: u64,
+
+    /// 0 waits indefinitely
+    #[serde(default)]
+    pub timeout_seconds: u64,
+}
+
+fn default_poke_interval() -> u64 {
+    60
+}
+
+impl SensorSpec {
+    /// Checks that the condition can be evaluated
+    /// # Errors
+    /// Will return `Err` if the condition is missing required fields
+    pub fn validate(&self) -> Result<()> {
+        if self.poke_interval_seconds == 0 {
+            return Err(anyhow!("Sensor poke interval must be positive"));
+        }
+        match &self.condition {
+            SensorCondition::Command(command) if command.is_empty() => {
+                Err(anyhow!("Sensor command cannot be empty"))
+            }
+            SensorCondition::Task(dependency)
+                if dependency.run_id.is_none() && dependency.tags.is_empty() =>
+            {
+                Err(anyhow!(
+                    "Dependency on task {} needs either a run ID or tags",
+                    dependency.task_id
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A partial update to the definition of a task. Fields that are `None`
+/// are left unchanged.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct TaskUpdate {
+    #[serde(default)]
+    pub details: Option<TaskDetails>,
+
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+
+    #[serde(default)]
+    pub parents: Option<Vec<TaskID>>,
+
+    #[serde(default)]
+    pub children: Option<Vec<TaskID>>,
+}
+
+impl TaskUpdate {
+    /// Applies the update to `task`
+    pub fn apply(&self, task: &mut Task) {
+        if let Some(details) = &self.details {
+            task.details.clone_from(details);
+        }
+        if let Some(max_retries) = self.max_retries {
+            task.max_retries = max_retries;
+        }
+        if let Some(parents) = &self.parents {
+            task.parents.clone_from(parents);
+        }
+        if let Some(children) = &self.children {
+            task.children.clone_from(children);
+        }
+    }
+}
+
 #[test]
 fn test_task_deserialization() {
     let data = r#"
@@ -233,6 +623,32 @@ pub struct TaskAttempt {
 
     #[serde(default)]
     pub max_rss: u64,
+
+    /// Outputs published by the task during this attempt
+    #[serde(default)]
+    pub outputs: TaskOutputs,
+
+    /// Tasks written by a generator to its generator file. This is consumed
+    /// by the runner, and not kept in the attempt's record.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub generated_tasks: String,
+
+    /// Identifies the inputs of a cached task, so later runs can reuse the
+    /// results of this attempt
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cache_key: String,
+
+    /// Set when the results were reused from an earlier attempt instead of
+    /// running the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_from: Option<CacheSource>,
+}
+
+/// The task whose attempt a cached result was taken from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheSource {
+    pub run_id: RunID,
+    pub task_id: TaskID,
 }
 
 impl Default for TaskAttempt {
@@ -248,6 +664,10 @@ impl Default for TaskAttempt {
             exit_code: 0i32,
             max_cpu: 0,
             max_rss: 0,
+            outputs: TaskOutputs::new(),
+            generated_tasks: String::new(),
+            cache_key: String::new(),
+            cached_from: None,
         }
     }
 }
@@ -267,6 +687,14 @@ pub struct StateChange {
     )]
     pub datetime: DateTime<Utc>,
     pub state: State,
+
+    /// Who requested the change, if it was made by an operator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+
+    /// Why the operator requested the change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 impl Default for StateChange {
@@ -274,6 +702,8 @@ impl Default for StateChange {
         StateChange {
             datetime: Utc::now(),
             state: State::Queued,
+            actor: None,
+            reason: None,
         }
     }
 }
@@ -286,6 +716,18 @@ impl StateChange {
             ..StateChange::default()
         }
     }
+
+    /// A state change requested by an operator, rather than one arising
+    /// from the normal progress of a run.
+    #[must_use]
+    pub fn with_actor(state: State, actor: Option<String>, reason: Option<String>) -> Self {
+        StateChange {
+            state,
+            actor,
+            reason,
+            ..StateChange::default()
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -299,6 +741,14 @@ pub struct TaskRecord {
     pub task: Task,
     pub attempts: Vec<TaskAttempt>,
     pub state_changes: Vec<StateChange>,
+
+    /// Outputs from the most recent successful attempt
+    #[serde(default)]
+    pub outputs: TaskOutputs,
+
+    /// Runs launched by a sub-run task, one per attempt
+    #[serde(default)]
+    pub subruns: Vec<RunID>,
 }
 
 impl TaskRecord {
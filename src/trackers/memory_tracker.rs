@@ -1,13 +1,16 @@
 use crate::messages::TrackerMessage;
 use crate::structs::{
-    DateTime, HashMap, HashSet, Parameters, RunID, RunRecord, RunSummary, RunTags, State,
-    StateChange, Task, TaskAttempt, TaskID, TaskRecord, TaskSet, TaskSummary, Utc,
+    CacheSource, DateTime, HashMap, HashSet, Parameters, RunID, RunRecord, RunSpec, RunSummary,
+    RunTags, RunTemplate, State, StateChange, Task, TaskAttempt, TaskID, TaskRecord, TaskSet,
+    TaskSummary, Utc,
 };
 use crate::Result;
 use tokio::sync::mpsc;
 use TrackerMessage::{
-    AddTasks, CreateRun, GetRun, GetRuns, GetState, GetStateUpdates, GetTask, GetTaskSummary,
-    GetTasks, LogTaskAttempt, Stop, UpdateState, UpdateTask, UpdateTaskState,
+    AddTasks, CreateRun, DeleteTemplate, GetCachedAttempt, GetRun, GetRuns, GetState,
+    GetStateUpdates, GetTask, GetTaskSummary, GetTasks, GetTemplate, GetTemplates, LinkSubrun,
+    LogTaskAttempt, RecordTaskStateChange, Stop, StoreTemplate, UpdateState, UpdateTask,
+    UpdateTaskState,
 };
 
 pub fn start(msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
@@ -20,11 +23,23 @@ pub fn start(msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
 // message processing if an error occurs.
 struct MemoryTracker {
     runs: Vec<RunRecord>,
+    /// The latest successful attempt for each cache key
+    cache: HashMap<String, (CacheSource, usize)>,
+    /// Every version of each run template, oldest first
+    templates: HashMap<String, Vec<RunTemplate>>,
+    /// The last version used by each template, kept after it's deleted so
+    /// versions aren't reused
+    template_versions: HashMap<String, usize>,
 }
 
 impl MemoryTracker {
     fn new() -> Self {
-        MemoryTracker { runs: Vec::new() }
+        MemoryTracker {
+            runs: Vec::new(),
+            cache: HashMap::new(),
+            templates: HashMap::new(),
+            template_versions: HashMap::new(),
+        }
     }
 
     fn range_checker(&self, run_id: RunID, task_id: &TaskID) -> Result<()> {
@@ -80,13 +95,33 @@ impl MemoryTracker {
     }
 
     fn update_task_state(&mut self, run_id: RunID, task_id: &TaskID, state: State) -> Result<()> {
+        self.record_task_state_change(run_id, task_id, StateChange::new(state))
+    }
+
+    fn record_task_state_change(
+        &mut self,
+        run_id: RunID,
+        task_id: &TaskID,
+        change: StateChange,
+    ) -> Result<()> {
         self.range_checker(run_id, task_id)?;
         self.runs[run_id]
             .tasks
             .get_mut(task_id)
             .unwrap()
             .state_changes
-            .push(StateChange::new(state));
+            .push(change);
+        Ok(())
+    }
+
+    fn link_subrun(&mut self, run_id: RunID, task_id: &TaskID, subrun_id: RunID) -> Result<()> {
+        self.range_checker(run_id, task_id)?;
+        self.runs[run_id]
+            .tasks
+            .get_mut(task_id)
+            .unwrap()
+            .subruns
+            .push(subrun_id);
         Ok(())
     }
 
@@ -97,15 +132,28 @@ impl MemoryTracker {
         attempt: &TaskAttempt,
     ) -> Result<()> {
         self.range_checker(run_id, task_id)?;
-        self.runs[run_id]
-            .tasks
-            .get_mut(task_id)
-            .unwrap()
-            .attempts
-            .push(attempt.clone());
+        let record = self.runs[run_id].tasks.get_mut(task_id).unwrap();
+        if attempt.succeeded {
+            record.outputs.clone_from(&attempt.outputs);
+            if !attempt.cache_key.is_empty() {
+                let source = CacheSource {
+                    run_id,
+                    task_id: task_id.clone(),
+                };
+                self.cache
+                    .insert(attempt.cache_key.clone(), (source, record.attempts.len()));
+            }
+        }
+        record.attempts.push(attempt.clone());
         Ok(())
     }
 
+    fn get_cached_attempt(&self, cache_key: &str) -> Option<(CacheSource, TaskAttempt)> {
+        let (source, idx) = self.cache.get(cache_key)?;
+        let attempt = self.runs[source.run_id].tasks[&source.task_id].attempts[*idx].clone();
+        Some((source.clone(), attempt))
+    }
+
     fn get_runs(
         &self,
         tags: &Option<RunTags>,
@@ -233,6 +281,47 @@ impl MemoryTracker {
         self.range_checker(run_id, task_id)?;
         Ok(self.runs[run_id].tasks.get(task_id).unwrap().clone())
     }
+
+    fn store_template(&mut self, name: String, spec: RunSpec) -> usize {
+        let version = self.template_versions.entry(name.clone()).or_insert(0);
+        *version += 1;
+        let template = RunTemplate {
+            name: name.clone(),
+            version: *version,
+            created: Utc::now(),
+            spec,
+        };
+        self.templates.entry(name).or_default().push(template);
+        *version
+    }
+
+    fn get_template(&self, name: &str, version: Option<usize>) -> Result<RunTemplate> {
+        let versions = self
+            .templates
+            .get(name)
+            .ok_or_else(|| anyhow!("No template named {name}"))?;
+        let template = match version {
+            Some(version) => versions.iter().find(|t| t.version == version),
+            None => versions.last(),
+        };
+        template
+            .cloned()
+            .ok_or_else(|| anyhow!("Template {name} has no version {version:?}"))
+    }
+
+    fn get_templates(&self) -> Vec<RunTemplate> {
+        self.templates
+            .values()
+            .filter_map(|versions| versions.last().cloned())
+            .collect()
+    }
+
+    fn delete_template(&mut self, name: &str) -> Result<()> {
+        self.templates
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("No template named {name}"))
+    }
 }
 
 pub async fn start_tracker(mut msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
@@ -286,6 +375,26 @@ pub async fn start_tracker(mut msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
                     .send(tracker.update_task_state(run_id, &task_id, state))
                     .unwrap_or(());
             }
+            RecordTaskStateChange {
+                run_id,
+                task_id,
+                change,
+                response,
+            } => {
+                response
+                    .send(tracker.record_task_state_change(run_id, &task_id, change))
+                    .unwrap_or(());
+            }
+            LinkSubrun {
+                run_id,
+                task_id,
+                subrun_id,
+                response,
+            } => {
+                response
+                    .send(tracker.link_subrun(run_id, &task_id, subrun_id))
+                    .unwrap_or(());
+            }
             LogTaskAttempt {
                 run_id,
                 task_id,
@@ -335,6 +444,38 @@ pub async fn start_tracker(mut msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
                     .send(tracker.get_task(run_id, &task_id))
                     .unwrap_or(());
             }
+            GetCachedAttempt {
+                cache_key,
+                response,
+            } => {
+                response
+                    .send(Ok(tracker.get_cached_attempt(&cache_key)))
+                    .unwrap_or(());
+            }
+            StoreTemplate {
+                name,
+                spec,
+                response,
+            } => {
+                response
+                    .send(Ok(tracker.store_template(name, spec)))
+                    .unwrap_or(());
+            }
+            GetTemplate {
+                name,
+                version,
+                response,
+            } => {
+                response
+                    .send(tracker.get_template(&name, version))
+                    .unwrap_or(());
+            }
+            GetTemplates { response } => {
+                response.send(Ok(tracker.get_templates())).unwrap_or(());
+            }
+            DeleteTemplate { name, response } => {
+                response.send(tracker.delete_template(&name)).unwrap_or(());
+            }
             Stop {} => break,
         }
     }
@@ -375,4 +516,61 @@ mod tests {
         let state_change = rx.await.unwrap().unwrap();
         assert_eq!(State::Queued, state_change.state);
     }
+
+    #[tokio::test]
+    async fn test_memory_templates() {
+        let (trx_tx, trx_rx) = mpsc::unbounded_channel();
+        super::start(trx_rx);
+
+        let store = |parameters: Parameters| {
+            let (tx, rx) = oneshot::channel();
+            let spec = RunSpec {
+                parameters,
+                ..RunSpec::default()
+            };
+            trx_tx
+                .send(TrackerMessage::StoreTemplate {
+                    name: "daily".to_owned(),
+                    spec,
+                    response: tx,
+                })
+                .unwrap();
+            rx
+        };
+        let get = |version: Option<usize>| {
+            let (tx, rx) = oneshot::channel();
+            trx_tx
+                .send(TrackerMessage::GetTemplate {
+                    name: "daily".to_owned(),
+                    version,
+                    response: tx,
+                })
+                .unwrap();
+            rx
+        };
+
+        let first = Parameters::from([("DATE".to_owned(), vec!["1".to_owned()])]);
+        assert_eq!(store(first).await.unwrap().unwrap(), 1);
+        assert_eq!(store(Parameters::new()).await.unwrap().unwrap(), 2);
+
+        let latest = get(None).await.unwrap().unwrap();
+        assert_eq!(latest.version, 2);
+        assert!(latest.spec.parameters.is_empty());
+        let original = get(Some(1)).await.unwrap().unwrap();
+        assert_eq!(original.spec.parameters["DATE"], vec!["1"]);
+        assert!(get(Some(3)).await.unwrap().is_err());
+
+        let (tx, rx) = oneshot::channel();
+        trx_tx
+            .send(TrackerMessage::DeleteTemplate {
+                name: "daily".to_owned(),
+                response: tx,
+            })
+            .unwrap();
+        rx.await.unwrap().unwrap();
+        assert!(get(None).await.unwrap().is_err());
+
+        // Versions carry on after a delete
+        assert_eq!(store(Parameters::new()).await.unwrap().unwrap(), 3);
+    }
 }
@@ -1,13 +1,13 @@
 use crate::messages::TrackerMessage;
 use crate::structs::{
-    DateTime, Deserialize, HashMap, HashSet, Parameters, RunID, RunRecord, RunSummary, RunTags,
-    Serialize, State, StateChange, Task, TaskAttempt, TaskID, TaskRecord, TaskSet, TaskSummary,
-    Utc,
+    CacheSource, DateTime, Deserialize, HashMap, HashSet, Parameters, RunID, RunRecord, RunSpec,
+    RunSummary, RunTags, RunTemplate, Serialize, State, StateChange, Task, TaskAttempt, TaskID,
+    TaskRecord, TaskSet, TaskSummary, Utc,
 };
 use crate::Result;
 use mongodb::{
     bson, bson::doc, options::ClientOptions, options::FindOneAndUpdateOptions,
-    options::FindOneOptions, Client,
+    options::FindOneOptions, options::FindOptions, Client,
 };
 use tokio::sync::mpsc;
 
@@ -63,6 +63,7 @@ struct MongoTracker {
     counters: mongodb::Collection<MongoCounter>,
     runs: mongodb::Collection<MongoRun>,
     tasks: mongodb::Collection<MongoTask>,
+    templates: mongodb::Collection<RunTemplate>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -109,6 +110,7 @@ impl MongoTracker {
         let counters = db.collection::<MongoCounter>("counters");
         let runs = db.collection::<MongoRun>("runs");
         let tasks = db.collection::<MongoTask>("tasks");
+        let templates = db.collection::<RunTemplate>("templates");
 
         MongoTracker {
             client,
@@ -116,6 +118,7 @@ impl MongoTracker {
             counters,
             runs,
             tasks,
+            templates,
         }
     }
 
@@ -194,7 +197,16 @@ impl MongoTracker {
     }
 
     async fn update_task_state(&self, run_id: RunID, task_id: TaskID, state: State) -> Result<()> {
-        let new_state = StateChange::new(state);
+        self.record_task_state_change(run_id, task_id, StateChange::new(state))
+            .await
+    }
+
+    async fn record_task_state_change(
+        &self,
+        run_id: RunID,
+        task_id: TaskID,
+        new_state: StateChange,
+    ) -> Result<()> {
         let filter = doc! {
             "task_id": bson::to_bson(&task_id)?,
             "run_id": bson::to_bson(&run_id)?,
@@ -207,6 +219,19 @@ impl MongoTracker {
         self.tasks.update_one(filter, update, None).await?;
         Ok(())
     }
+    async fn link_subrun(&self, run_id: RunID, task_id: TaskID, subrun_id: RunID) -> Result<()> {
+        let filter = doc! {
+            "task_id": bson::to_bson(&task_id)?,
+            "run_id": bson::to_bson(&run_id)?,
+        };
+        let update = doc! {
+            "$push": {
+                "record.subruns": bson::to_bson(&subrun_id)?
+            }
+        };
+        self.tasks.update_one(filter, update, None).await?;
+        Ok(())
+    }
     async fn log_task_attempt(
         &self,
         run_id: RunID,
@@ -217,11 +242,17 @@ impl MongoTracker {
             "task_id": bson::to_bson(&task_id)?,
             "run_id": bson::to_bson(&run_id)?,
         };
-        let update = doc! {
+        let mut update = doc! {
             "$push": {
                 "record.attempts": bson::to_bson(&attempt)?
             }
         };
+        if attempt.succeeded {
+            update.insert(
+                "$set",
+                doc! { "record.outputs": bson::to_bson(&attempt.outputs)? },
+            );
+        }
         self.tasks.update_one(filter, update, None).await?;
         Ok(())
     }
@@ -452,6 +483,94 @@ impl MongoTracker {
             None => Err(anyhow!(format!("No such task: {:?}", task_id))),
         }
     }
+
+    async fn get_cached_attempt(
+        &self,
+        cache_key: String,
+    ) -> Result<Option<(CacheSource, TaskAttempt)>> {
+        let result = self
+            .tasks
+            .find_one(
+                doc! {
+                    "record.attempts": {
+                        "$elemMatch": { "cache_key": &cache_key, "succeeded": true }
+                    }
+                },
+                FindOneOptions::builder()
+                    .sort(doc! { "run_id": -1 })
+                    .build(),
+            )
+            .await?;
+        Ok(result.and_then(|mtask| {
+            let attempt = mtask
+                .record
+                .attempts
+                .into_iter()
+                .rev()
+                .find(|attempt| attempt.succeeded && attempt.cache_key == cache_key)?;
+            let source = CacheSource {
+                run_id: mtask.run_id,
+                task_id: mtask.task_id,
+            };
+            Some((source, attempt))
+        }))
+    }
+
+    async fn store_template(&self, name: String, spec: RunSpec) -> Result<usize> {
+        // The counter outlives deletes, so versions are never reused
+        let version = self.inc_counter(&format!("template:{name}")).await? + 1;
+        let template = RunTemplate {
+            name,
+            version,
+            created: Utc::now(),
+            spec,
+        };
+        self.templates.insert_one(template, None).await?;
+        Ok(version)
+    }
+
+    async fn get_template(&self, name: String, version: Option<usize>) -> Result<RunTemplate> {
+        let mut filter = doc! { "name": &name };
+        if let Some(version) = version {
+            filter.insert("version", bson::to_bson(&version)?);
+        }
+        let options = FindOneOptions::builder()
+            .sort(doc! { "version": -1 })
+            .build();
+        self.templates
+            .find_one(filter, options)
+            .await?
+            .ok_or_else(|| match version {
+                Some(version) => anyhow!("Template {name} has no version {version}"),
+                None => anyhow!("No template named {name}"),
+            })
+    }
+
+    async fn get_templates(&self) -> Result<Vec<RunTemplate>> {
+        let options = FindOptions::builder()
+            .sort(doc! { "name": 1, "version": -1 })
+            .build();
+        let mut cursor = self.templates.find(None, options).await?;
+        let mut templates: Vec<RunTemplate> = Vec::new();
+        while let Some(template) = cursor.try_next().await? {
+            if templates.last().is_none_or(|t| t.name != template.name) {
+                templates.push(template);
+            }
+        }
+        Ok(templates)
+    }
+
+    async fn delete_template(&self, name: String) -> Result<()> {
+        let result = self
+            .templates
+            .delete_many(doc! { "name": &name }, None)
+            .await?;
+        if result.deleted_count == 0 {
+            Err(anyhow!("No template named {name}"))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 pub async fn start_tracker(
@@ -529,6 +648,32 @@ pub async fn start_tracker(
                         .unwrap_or(());
                 });
             }
+            LinkSubrun {
+                run_id,
+                task_id,
+                subrun_id,
+                response,
+            } => {
+                let t = tracker.clone();
+                tokio::spawn(async move {
+                    response
+                        .send(t.link_subrun(run_id, task_id, subrun_id).await)
+                        .unwrap_or(());
+                });
+            }
+            RecordTaskStateChange {
+                run_id,
+                task_id,
+                change,
+                response,
+            } => {
+                let t = tracker.clone();
+                tokio::spawn(async move {
+                    response
+                        .send(t.record_task_state_change(run_id, task_id, change).await)
+                        .unwrap_or(());
+                });
+            }
             LogTaskAttempt {
                 run_id,
                 task_id,
@@ -605,6 +750,53 @@ pub async fn start_tracker(
                         .unwrap_or(());
                 });
             }
+            GetCachedAttempt {
+                cache_key,
+                response,
+            } => {
+                let t = tracker.clone();
+                tokio::spawn(async move {
+                    response
+                        .send(t.get_cached_attempt(cache_key).await)
+                        .unwrap_or(());
+                });
+            }
+            StoreTemplate {
+                name,
+                spec,
+                response,
+            } => {
+                let t = tracker.clone();
+                tokio::spawn(async move {
+                    response
+                        .send(t.store_template(name, spec).await)
+                        .unwrap_or(());
+                });
+            }
+            GetTemplate {
+                name,
+                version,
+                response,
+            } => {
+                let t = tracker.clone();
+                tokio::spawn(async move {
+                    response
+                        .send(t.get_template(name, version).await)
+                        .unwrap_or(());
+                });
+            }
+            GetTemplates { response } => {
+                let t = tracker.clone();
+                tokio::spawn(async move {
+                    response.send(t.get_templates().await).unwrap_or(());
+                });
+            }
+            DeleteTemplate { name, response } => {
+                let t = tracker.clone();
+                tokio::spawn(async move {
+                    response.send(t.delete_template(name).await).unwrap_or(());
+                });
+            }
             Stop {} => break,
         }
     }
@@ -10,8 +10,9 @@ pub fn start(msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
 pub async fn start_tracker(mut msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
     while let Some(msg) = msgs.recv().await {
         use TrackerMessage::{
-            CreateRun, GetRun, GetRuns, GetState, GetStateUpdates, GetTask, GetTaskSummary,
-            GetTasks, Stop,
+            CreateRun, DeleteTemplate, GetCachedAttempt, GetRun, GetRuns, GetState,
+            GetStateUpdates, GetTask, GetTaskSummary, GetTasks, GetTemplate, GetTemplates, Stop,
+            StoreTemplate,
         };
 
         match msg {
@@ -52,6 +53,27 @@ pub async fn start_tracker(mut msgs: mpsc::UnboundedReceiver<TrackerMessage>) {
                     .send(Err(anyhow!("Noop tracker does not support queries")))
                     .unwrap_or(());
             }
+            GetCachedAttempt { response, .. } => {
+                response.send(Ok(None)).unwrap_or(());
+            }
+            StoreTemplate { response, .. } => {
+                response
+                    .send(Err(anyhow!("Noop tracker does not support templates")))
+                    .unwrap_or(());
+            }
+            GetTemplate { response, .. } => {
+                response
+                    .send(Err(anyhow!("Noop tracker does not support queries")))
+                    .unwrap_or(());
+            }
+            GetTemplates { response } => {
+                response.send(Ok(Vec::new())).unwrap_or(());
+            }
+            DeleteTemplate { response, .. } => {
+                response
+                    .send(Err(anyhow!("Noop tracker does not support templates")))
+                    .unwrap_or(());
+            }
             Stop {} => break,
             _ => {}
         }
@@ -1,6 +1,12 @@
-use crate::structs::{ExpansionValues, Parameters};
-use std::collections::HashSet;
+use super::Result;
+use crate::structs::{ExpansionValues, Parameters, TaskOutputs};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{de, Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Write};
 use std::hash::{BuildHasher, Hash};
+use std::str::FromStr;
 
 // Return the set of variables that are found in the template
 pub fn find_applicable_vars<S: AsRef<str> + Hash + Eq>(
@@ -46,31 +52,205 @@ fn cartesian_product<T: Clone>(input: &[Vec<T>]) -> Vec<Vec<T>> {
     cur
 }
 
+/// Parameter whose values are comma-separated lists of parameter names
+/// that are expanded pairwise (zipped), rather than as a cartesian product
+pub const ZIP_PARAMETER: &str = "@zip";
+
+/// The largest number of values a single range may generate
+const MAX_RANGE_VALUES: usize = 100_000;
+
+/// Returns the groups of parameters that should be zipped together
+fn zip_groups(variables: &Parameters) -> Vec<Vec<String>> {
+    variables
+        .get(ZIP_PARAMETER)
+        .map(|groups| {
+            groups
+                .iter()
+                .map(|group| group.split(',').map(|k| k.trim().to_owned()).collect())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Generates the values for a range spec of the form `START..END[/STEP]`,
+/// inclusive of `END`
+fn int_range(spec: &str) -> Result<Vec<String>> {
+    let (range, step) = spec.split_once('/').unwrap_or((spec, "1"));
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| anyhow!("Expected START..END"))?;
+    let start: i64 = start.trim().parse()?;
+    let end: i64 = end.trim().parse()?;
+    let step: usize = step.trim().parse()?;
+    if step == 0 || start > end {
+        return Err(anyhow!("Range must have START <= END and a positive step"));
+    }
+
+    let values: Vec<String> = (start..=end)
+        .step_by(step)
+        .take(MAX_RANGE_VALUES + 1)
+        .map(|x| x.to_string())
+        .collect();
+    if values.len() > MAX_RANGE_VALUES {
+        return Err(anyhow!("Range exceeds {MAX_RANGE_VALUES} values"));
+    }
+    Ok(values)
+}
+
+/// Generates the dates for a range spec of the form
+/// `START..END[/STEP(d|w)][:FORMAT]`, inclusive of `END`. Dates are given
+/// as `YYYY-MM-DD`, and the format defaults to `%Y-%m-%d`.
+fn date_range(spec: &str) -> Result<Vec<String>> {
+    let (spec, format) = spec.split_once(':').unwrap_or((spec, "%Y-%m-%d"));
+    let (range, step) = spec.split_once('/').unwrap_or((spec, "1d"));
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| anyhow!("Expected START..END"))?;
+    let start = NaiveDate::parse_from_str(start.trim(), "%Y-%m-%d")?;
+    let end = NaiveDate::parse_from_str(end.trim(), "%Y-%m-%d")?;
+
+    let step = step.trim();
+    let invalid_step = || anyhow!("Invalid step {step:?}, expected Nd or Nw");
+    let unit = step.chars().next_back().ok_or_else(invalid_step)?;
+    let amount: i64 = step[..step.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid_step())?;
+    let days = match unit {
+        'd' => Some(amount),
+        'w' => amount.checked_mul(7),
+        _ => None,
+    };
+    let delta = days.and_then(Duration::try_days).ok_or_else(invalid_step)?;
+    if delta <= Duration::zero() || start > end {
+        return Err(anyhow!("Range must have START <= END and a positive step"));
+    }
+
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(anyhow!("Invalid date format {format:?}"));
+    }
+
+    let mut values = Vec::new();
+    let mut date = start;
+    while date <= end {
+        if values.len() == MAX_RANGE_VALUES {
+            return Err(anyhow!("Range exceeds {MAX_RANGE_VALUES} values"));
+        }
+        // Dates have no time or zone, so formats asking for them can't be
+        // written
+        let mut value = String::new();
+        write!(value, "{}", date.format_with_items(items.iter().cloned()))
+            .map_err(|_| anyhow!("Date format {format:?} needs a time or zone"))?;
+        values.push(value);
+        date = match date.checked_add_signed(delta) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(values)
+}
+
+/// Expands any range generators in the parameter values, and checks that
+/// zipped parameters have the same number of values. Generators are values
+/// of the form `@range START..END[/STEP]` or
+/// `@dates START..END[/STEP][:FORMAT]`.
+///
+/// # Errors
+/// Will return `Err` if a generator is malformed, or zipped parameters
+/// are missing or have differing lengths.
+pub fn resolve_parameters(parameters: &Parameters) -> Result<Parameters> {
+    let mut resolved = Parameters::new();
+    for (key, values) in parameters {
+        if key == ZIP_PARAMETER {
+            resolved.insert(key.clone(), values.clone());
+            continue;
+        }
+        let mut new_values = Vec::new();
+        for value in values {
+            if let Some(spec) = value.strip_prefix("@range ") {
+                new_values.extend(
+                    int_range(spec)
+                        .map_err(|e| anyhow!("Invalid range {value:?} for {key}: {e}"))?,
+                );
+            } else if let Some(spec) = value.strip_prefix("@dates ") {
+                new_values.extend(
+                    date_range(spec)
+                        .map_err(|e| anyhow!("Invalid range {value:?} for {key}: {e}"))?,
+                );
+            } else {
+                new_values.push(value.clone());
+            }
+        }
+        resolved.insert(key.clone(), new_values);
+    }
+
+    for group in zip_groups(&resolved) {
+        let mut lengths = HashSet::new();
+        for key in &group {
+            let values = resolved
+                .get(key)
+                .ok_or_else(|| anyhow!("Zipped parameter {key} is not defined"))?;
+            lengths.insert(values.len());
+        }
+        if lengths.len() > 1 {
+            return Err(anyhow!(
+                "Zipped parameters {group:?} must have the same number of values"
+            ));
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Generates every combination of values for the parameters in `subset`.
+/// Parameters in the same zip group vary together, so contribute one value
+/// from each of their lists, by position.
 #[must_use]
 pub fn generate_interpolation_sets<S: BuildHasher>(
     variables: &Parameters,
     subset: &HashSet<String, S>,
 ) -> Vec<ExpansionValues> {
-    let mut vals = Vec::new();
-    let mut keys = Vec::new();
+    // Each axis is a list of alternative assignments to one or more variables
+    let mut axes: Vec<Vec<ExpansionValues>> = Vec::new();
+    let mut zipped = HashSet::new();
+
+    for group in zip_groups(variables) {
+        let keys: Vec<&String> = group
+            .iter()
+            .filter(|k| subset.contains(*k) && variables.contains_key(*k))
+            .collect();
+        zipped.extend(group.iter().cloned());
+        if keys.is_empty() {
+            continue;
+        }
+        let len = keys
+            .iter()
+            .map(|k| variables[*k].len())
+            .min()
+            .unwrap_or_default();
+        axes.push(
+            (0..len)
+                .map(|i| {
+                    keys.iter()
+                        .map(|k| ((*k).clone(), variables[*k][i].clone()))
+                        .collect()
+                })
+                .collect(),
+        );
+    }
 
     // Extract the variables that apply
     for (k, v) in variables.iter() {
-        if subset.contains(k) {
-            vals.push(v.clone());
-            keys.push(k);
+        if k != ZIP_PARAMETER && subset.contains(k) && !zipped.contains(k) {
+            axes.push(v.iter().map(|x| vec![(k.clone(), x.clone())]).collect());
         }
     }
 
     // Generate the cartesian product of the variable values
-    cartesian_product(&vals)
-        .iter()
+    cartesian_product(&axes)
+        .into_iter()
         .map(|x| {
-            let mut v = x
-                .iter()
-                .zip(&keys)
-                .map(|(a, b)| ((*b).clone(), (*a).clone()))
-                .collect::<Vec<(String, String)>>();
+            let mut v: ExpansionValues = x.concat();
             v.sort();
             v
         })
@@ -95,10 +275,443 @@ pub fn apply_vars(template: &[String], interpolation_sets: &[ExpansionValues]) -
         .collect()
 }
 
+/// Evaluates a date macro of the form `DATE[(+|-)N(w|d|h)][:FORMAT]`, relative
+/// to `base`. The format defaults to `%Y-%m-%d`. Returns `None` if `spec`
+/// isn't a valid date macro.
+#[must_use]
+pub fn eval_date_macro(spec: &str, base: DateTime<Utc>) -> Option<String> {
+    let spec = spec.strip_prefix("DATE")?;
+    let (offset, format) = match spec.split_once(':') {
+        Some((offset, format)) => (offset, format),
+        None => (spec, "%Y-%m-%d"),
+    };
+
+    let mut date = base;
+    if !offset.is_empty() {
+        let (sign, offset) = if let Some(rest) = offset.strip_prefix('+') {
+            (1, rest)
+        } else if let Some(rest) = offset.strip_prefix('-') {
+            (-1, rest)
+        } else {
+            return None;
+        };
+        let unit = offset.chars().next_back()?;
+        let amount: i64 = offset[..offset.len() - unit.len_utf8()].parse().ok()?;
+        let amount = amount.checked_mul(sign)?;
+        let delta = match unit {
+            'w' => Duration::try_weeks(amount)?,
+            'd' => Duration::try_days(amount)?,
+            'h' => Duration::try_hours(amount)?,
+            _ => return None,
+        };
+        date = date.checked_add_signed(delta)?;
+    }
+
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return None;
+    }
+    Some(date.format_with_items(items.into_iter()).to_string())
+}
+
+/// Replaces `{{NAME}}` placeholders in `template` with the matching built-in
+/// variable from `vars`, or the result of a date macro relative to `base`.
+/// Unrecognized placeholders are left untouched.
+#[must_use]
+pub fn apply_builtin_vars<S: BuildHasher>(
+    template: &str,
+    vars: &HashMap<String, String, S>,
+    base: DateTime<Utc>,
+) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            result.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let name = &after[..end];
+        match vars
+            .get(name)
+            .cloned()
+            .or_else(|| eval_date_macro(name, base))
+        {
+            Some(value) => result.push_str(&value),
+            None => result.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Calls `f` on every string value within `value`
+fn for_each_string<F: FnMut(&mut String)>(value: &mut serde_json::Value, f: &mut F) {
+    match value {
+        serde_json::Value::String(s) => f(s),
+        serde_json::Value::Array(values) => {
+            for value in values {
+                for_each_string(value, f);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for value in map.values_mut() {
+                for_each_string(value, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Calls `f` on every string value within a task's details, except those
+/// under the top-level fields named in `skip_fields`
+fn for_each_detail_string<F: FnMut(&mut String)>(
+    details: &mut serde_json::Value,
+    skip_fields: &[&str],
+    f: &mut F,
+) {
+    if let serde_json::Value::Object(map) = details {
+        for (field, value) in map.iter_mut() {
+            if !skip_fields.contains(&field.as_str()) {
+                for_each_string(value, f);
+            }
+        }
+    } else {
+        for_each_string(details, f);
+    }
+}
+
+/// Returns every string value within a task's details, except those under
+/// the top-level fields named in `skip_fields`
+#[must_use]
+pub fn detail_strings(details: &serde_json::Value, skip_fields: &[&str]) -> Vec<String> {
+    let mut strings = Vec::new();
+    for_each_detail_string(&mut details.clone(), skip_fields, &mut |s| {
+        strings.push(s.clone());
+    });
+    strings
+}
+
+/// Applies a single interpolation set to every string within a task's
+/// details, except those under the top-level fields named in `skip_fields`
+#[must_use]
+pub fn apply_vars_to_details(
+    details: &serde_json::Value,
+    interpolation_set: &ExpansionValues,
+    skip_fields: &[&str],
+) -> serde_json::Value {
+    let mut new_details = details.clone();
+    for_each_detail_string(&mut new_details, skip_fields, &mut |s| {
+        *s = interpolation_set
+            .iter()
+            .fold(s.clone(), |p, (var, val)| p.replace(var, val));
+    });
+    new_details
+}
+
+/// Applies `apply_builtin_vars` to every string within a task's details
+pub fn apply_builtin_vars_to_details<S: BuildHasher>(
+    details: &mut serde_json::Value,
+    vars: &HashMap<String, String, S>,
+    base: DateTime<Utc>,
+) {
+    for_each_string(details, &mut |s| {
+        if s.contains("{{") {
+            *s = apply_builtin_vars(s, vars, base);
+        }
+    });
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString<T> {
+    Number(T),
+    String(String),
+}
+
+impl<T: FromStr> NumberOrString<T>
+where
+    T::Err: Display,
+{
+    fn into_number(self) -> std::result::Result<T, String> {
+        match self {
+            NumberOrString::Number(x) => Ok(x),
+            NumberOrString::String(s) => s
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid number {s:?}: {e}")),
+        }
+    }
+}
+
+/// Deserializes a number that may also be given as a string, so that numeric
+/// task details can be set from expanded parameters.
+///
+/// # Errors
+/// Will return `Err` if the value is neither a number, nor a string that
+/// parses as one.
+pub fn number_or_string<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    NumberOrString::deserialize(deserializer)?
+        .into_number()
+        .map_err(de::Error::custom)
+}
+
+/// As `number_or_string`, for the values of a map
+///
+/// # Errors
+/// Will return `Err` if any value is neither a number, nor a string that
+/// parses as one.
+pub fn number_or_string_map<'de, D, T>(
+    deserializer: D,
+) -> std::result::Result<HashMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    HashMap::<String, NumberOrString<T>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(k, v)| Ok((k, v.into_number().map_err(de::Error::custom)?)))
+        .collect()
+}
+
+/// Prefix of a line in a task's stdout that publishes an output
+pub const OUTPUT_MARKER: &str = "DAGGY_OUTPUT ";
+
+/// Parses `KEY=VALUE` lines into a set of outputs. Lines without an `=`
+/// are ignored.
+#[must_use]
+pub fn parse_outputs(text: &str) -> TaskOutputs {
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.trim().to_owned(), v.to_owned()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// Extracts the outputs published in a task's stdout, via lines
+/// of the form `DAGGY_OUTPUT KEY=VALUE`
+#[must_use]
+pub fn parse_output_markers(output: &str) -> TaskOutputs {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix(OUTPUT_MARKER))
+        .flat_map(|line| parse_outputs(line).into_iter())
+        .collect()
+}
+
+/// Matches `text` against a shell-style glob `pattern`, where `*` matches
+/// any run of characters and `?` matches any single character.
+#[must_use]
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen, and the text position it was matched at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("report.*", "report.daily"));
+        assert!(glob_match("report*", "report"));
+        assert!(glob_match("*_A", "Transform_A"));
+        assert!(glob_match("Tr?nsform_*", "Transform_B"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("report.*", "report"));
+        assert!(!glob_match("*_A", "Transform_B"));
+        assert!(!glob_match("Transform", "Transform_A"));
+    }
+
+    #[test]
+    fn zipped_and_ranged_parameters() {
+        let parameters: Parameters = serde_json::from_str(
+            r#"{
+                "{{SRC}}": [ "a", "b", "c" ],
+                "{{DST}}": [ "x", "y", "z" ],
+                "{{N}}": [ "@range 1..5/2" ],
+                "{{DAY}}": [ "@dates 2022-02-27..2022-03-01:%Y%m%d", "latest" ],
+                "@zip": [ "{{SRC}},{{DST}}" ]
+            }"#,
+        )
+        .unwrap();
+        let parameters = resolve_parameters(&parameters).unwrap();
+        assert_eq!(parameters["{{N}}"], vec!["1", "3", "5"]);
+        assert_eq!(
+            parameters["{{DAY}}"],
+            vec!["20220227", "20220228", "20220301", "latest"]
+        );
+        assert_eq!(
+            date_range("2022-01-01..2022-01-20/1w").unwrap(),
+            vec!["2022-01-01", "2022-01-08", "2022-01-15"]
+        );
+
+        let subset: HashSet<String> = ["{{SRC}}", "{{DST}}", "{{N}}"]
+            .iter()
+            .map(|x| (*x).to_owned())
+            .collect();
+        let sets = generate_interpolation_sets(&parameters, &subset);
+        assert_eq!(sets.len(), 9);
+        for set in &sets {
+            let src = &set.iter().find(|(k, _)| k == "{{SRC}}").unwrap().1;
+            let dst = &set.iter().find(|(k, _)| k == "{{DST}}").unwrap().1;
+            let pair = (src.as_str(), dst.as_str());
+            assert!([("a", "x"), ("b", "y"), ("c", "z")].contains(&pair));
+        }
+
+        // Zipped parameters must line up
+        let mut bad = parameters.clone();
+        bad.insert("{{DST}}".to_owned(), vec!["x".to_owned()]);
+        assert!(resolve_parameters(&bad).is_err());
+
+        for spec in [
+            "@range 5..1",
+            "@range 1..5/0",
+            "@dates 2022-01-01..2022-01-05/1m",
+            "@dates 2022-01-01..2022-01-05/",
+            "@dates 2022-01-01..2022-01-05/é",
+            "@dates 2022-01-01..2022-01-05/1é",
+            "@dates 2022-01-01..2022-01-05/-1w",
+            "@dates 2022-01-01..2022-01-05/9223372036854775807w",
+            "@dates 2022-01-01..2022-01-05/99999999999999d",
+            "@dates 2022-01-01..2022-01-02:%H",
+            "@dates 2022-01-01..2022-01-02:%Y-%m-%d %z",
+        ] {
+            let bad: Parameters = HashMap::from([("{{X}}".to_owned(), vec![spec.to_owned()])]);
+            assert!(resolve_parameters(&bad).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn detail_expansion() {
+        #[derive(Deserialize)]
+        struct Timeout {
+            #[serde(deserialize_with = "number_or_string")]
+            timeout: u64,
+        }
+
+        let details = serde_json::json!({
+            "command": [ "/bin/echo", "{{NAME}}" ],
+            "environment": { "OUT": "/data/{{NAME}}" },
+            "logdir": "/logs/{{NAME}}",
+            "timeout": "{{TIMEOUT}}",
+            "token": "{{NAME}}"
+        });
+
+        let mut strings = detail_strings(&details, &["token"]);
+        strings.sort();
+        assert_eq!(
+            strings,
+            vec![
+                "/bin/echo",
+                "/data/{{NAME}}",
+                "/logs/{{NAME}}",
+                "{{NAME}}",
+                "{{TIMEOUT}}"
+            ]
+        );
+
+        let set = vec![
+            ("{{NAME}}".to_owned(), "abc".to_owned()),
+            ("{{TIMEOUT}}".to_owned(), "30".to_owned()),
+        ];
+        let expanded = apply_vars_to_details(&details, &set, &["token"]);
+        assert_eq!(expanded["command"][1], "abc");
+        assert_eq!(expanded["environment"]["OUT"], "/data/abc");
+        assert_eq!(expanded["logdir"], "/logs/abc");
+        assert_eq!(expanded["timeout"], "30");
+        assert_eq!(expanded["token"], "{{NAME}}");
+
+        let parsed: Timeout = serde_json::from_value(expanded).unwrap();
+        assert_eq!(parsed.timeout, 30);
+        assert!(serde_json::from_value::<Timeout>(details).is_err());
+    }
+
+    #[test]
+    fn builtin_vars() {
+        use chrono::TimeZone;
+
+        let base = Utc.with_ymd_and_hms(2022, 3, 1, 6, 30, 0).unwrap();
+        let vars = HashMap::from([("RUN_ID".to_owned(), "7".to_owned())]);
+
+        assert_eq!(eval_date_macro("DATE", base).unwrap(), "2022-03-01");
+        assert_eq!(eval_date_macro("DATE-1d:%Y%m%d", base).unwrap(), "20220228");
+        assert_eq!(eval_date_macro("DATE+2w", base).unwrap(), "2022-03-15");
+        assert_eq!(eval_date_macro("DATE-7h:%d %H", base).unwrap(), "28 23");
+        assert!(eval_date_macro("DATE-1y", base).is_none());
+        assert!(eval_date_macro("DATE:%Q", base).is_none());
+        assert!(eval_date_macro("DAY", base).is_none());
+        // Malformed offsets are rejected rather than panicking
+        assert!(eval_date_macro("DATEé1d", base).is_none());
+        assert!(eval_date_macro("DATE+1é", base).is_none());
+        assert!(eval_date_macro("DATE+", base).is_none());
+        assert!(eval_date_macro("DATE--9223372036854775808d", base).is_none());
+        assert!(eval_date_macro("DATE+9223372036854775807w", base).is_none());
+
+        assert_eq!(
+            apply_builtin_vars("run_{{RUN_ID}}_{{DATE-1d:%Y%m%d}}.csv", &vars, base),
+            "run_7_20220228.csv"
+        );
+        assert_eq!(
+            apply_builtin_vars("{{UNKNOWN}} {{RUN_ID}} {{", &vars, base),
+            "{{UNKNOWN}} 7 {{"
+        );
+
+        let mut details = serde_json::json!({
+            "command": [ "/bin/echo", "{{RUN_ID}}" ],
+            "environment": { "DAY": "{{DATE}}" },
+            "timeout": 5
+        });
+        apply_builtin_vars_to_details(&mut details, &vars, base);
+        assert_eq!(details["command"][1], "7");
+        assert_eq!(details["environment"]["DAY"], "2022-03-01");
+    }
+
+    #[test]
+    fn output_parsing() {
+        let output = "starting\nDAGGY_OUTPUT rows=42\nDAGGY_OUTPUT path=/tmp/a=b\nrows=7\n";
+        let outputs = parse_output_markers(output);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs["rows"], "42");
+        assert_eq!(outputs["path"], "/tmp/a=b");
+
+        let outputs = parse_outputs("a=1\n\nnot an output\n=2\n");
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs["a"], "1");
+    }
+
     #[test]
     fn var_expansion_single() {
         let input = vec!["Hello".to_owned(), "FIRST_NAME".to_owned()];